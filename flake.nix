@@ -62,6 +62,9 @@
               openssl
             ];
 
+            # Reported by the /version endpoint
+            GIT_HASH = self.rev or "dirty";
+
             PROTOC = "${pkgs.protobuf}/bin/protoc";
             PROTOC_INCLUDE = "${pkgs.protobuf}/include";
 
@@ -0,0 +1,283 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path, State},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{index, AppError, AppState, Playback, Session, SessionState, User};
+
+pub(crate) fn router() -> Router<AppState> {
+    Router::new()
+        .route("/sessions", get(list_sessions))
+        .route("/sessions/:id", delete(delete_session))
+        .route("/sessions/:id/videos/:vid/override", put(override_video))
+        .route("/reindex", post(reindex))
+        .route("/cache", delete(clear_cache))
+        .route("/cache/vacuum", post(vacuum_cache))
+        .route("/trash", get(list_trash))
+        .route("/sessions/:id/trash/:vid", delete(purge_trashed))
+        .route("/sessions/:id/trash/:vid/restore", post(restore_trashed))
+}
+
+/// Extractor guarding admin routes behind the configured admin token.
+/// Expects `Authorization: Bearer <token>`, admin routes are disabled when no token is configured.
+pub(crate) struct Admin;
+
+#[async_trait]
+impl FromRequestParts<AppState> for Admin {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let Some(admin_token) = &state.config.admin_token else {
+            return Err((StatusCode::NOT_FOUND, "nothing to see here").into_response());
+        };
+        let provided = parts
+            .headers
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match provided {
+            Some(token) if admin_token.matches(token) => Ok(Self),
+            _ => Err((StatusCode::UNAUTHORIZED, "invalid admin token").into_response()),
+        }
+    }
+}
+
+/// Session listing entry, deliberately leaving out tokens and passwords.
+#[derive(Serialize, Debug)]
+struct SessionSummary {
+    id: Option<String>,
+    kind: &'static str,
+    username: Option<String>,
+    last_known_playback: Option<Playback>,
+}
+
+impl From<SessionState> for SessionSummary {
+    fn from(state: SessionState) -> Self {
+        let id = state.id.map(|id| id.id.to_raw());
+        match state.session {
+            Session::QuickConnect(_) => SessionSummary {
+                id,
+                kind: "QuickConnect",
+                username: None,
+                last_known_playback: None,
+            },
+            Session::User(user) => SessionSummary {
+                id,
+                kind: "User",
+                username: Some(user.username),
+                last_known_playback: user.last_known_playback,
+            },
+        }
+    }
+}
+
+async fn list_sessions(
+    _: Admin,
+    State(app): State<AppState>,
+) -> Result<Json<Vec<SessionSummary>>, AppError> {
+    let sessions: Vec<SessionState> = app.db.get().select("session").await?;
+    Ok(Json(sessions.into_iter().map(SessionSummary::from).collect()))
+}
+
+async fn delete_session(
+    _: Admin,
+    State(app): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    let deleted: Option<SessionState> = app.db.get().delete(("session", id.as_str())).await?;
+    tracing::info!(session = id, deleted = deleted.is_some(), "Admin deleted session");
+    match deleted {
+        Some(_) => Ok(StatusCode::NO_CONTENT),
+        None => Ok(StatusCode::NOT_FOUND),
+    }
+}
+
+/// Rebuilds the index of every logged in user, a few at a time.
+async fn reindex(
+    _: Admin,
+    State(app): State<AppState>,
+) -> Result<Json<index::ReindexSummary>, AppError> {
+    let summary = index::HeresphereIndex::reindex_all(&app, app.config.max_concurrent_requests).await?;
+    tracing::info!(summary = ?summary, "Admin reindexed all users");
+    Ok(Json(summary))
+}
+
+/// Drops stale cache entries, see `HeresphereIndex::vacuum`.
+async fn vacuum_cache(
+    _: Admin,
+    State(app): State<AppState>,
+) -> Result<Json<index::VacuumSummary>, AppError> {
+    let summary = index::HeresphereIndex::vacuum(&app, false).await?;
+    tracing::info!(summary = ?summary, "Admin vacuumed cache");
+    Ok(Json(summary))
+}
+
+/// Drops every cached index and video, sessions stay logged in.
+async fn clear_cache(
+    _: Admin,
+    State(app): State<AppState>,
+) -> Result<Json<index::VacuumSummary>, AppError> {
+    let summary = index::HeresphereIndex::vacuum(&app, true).await?;
+    tracing::info!(summary = ?summary, "Admin cleared cache");
+    Ok(Json(summary))
+}
+
+/// Per-video fixes an admin can apply on behalf of a user, fields left out keep their saved value.
+#[derive(Deserialize, Debug)]
+struct OverrideRequest {
+    /// `null` clears the override and goes back to marker detection.
+    #[serde(default, deserialize_with = "deserialize_some")]
+    is_eye_swapped: Option<Option<bool>>,
+}
+
+/// Tells a field set to `null` (`Some(None)`) apart from one that's left out (`None`).
+fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+async fn override_video(
+    _: Admin,
+    State(app): State<AppState>,
+    Path((id, vid)): Path<(String, String)>,
+    Json(request): Json<OverrideRequest>,
+) -> Result<StatusCode, AppError> {
+    let session: Option<SessionState> = app.db.get().select(("session", id.as_str())).await?;
+    let Some(SessionState {
+        session: Session::User(user),
+        ..
+    }) = session
+    else {
+        return Ok(StatusCode::NOT_FOUND);
+    };
+    let patch = index::VideoOverridePatch {
+        is_eye_swapped: request.is_eye_swapped,
+        ..Default::default()
+    };
+    index::VideoOverride::save(&app.db.get(), &user.cache_key(), &vid, &patch).await?;
+    tracing::info!(session = %id, video = %vid, request = ?request, "Admin overrode video");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_trash(
+    _: Admin,
+    State(app): State<AppState>,
+) -> Result<Json<Vec<index::TrashedVideo>>, AppError> {
+    Ok(Json(index::TrashedVideo::all(&app.db.get()).await?))
+}
+
+/// User behind a session, for admin routes acting on their behalf.
+async fn session_user(app: &AppState, id: &str) -> Result<Option<User>, AppError> {
+    let session: Option<SessionState> = app.db.get().select(("session", id)).await?;
+    Ok(match session {
+        Some(SessionState {
+            session: Session::User(user),
+            ..
+        }) => Some(user),
+        _ => None,
+    })
+}
+
+/// Puts a trashed video back into the index of the user.
+async fn restore_trashed(
+    _: Admin,
+    State(app): State<AppState>,
+    Path((id, vid)): Path<(String, String)>,
+) -> Result<StatusCode, AppError> {
+    let Some(user) = session_user(&app, &id).await? else {
+        return Ok(StatusCode::NOT_FOUND);
+    };
+    let restored = index::TrashedVideo::restore(&app.db.get(), &user.cache_key(), &vid).await?;
+    tracing::info!(session = %id, video = %vid, restored, "Admin restored video");
+    match restored {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Ok(StatusCode::NOT_FOUND),
+    }
+}
+
+/// Deletes a trashed video from Jellyfin for good, as the user who trashed it.
+async fn purge_trashed(
+    _: Admin,
+    State(app): State<AppState>,
+    Path((id, vid)): Path<(String, String)>,
+) -> Result<StatusCode, AppError> {
+    // Jellyfin would never hear of it, and the video would come back with the next scan
+    if app.config.read_only {
+        return Ok(StatusCode::CONFLICT);
+    }
+    let Some(user) = session_user(&app, &id).await? else {
+        return Ok(StatusCode::NOT_FOUND);
+    };
+    let trashed = index::TrashedVideo::ids_for_user(&app.db.get(), &user.cache_key()).await?;
+    if !trashed.contains(&vid) {
+        return Ok(StatusCode::NOT_FOUND);
+    }
+    app.jellyfin_user(&user)?.delete_item(&vid).await?;
+    // Jellyfin no longer has it, so there's nothing left to restore
+    index::TrashedVideo::restore(&app.db.get(), &user.cache_key(), &vid).await?;
+    tracing::info!(session = %id, video = %vid, "Admin purged video");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{create_user_session, library_routes, mock_jellyfin, test_app, test_config, test_db, VIDEO_ID};
+
+    /// Logged in user with its own Jellyfin id, whose library was loaded before.
+    async fn indexed_user(app: &AppState, username: &str, user_id: uuid::Uuid) -> eyre::Result<User> {
+        let mut state = create_user_session(&app.db.get(), username).await?;
+        let Session::User(user) = &mut state.session else {
+            unreachable!("Not a user session");
+        };
+        user.user_id = user_id.simple().to_string();
+        let user = user.clone();
+        app.update_session(state).await?;
+        let _: Option<index::HeresphereIndex> = app
+            .db
+            .get()
+            .create(("index", user.cache_key().as_str()))
+            .content(index::HeresphereIndex {
+                id: None,
+                libraries: vec![],
+                videos: vec![],
+                last_updated: chrono::Utc::now() - chrono::Duration::days(1),
+                host: Some("https://vr.example".to_string()),
+            })
+            .await?;
+        Ok(user)
+    }
+
+    #[tokio::test]
+    async fn reindex_rebuilds_every_indexed_user() -> eyre::Result<()> {
+        let (db, path) = test_db().await?;
+        let (url, _) = mock_jellyfin(library_routes(serde_json::json!([
+            { "Id": VIDEO_ID, "Name": "Video", "MediaSources": [{}] },
+        ])))
+        .await?;
+        let app = test_app(db.clone(), test_config(&url));
+        let alice = indexed_user(&app, "alice", uuid::Uuid::from_u128(1)).await?;
+        let bob = indexed_user(&app, "bob", uuid::Uuid::from_u128(2)).await?;
+
+        let Json(mut summary) = reindex(Admin, State(app.clone())).await.map_err(|e| e.0)?;
+        summary.reindexed.sort();
+        assert_eq!(summary.reindexed, vec!["alice".to_string(), "bob".to_string()]);
+        assert!(summary.failed.is_empty());
+        for user in [alice, bob] {
+            let index: Option<index::HeresphereIndex> = db.select(("index", user.cache_key().as_str())).await?;
+            assert_eq!(index.expect("No index").videos, vec![VIDEO_ID.to_string()]);
+        }
+        drop(app);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+}
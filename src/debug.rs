@@ -0,0 +1,178 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path, State},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use axum_extra::extract::cookie::CookieJar;
+
+use crate::{index, jellyfin, AppError, AppState, JellyVrError, Session, SessionState, User};
+
+pub(crate) fn router() -> Router<AppState> {
+    Router::new()
+        .route("/item/:vid", get(item_preview))
+        .route("/playback", get(playback))
+}
+
+/// Extractor for the logged in user behind the `jellyvr_session` cookie.
+/// Debug routes are hidden entirely unless `debug_endpoints` is enabled.
+pub(crate) struct DebugUser(User);
+
+#[async_trait]
+impl FromRequestParts<AppState> for DebugUser {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        if !state.config.debug_endpoints {
+            return Err((StatusCode::NOT_FOUND, "nothing to see here").into_response());
+        }
+        let jar = CookieJar::from_headers(&parts.headers);
+        let Some(cookie) = jar.get("jellyvr_session") else {
+            return Err(AppError(JellyVrError::Unauthorized.into()).into_response());
+        };
+        let session = state
+            .session_from_cookie(cookie.value())
+            .await
+            .map_err(|err| AppError(err).into_response())?;
+        match session {
+            Some(SessionState {
+                session: Session::User(user),
+                ..
+            }) => Ok(Self(user)),
+            _ => Err(AppError(JellyVrError::Unauthorized.into()).into_response()),
+        }
+    }
+}
+
+/// Shows the raw Jellyfin item next to the `VideoData` JellyVR builds from it.
+async fn item_preview(
+    DebugUser(user): DebugUser,
+    State(app): State<AppState>,
+    Path(vid): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let jellyfin_user = app.jellyfin_user(&user)?;
+    let item = jellyfin_user.item(&vid).await?;
+    let remote_host = &app.jellyfin.server(&user.server)?.remote_host;
+    let video = index::baseitem_to_video(
+        remote_host,
+        &user.token,
+        &index::ImageSource::direct(remote_host, &user.token),
+        &app.config,
+        &item,
+    );
+    let preview = serde_json::to_string_pretty(&serde_json::json!({
+        "jellyfin": item,
+        "heresphere": video,
+    }))?;
+    Ok((
+        [("Content-Type", "application/json")],
+        preview.replace(&user.token, "REDACTED"),
+    ))
+}
+
+/// What JellyVR thinks the user is playing, to tell drift apart from missed events.
+async fn playback(DebugUser(user): DebugUser) -> impl IntoResponse {
+    let playback = user.last_known_playback.map(|playback| {
+        serde_json::json!({
+            "video_id": playback.video_id,
+            "play_session_id": playback.play_session_id,
+            "position_ms": jellyfin::ticks_to_ms(playback.position_estimate),
+            "predicted_position_ms": jellyfin::ticks_to_ms(playback.predicted_position()),
+            "duration_ms": jellyfin::ticks_to_ms(playback.duration),
+            "speed": playback.speed,
+            "is_paused": playback.is_paused,
+            "is_started": playback.is_started,
+            "is_marked_played": playback.is_marked_played,
+            "started_at": playback.started_at,
+            "last_update": playback.last_update,
+        })
+    });
+    Json(serde_json::json!({ "playback": playback }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{body_text, create_user_session, mock_jellyfin, test_app, test_config, test_db, VIDEO_ID};
+    use crate::{Playback, Secret};
+
+    #[tokio::test]
+    async fn item_preview_shows_both_without_the_token() -> eyre::Result<()> {
+        let (db, path) = test_db().await?;
+        let routes = Router::new().route(
+            "/Users/:user/Items/:id",
+            get(|Path((_, id)): Path<(String, String)>| async move {
+                Json(serde_json::json!({ "Id": id, "Name": "Video", "MediaSources": [{ "Id": "source", "Container": "mp4" }] }))
+            }),
+        );
+        let (url, _) = mock_jellyfin(routes).await?;
+        let app = test_app(db.clone(), test_config(&url));
+        let Session::User(mut user) = create_user_session(&db, "alice").await?.session else {
+            unreachable!("Not a user session");
+        };
+        user.token = "jellyfin-token".to_string();
+
+        let response = item_preview(DebugUser(user), State(app.clone()), Path(VIDEO_ID.to_string()))
+            .await
+            .map_err(|e| e.0)?
+            .into_response();
+        let preview = body_text(response).await?;
+        assert!(!preview.contains("jellyfin-token"));
+        let preview: serde_json::Value = serde_json::from_str(&preview)?;
+        assert_eq!(preview["jellyfin"]["Name"], "Video");
+        assert_eq!(preview["heresphere"]["title"], "Video");
+        assert_eq!(
+            preview["heresphere"]["media"][0]["sources"][0]["url"],
+            format!("{}/Items/source/Download?api_key=REDACTED", url)
+        );
+        drop(app);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn playback_shows_the_stored_playback() -> eyre::Result<()> {
+        let (db, path) = test_db().await?;
+        let Session::User(user) = create_user_session(&db, "alice").await?.session else {
+            unreachable!("Not a user session");
+        };
+        let shown = |user: User| async move {
+            let text = body_text(playback(DebugUser(user)).await.into_response()).await?;
+            eyre::Ok(serde_json::from_str::<serde_json::Value>(&text)?)
+        };
+        assert!(shown(user.clone()).await?["playback"].is_null());
+
+        let stored = Playback {
+            play_session_id: "play".to_string(),
+            video_id: VIDEO_ID.to_string(),
+            duration: jellyfin::ms_to_ticks(600_000.0),
+            position_estimate: jellyfin::ms_to_ticks(42_000.0),
+            speed: 1.0,
+            started_at: chrono::Utc::now(),
+            last_update: chrono::Utc::now(),
+            is_paused: true,
+            is_started: true,
+            is_marked_played: false,
+            media_url: Some(Secret("/Videos/x/stream?api_key=token".to_string())),
+            is_transcoded: false,
+        };
+        let user = User { last_known_playback: Some(stored), ..user };
+        let shown = shown(user).await?;
+        let shown = &shown["playback"];
+        assert_eq!(shown["video_id"], VIDEO_ID);
+        assert_eq!(shown["play_session_id"], "play");
+        assert_eq!(shown["position_ms"], 42_000.0);
+        // Paused, so it's not expected to have moved on
+        assert_eq!(shown["predicted_position_ms"], 42_000.0);
+        assert_eq!(shown["duration_ms"], 600_000.0);
+        assert_eq!(shown["is_paused"], true);
+        // The media url carries the token
+        assert!(!shown.to_string().contains("api_key"));
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+}
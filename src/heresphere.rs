@@ -3,6 +3,76 @@ use serde_repr::{Serialize_repr, Deserialize_repr};
 
 pub static MAGIC_HEADER: &'static str = "HereSphere-JSON-Version";
 
+/// Projections HereSphere understands.
+pub static PROJECTIONS: &[&str] = &[
+    "equirectangular",
+    "perspective",
+    "equirectangular360",
+    "fisheye",
+    "cubemap",
+    "equiangularCubemap",
+];
+
+/// Stereo modes HereSphere understands.
+pub static STEREO_MODES: &[&str] = &["mono", "sbs", "tb"];
+
+/// Fisheye lens geometry, picked for a video when a marker shows up in its file name or tags.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LensPreset {
+    /// Lens HereSphere knows, ex. `MKX200` or `Linear` for a plain fisheye.
+    pub lens: String,
+    /// Field of view in degrees.
+    pub fov: f64,
+}
+
+/// Markers for common VR cameras, matched case-insensitively.
+pub fn builtin_lens_presets() -> Vec<(String, LensPreset)> {
+    [
+        ("MKX200", "MKX200", 200.0),
+        ("MKX220", "MKX220", 220.0),
+        ("VRCA220", "VRCA220", 220.0),
+        ("FISHEYE190", "Linear", 190.0),
+    ]
+    .into_iter()
+    .map(|(marker, lens, fov)| {
+        (
+            marker.to_string(),
+            LensPreset {
+                lens: lens.to_string(),
+                fov,
+            },
+        )
+    })
+    .collect()
+}
+
+/// Tags or file name words giving away the stereo layout, matched case-insensitively.
+/// HereSphere works out half and full width (or height) from the video itself, both just need the layout.
+pub fn builtin_stereo_markers() -> Vec<(String, String)> {
+    [
+        ("SBS", "sbs"),
+        ("HSBS", "sbs"),
+        ("FSBS", "sbs"),
+        ("LR", "sbs"),
+        ("TB", "tb"),
+        ("HTB", "tb"),
+        ("FTB", "tb"),
+        ("OU", "tb"),
+        ("HOU", "tb"),
+    ]
+    .into_iter()
+    .map(|(marker, stereo)| (marker.to_string(), stereo.to_string()))
+    .collect()
+}
+
+/// Field of view in degrees for projections that need one, used unless a lens preset says otherwise.
+pub fn builtin_projection_fovs() -> Vec<(String, f64)> {
+    [("equirectangular", 180.0), ("equirectangular360", 360.0), ("fisheye", 190.0)]
+        .into_iter()
+        .map(|(projection, fov)| (projection.to_string(), fov))
+        .collect()
+}
+
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Index {
     pub access: i32,
@@ -19,7 +89,10 @@ pub struct Banner {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Library {
     pub name: String,
-    pub list: Vec<String>
+    pub list: Vec<String>,
+    /// Not part of HereSphere's own API, clients that don't know it ignore it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -42,6 +115,7 @@ pub struct VideoData {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub access: Option<i32>,
     pub title: String,
+    /// Duration in milliseconds.
     pub duration: f64,
     pub media: Vec<Media>,
     #[serde(skip_serializing_if = "Vec::is_empty")]
@@ -90,7 +164,10 @@ pub struct VideoData {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub write_tags: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub write_hsp: Option<bool>
+    pub write_hsp: Option<bool>,
+    /// JellyVR extension: HLS trickplay manifest with scrub preview tiles.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trickplay: Option<String>
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -110,8 +187,10 @@ pub struct Subtitle {
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Tag {
     pub name: String,
+    /// Start in milliseconds.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub start: Option<f64>,
+    /// End in milliseconds.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub end: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
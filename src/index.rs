@@ -1,15 +1,21 @@
 use super::AppError;
 use super::AppState;
+use super::JellyVrError;
+use super::retry_db;
+use super::{Secret, Session, SessionState, User};
 use crate::heresphere;
 use crate::jellyfin::{
     self,
-    types::{BaseItemKind, LocationType},
+    types::{BaseItemKind, LocationType, PlayAccess},
 };
 use crate::AppConfig;
 use color_eyre::Section;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use futures::StreamExt;
 use serde::Deserialize;
 use serde::Serialize;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use surrealdb;
 use surrealdb::engine::local::Db;
 use surrealdb::Surreal;
@@ -19,8 +25,34 @@ use tracing;
 pub(crate) struct HeresphereIndex {
     pub(crate) id: Option<surrealdb::sql::Thing>,
     pub(crate) libraries: Vec<heresphere::Library>,
-    pub(crate) scan: Option<heresphere::Scan>,
+    /// Ids of the indexed videos in scan order, the scan itself is read from `videos` when it's asked for.
+    #[serde(default)]
+    pub(crate) videos: Vec<String>,
     pub(crate) last_updated: chrono::DateTime<chrono::Utc>,
+    /// Host the links in this index point to, used to rebuild it outside of a request.
+    #[serde(default)]
+    pub(crate) host: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub(crate) struct ReindexSummary {
+    pub(crate) reindexed: Vec<String>,
+    /// Users that never loaded their library, there's no host to build links with.
+    pub(crate) skipped: Vec<String>,
+    pub(crate) failed: Vec<ReindexFailure>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct ReindexFailure {
+    pub(crate) username: String,
+    pub(crate) error: String,
+}
+
+/// What a cache vacuum dropped, sessions and video overrides are never part of it.
+#[derive(Serialize, Debug, Clone, Default)]
+pub(crate) struct VacuumSummary {
+    /// Cache keys of the users whose index and videos were dropped.
+    pub(crate) indexes: Vec<String>,
 }
 
 #[derive(Serialize, Debug, Clone)]
@@ -33,66 +65,183 @@ impl HeresphereIndex {
     pub(crate) async fn prime_data(
         app: &AppState,
         host: &str,
-        user_id: &str,
-        token: &str,
+        user: &User,
     ) -> Result<HeresphereIndex, AppError> {
-        let user = app.jellyfin.client.resume_user(&user_id, &token);
-        let items = user
-            .items()
-            .await?
-            .items
-            .ok_or(AppError(eyre::eyre!("No items in BaseItemDtoQueryResult")))?;
-        let videos = baseitems_to_video_cache(
-            &user_id,
-            &app.config.jellyfin_remote_host,
-            &token,
+        let jellyfin_user = app.jellyfin_user(user)?;
+        let remote_host = &app.jellyfin.server(&user.server)?.remote_host;
+        let token = &user.token;
+        let cache_key = user.cache_key();
+        let images = ImageSource::new(app, host, user)?;
+        let sort = match jellyfin_user.sort_preference().await {
+            Ok(Some(sort)) => sort,
+            Ok(None) => jellyfin::ItemSort::default(),
+            Err(e) => {
+                tracing::debug!(error = ?e, "Failed to get display preferences, using default sort");
+                jellyfin::ItemSort::default()
+            }
+        };
+        let items = match selected_libraries(&app.config, &jellyfin_user).await? {
+            None => jellyfin_user
+                .items(&app.config.item_types, &app.config.item_fields, &sort, &app.config.item_filters, None)
+                .await?
+                .items
+                .ok_or(AppError(eyre::eyre!("No items in BaseItemDtoQueryResult")))?,
+            Some(library_ids) => {
+                let mut items = vec![];
+                for library_id in library_ids {
+                    items.extend(
+                        jellyfin_user
+                            .items(&app.config.item_types, &app.config.item_fields, &sort, &app.config.item_filters, Some(&library_id))
+                            .await?
+                            .items
+                            .unwrap_or_default(),
+                    );
+                }
+                dedup_items(items)
+            }
+        };
+        let trashed = TrashedVideo::ids_for_user(&app.db.get(), &cache_key).await?;
+        let items: Vec<_> = items
+            .into_iter()
+            .filter(|item| {
+                item.id
+                    .map_or(true, |id| !trashed.contains(&id.simple().to_string()))
+            })
+            .collect();
+        let trickplay = if app.config.enable_trickplay {
+            match jellyfin_user.trickplay(&app.config.item_types).await {
+                Ok(trickplay) => trickplay_urls(remote_host, token, &primary_media_sources(&items), &trickplay),
+                Err(e) => {
+                    tracing::warn!(error = ?e, "Failed to get trickplay info");
+                    HashMap::new()
+                }
+            }
+        } else {
+            HashMap::new()
+        };
+        let mut videos = baseitems_to_video_cache(
+            &cache_key,
+            remote_host,
+            token,
+            &images,
             &app.config,
             &items,
+            &trickplay,
         );
+        let channels = if app.config.live_tv {
+            match jellyfin_user.live_tv_channels().await {
+                Ok(channels) => channels.items.unwrap_or_default(),
+                Err(e) => {
+                    tracing::warn!(error = ?e, "Failed to get live TV channels");
+                    vec![]
+                }
+            }
+        } else {
+            vec![]
+        };
+        videos.extend(channels_to_video_cache(&cache_key, remote_host, token, &images, &app.config, &channels));
+        let overrides = VideoOverride::all_for_user(&app.db.get(), &cache_key).await?;
+        for video in &mut videos {
+            if let Some(video_override) = video.video_id().and_then(|id| overrides.get(&id)) {
+                video_override.apply(&mut video.data);
+            }
+        }
         tracing::debug!(
             videos_len = videos.len(),
             "Priming cache"
         );
+        let next_up = if app.config.next_up && app.config.item_types.iter().any(|item_type| item_type == "Episode") {
+            match jellyfin_user.next_up().await {
+                Ok(next_up) => next_up.items.unwrap_or_default(),
+                Err(e) => {
+                    tracing::warn!(error = ?e, "Failed to get next up episodes");
+                    vec![]
+                }
+            }
+        } else {
+            vec![]
+        };
+        let mut libraries = baseitems_to_libraries(&host, &app.config, &images, &items, &next_up);
+        if !channels.is_empty() {
+            libraries.push(heresphere::Library {
+                name: "Live TV".to_string(),
+                list: channels.iter().map(|channel| item_link(&host, channel)).collect(),
+                image: None,
+            });
+        }
+        // Tell libraries of different servers apart when a user is logged into several of them
+        if app.jellyfin.servers.len() > 1 {
+            for library in &mut libraries {
+                library.name = format!("{}: {}", user.server, library.name);
+            }
+        }
+        let prefetch_ids: Vec<String> = match app.config.prefetch_playback_info {
+            true => prefetch_candidates(&app.config, &items, &next_up),
+            false => vec![],
+        };
         let index = HeresphereIndex {
-            id: Some(surrealdb::sql::Thing::from(("index", user_id))),
-            libraries: baseitems_to_libraries(&host, &items),
-            scan: Some(video_cache_to_scan(&videos, &host)),
+            id: Some(surrealdb::sql::Thing::from(("index", cache_key.as_str()))),
+            libraries,
+            videos: videos.iter().filter_map(VideoCache::video_id).collect(),
             last_updated: chrono::Utc::now(),
+            host: Some(host.to_string()),
         };
-        app.db
+        app.db.get()
             .query("DELETE type::thing('index', $user); INSERT INTO index $data")
             .bind(Binding {
-                user: user_id.to_string(),
+                user: cache_key.clone(),
                 data: index.clone(),
             })
             .await?
             .check()
             .with_note(|| "Inserting cache")?;
-        app.db
-            .query("DELETE videos:[<string> $user, '']..; INSERT INTO videos $data")
-            .bind(Binding {
-                user: user_id.to_string(),
-                data: videos,
-            })
-            .await?
-            .check()
+        replace_user_videos(&app.db.get(), &cache_key, videos)
+            .await
             .with_note(|| "Inserting videos")?;
+        if app.config.prefetch_playback_info {
+            // One request per likely video, the index doesn't wait for them. Videos played before
+            // they're prefetched, or not among them, just get their playback info fetched then
+            let app = app.clone();
+            let user = user.clone();
+            tokio::spawn(async move {
+                if let Err(e) = PrefetchedPlaybackInfo::prefetch(&app, &user, &prefetch_ids).await {
+                    tracing::warn!(username = %user.username, error = ?e.0, "Failed to prefetch playback info");
+                }
+            });
+        }
         Ok(index)
     }
 
+    /// When the user's index was built, if it's fresh enough to be served as is.
+    /// Only reads the timestamp, so conditional requests are answered without loading the index.
+    pub(crate) async fn fresh_since(app: &AppState, user_key: &str) -> eyre::Result<Option<chrono::DateTime<chrono::Utc>>> {
+        let last_updated: Option<chrono::DateTime<chrono::Utc>> = retry_db(|| async move {
+            app.db.get()
+                .query("SELECT VALUE last_updated FROM type::thing('index', $user)")
+                .bind(("user", user_key))
+                .await?
+                .check()
+        })
+        .await?
+        .take(0)?;
+        Ok(last_updated.filter(|last_updated| *last_updated >= chrono::Utc::now() - app.config.cache_lifetime))
+    }
+
     pub(crate) async fn prime_data_maybe(
         app: &AppState,
         host: &str,
-        user_id: &str,
-        token: &str,
+        user: &User,
     ) -> Result<HeresphereIndex, AppError> {
-        let session: Result<Option<HeresphereIndex>, _> = app.db.select(("index", user_id)).await;
+        let session: Result<Option<HeresphereIndex>, _> =
+            app.db.get().select(("index", user.cache_key().as_str())).await;
         match session {
             Ok(Some(state)) => {
+                // Indexes from before the scan was streamed list no videos, even with some in "Everything"
+                let is_outdated = state.videos.is_empty() && state.libraries.iter().any(|library| !library.list.is_empty());
                 // Check if cache is too old
-                if state.last_updated < chrono::Utc::now() - app.config.cache_lifetime {
+                if is_outdated || state.last_updated < chrono::Utc::now() - app.config.cache_lifetime {
                     tracing::info!("Cache is too old, updating");
-                    HeresphereIndex::prime_data(app, host, user_id, token).await
+                    HeresphereIndex::prime_data(app, host, user).await
                 } else {
                     tracing::debug!("Cache is fresh");
                     Ok(state)
@@ -100,21 +249,142 @@ impl HeresphereIndex {
             }
             Ok(None) => {
                 tracing::debug!("No cache found, creating initial cache.");
-                HeresphereIndex::prime_data(app, host, user_id, token).await
+                HeresphereIndex::prime_data(app, host, user).await
             }
             Err(e) => {
                 tracing::error!(error = ?e, "Error getting cache, creating initial cache.");
-                HeresphereIndex::prime_data(app, host, user_id, token).await
+                HeresphereIndex::prime_data(app, host, user).await
+            }
+        }
+    }
+
+    /// Rebuilds the index of every logged in user that already has one, `concurrency` users at a time.
+    /// Failures are logged per user and don't stop the others.
+    /// Each user's index is replaced as a whole, so dropping this midway leaves the rest on their previous index.
+    pub(crate) async fn reindex_all(app: &AppState, concurrency: usize) -> eyre::Result<ReindexSummary> {
+        let sessions: Vec<SessionState> = app.db.get().select("session").await?;
+        let mut users: Vec<User> = sessions
+            .into_iter()
+            .filter_map(|state| match state.session {
+                Session::User(user) => Some(user),
+                _ => None,
+            })
+            .collect();
+        users.sort_by_key(User::cache_key);
+        users.dedup_by_key(|user| user.cache_key());
+
+        let results: Vec<(String, Result<bool, String>)> = futures::stream::iter(users)
+            .map(|user| async move {
+                let index: Result<Option<HeresphereIndex>, _> =
+                    app.db.get().select(("index", user.cache_key().as_str())).await;
+                let host = match index {
+                    Ok(Some(HeresphereIndex { host: Some(host), .. })) => host,
+                    Ok(_) => {
+                        tracing::debug!(username = %user.username, "No previous index to rebuild");
+                        return (user.username, Ok(false));
+                    }
+                    Err(e) => {
+                        tracing::warn!(username = %user.username, error = ?e, "Failed to read index");
+                        return (user.username, Err(e.to_string()));
+                    }
+                };
+                match HeresphereIndex::prime_data(app, &host, &user).await {
+                    Ok(_) => {
+                        tracing::info!(username = %user.username, "Rebuilt index");
+                        (user.username, Ok(true))
+                    }
+                    Err(e) => {
+                        tracing::warn!(username = %user.username, error = ?e.0, "Failed to rebuild index");
+                        (user.username, Err(e.0.to_string()))
+                    }
+                }
+            })
+            .buffer_unordered(concurrency.max(1))
+            .collect()
+            .await;
+
+        let mut summary = ReindexSummary::default();
+        for (username, result) in results {
+            match result {
+                Ok(true) => summary.reindexed.push(username),
+                Ok(false) => summary.skipped.push(username),
+                Err(error) => summary.failed.push(ReindexFailure { username, error }),
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Drops cached indexes, and the videos that go with them, of users that no longer have a session
+    /// or haven't loaded their library within `cache_retention`, plus videos left without an index.
+    /// With `everything` the whole cache goes. Sessions and video overrides are left alone either way,
+    /// users just get their index rebuilt the next time HereSphere asks.
+    pub(crate) async fn vacuum(app: &AppState, everything: bool) -> eyre::Result<VacuumSummary> {
+        let sessions: Vec<SessionState> = app.db.get().select("session").await?;
+        let users: Vec<String> = sessions
+            .into_iter()
+            .filter_map(|state| match state.session {
+                Session::User(user) => Some(user.cache_key()),
+                _ => None,
+            })
+            .collect();
+        // Just the ages, whole indexes carry the scan of every video
+        #[derive(Deserialize)]
+        struct IndexAge {
+            id: surrealdb::sql::Thing,
+            last_updated: chrono::DateTime<chrono::Utc>,
+        }
+        let indexes: Vec<IndexAge> = app.db.get().query("SELECT id, last_updated FROM index").await?.take(0)?;
+        let cutoff = chrono::Utc::now() - app.config.cache_retention;
+        let mut summary = VacuumSummary::default();
+        for index in indexes {
+            let key = index.id.id.to_raw();
+            if everything || index.last_updated < cutoff || !users.contains(&key) {
+                // Only the index as it was read, a rebuild in the meantime keeps it and its videos
+                let mut response = app
+                    .db
+                    .get()
+                    .query(
+                        "DELETE type::thing('index', $user) WHERE last_updated = $last_updated RETURN BEFORE;
+                        DELETE videos WHERE meta::id(id)[0] = <string> $user AND type::thing('index', $user).id = NONE;",
+                    )
+                    .bind(("user", &key))
+                    .bind(("last_updated", index.last_updated))
+                    .await?
+                    .check()
+                    .with_note(|| "Dropping cached index")?;
+                let dropped: Vec<IndexAge> = response.take(0)?;
+                if !dropped.is_empty() {
+                    summary.indexes.push(key);
+                }
             }
         }
+        // Indexes are looked up in the same transaction, one written by a rebuild in the meantime
+        // keeps its videos. Rebuilds write the index before the videos, so those are never orphans
+        app.db.get()
+            .query(
+                "BEGIN TRANSACTION;
+                LET $indexed = (SELECT VALUE <string> meta::id(id) FROM index);
+                DELETE videos WHERE <string> meta::id(id)[0] NOTINSIDE $indexed;
+                COMMIT TRANSACTION;",
+            )
+            .await?
+            .check()
+            .with_note(|| "Dropping orphaned videos")?;
+        app.db.get()
+            .query("DELETE playback_info WHERE fetched_at < $cutoff")
+            .bind(("cutoff", chrono::Utc::now() - app.config.prefetch_playback_info_ttl))
+            .await?
+            .check()
+            .with_note(|| "Dropping expired playback info")?;
+        Ok(summary)
     }
 
     pub(crate) async fn get_video(
         db: &Surreal<Db>,
-        user_id: &str,
+        user_key: &str,
         video_id: &str,
     ) -> Result<VideoCache, AppError> {
-        let binds = HashMap::from([("user", user_id), ("video", video_id)]);
+        let binds = HashMap::from([("user", user_key), ("video", video_id)]);
         let resp = db
             .query("SELECT * FROM type::thing('videos', [<string> $user, $video])")
             .bind(binds)
@@ -125,34 +395,473 @@ impl HeresphereIndex {
             Some(video) => {
                 Ok(video)
             }
-            None => Err(AppError(eyre::eyre!("No video found"))),
+            None => Err(AppError(JellyVrError::NotFound(format!("Video {}", video_id)).into())),
         }
     }
+
+    /// Swaps the user tags of a cached video for the ones just written back to Jellyfin.
+    pub(crate) async fn set_video_user_tags(
+        db: &Surreal<Db>,
+        config: &AppConfig,
+        user_key: &str,
+        video: &mut VideoCache,
+        tags: &[String],
+    ) -> Result<(), AppError> {
+        let synthetic = video
+            .data
+            .tags
+            .iter()
+            .filter(|tag| !matches!(tag.name.split_once(':'), Some(("Tag", _)) | None))
+            .cloned();
+        let user = tags.iter().map(|tag| heresphere::Tag {
+            name: sanitize_tag_name(config, &format!("Tag:{}", tag)),
+            ..Default::default()
+        });
+        video.data.tags = synthetic.chain(user).collect();
+        db.query("UPDATE type::thing('videos', [<string> $user, $video]) MERGE { data: { tags: $tags } }")
+            .bind(("user", user_key))
+            .bind(("video", video.video_id().unwrap_or_default()))
+            .bind(("tags", &video.data.tags))
+            .await?
+            .check()
+            .with_note(|| "Updating cached video")?;
+        Ok(())
+    }
+
+    /// Updates the cached favorite (unwatched) flag of a video after it was changed from the headset.
+    pub(crate) async fn set_video_favorite(
+        db: &Surreal<Db>,
+        user_key: &str,
+        video_id: &str,
+        is_favorite: bool,
+    ) -> Result<(), AppError> {
+        db.query("UPDATE type::thing('videos', [<string> $user, $video]) MERGE { data: { isFavorite: $is_favorite } }")
+            .bind(("user", user_key))
+            .bind(("video", video_id))
+            .bind(("is_favorite", is_favorite))
+            .await?
+            .check()
+            .with_note(|| "Updating cached video")?;
+        Ok(())
+    }
+}
+
+/// Ids of the Jellyfin libraries to index according to the include/exclude lists,
+/// or `None` when neither is configured and every library is fair game.
+async fn selected_libraries(
+    config: &AppConfig,
+    jellyfin_user: &jellyfin::JellyfinUser,
+) -> Result<Option<Vec<String>>, AppError> {
+    if config.include_libraries.is_empty() && config.exclude_libraries.is_empty() {
+        return Ok(None);
+    }
+    let views = jellyfin_user.views().await?.items.unwrap_or_default();
+    let library_ids = views
+        .iter()
+        .filter(|view| {
+            is_library_selected(
+                &config.include_libraries,
+                &config.exclude_libraries,
+                view.name.as_deref().unwrap_or_default(),
+            )
+        })
+        .filter_map(|view| view.id.map(|id| id.simple().to_string()))
+        .collect::<Vec<_>>();
+    tracing::debug!(
+        libraries = views.len(),
+        selected = library_ids.len(),
+        "Filtered libraries"
+    );
+    Ok(Some(library_ids))
+}
+
+fn is_library_selected(include: &[String], exclude: &[String], name: &str) -> bool {
+    let listed = |names: &[String]| names.iter().any(|listed| listed.eq_ignore_ascii_case(name));
+    // Exclude wins when a library is on both lists
+    (include.is_empty() || listed(include)) && !listed(exclude)
+}
+
+/// Drops items seen before, libraries overlap (ex. a movie in its library and in a collection)
+/// and each video can only be cached once.
+fn dedup_items(items: Vec<jellyfin::types::BaseItemDto>) -> Vec<jellyfin::types::BaseItemDto> {
+    let mut seen = HashSet::new();
+    items
+        .into_iter()
+        .filter(|item| item.id.map_or(true, |id| seen.insert(id)))
+        .collect()
 }
 
 pub(crate) fn baseitems_to_libraries(
     host: &str,
+    config: &AppConfig,
+    images: &ImageSource,
     items: &[jellyfin::types::BaseItemDto],
+    next_up: &[jellyfin::types::BaseItemDto],
 ) -> Vec<heresphere::Library> {
-    let everything = items
+    let available: Vec<&jellyfin::types::BaseItemDto> = items
+        .iter()
+        .filter(|item| baseitem_skip_reason(config, item).is_none())
+        .collect();
+    // Collapsed series only show up in their own library, not between the movies
+    let everything = available
+        .iter()
+        .filter(|item| !config.collapse_series || baseitem_series(item).is_none())
+        .map(|item| item_link(host, item))
+        .collect();
+
+    let mut libraries = vec![heresphere::Library {
+        name: "Everything".to_string(),
+        list: everything,
+        image: None,
+    }];
+
+    if config.recently_added_count > 0 {
+        let mut recent = available.clone();
+        // Stable sort keeps the server order for items added at the same time
+        recent.sort_by(|a, b| b.date_created.cmp(&a.date_created));
+        libraries.push(heresphere::Library {
+            name: "Recently Added".to_string(),
+            list: recent
+                .iter()
+                .take(config.recently_added_count)
+                .map(|item| item_link(host, item))
+                .collect(),
+            image: None,
+        });
+    }
+
+    if config.continue_watching {
+        libraries.push(heresphere::Library {
+            name: "Continue Watching".to_string(),
+            list: in_progress_items(&available)
+                .iter()
+                .map(|item| item_link(host, item))
+                .collect(),
+            image: None,
+        });
+    }
+
+    if config.next_up {
+        let next_up = indexed_next_up(&available, next_up)
+            .iter()
+            .map(|episode| item_link(host, episode))
+            .collect::<Vec<_>>();
+        if !next_up.is_empty() {
+            libraries.push(heresphere::Library {
+                name: "Next Up".to_string(),
+                list: next_up,
+                image: None,
+            });
+        }
+    }
+
+    if config.browse_by_year != "none" {
+        // Only years that have something in them, newest first
+        let mut by_year: BTreeMap<i32, Vec<String>> = BTreeMap::new();
+        for item in &available {
+            let Some(year) = baseitem_year(item) else {
+                continue;
+            };
+            let key = match config.browse_by_year.as_str() {
+                "decade" => year - year.rem_euclid(10),
+                _ => year,
+            };
+            by_year.entry(key).or_default().push(item_link(host, item));
+        }
+        libraries.extend(by_year.into_iter().rev().map(|(year, list)| heresphere::Library {
+            name: match config.browse_by_year.as_str() {
+                "decade" => format!("{}s", year),
+                _ => year.to_string(),
+            },
+            list,
+            image: None,
+        }));
+    }
+
+    libraries.extend(entity_libraries(
+        host,
+        &config.browse_by,
+        config.browse_by_min_videos,
+        config.browse_by_images.then_some(images),
+        &available,
+    ));
+
+    if config.collapse_series {
+        libraries.extend(series_libraries(host, &available));
+    }
+
+    libraries
+}
+
+/// Series an episode belongs to.
+fn baseitem_series(item: &jellyfin::types::BaseItemDto) -> Option<&str> {
+    match item.type_ {
+        Some(BaseItemKind::Episode) => item.series_name.as_deref(),
+        _ => None,
+    }
+}
+
+/// Production year of an item, falling back to the year it premiered.
+fn baseitem_year(item: &jellyfin::types::BaseItemDto) -> Option<i32> {
+    use chrono::Datelike;
+    item.production_year.or_else(|| item.premiere_date.map(|date| date.year()))
+}
+
+/// Episodes of each series in season and episode order, whatever the sort of the items.
+fn episodes_by_series<'a>(
+    items: &[&'a jellyfin::types::BaseItemDto],
+) -> BTreeMap<&'a str, Vec<&'a jellyfin::types::BaseItemDto>> {
+    let mut by_series: BTreeMap<&str, Vec<&jellyfin::types::BaseItemDto>> = BTreeMap::new();
+    for &item in items {
+        if let Some(series) = baseitem_series(item) {
+            by_series.entry(series).or_default().push(item);
+        }
+    }
+    for episodes in by_series.values_mut() {
+        // Stable, so episodes without numbers keep their order at the front
+        episodes.sort_by_key(|episode| (episode.parent_index_number, episode.index_number));
+    }
+    by_series
+}
+
+/// `NextEpisode:` tag of every episode followed by another one, naming that one as HereSphere lists it.
+fn next_episode_tags(config: &AppConfig, items: &[&jellyfin::types::BaseItemDto]) -> HashMap<String, heresphere::Tag> {
+    let mut tags = HashMap::new();
+    for episodes in episodes_by_series(items).into_values() {
+        for pair in episodes.windows(2) {
+            let Some(id) = pair[0].id else {
+                continue;
+            };
+            let name = format!("NextEpisode:{}", render_episode_title(&config.episode_title_template, pair[1]));
+            let tag = heresphere::Tag {
+                name: sanitize_tag_name(config, &name),
+                track: config.tag_tracks.get("NextEpisode").copied(),
+                ..Default::default()
+            };
+            tags.insert(id.simple().to_string(), tag);
+        }
+    }
+    tags
+}
+
+/// A library per series with its episodes in season and episode order, whatever the sort of the items.
+fn series_libraries(host: &str, items: &[&jellyfin::types::BaseItemDto]) -> Vec<heresphere::Library> {
+    episodes_by_series(items)
+        .into_iter()
+        .map(|(series, episodes)| {
+            heresphere::Library {
+                name: series.to_string(),
+                list: episodes.iter().map(|episode| item_link(host, episode)).collect(),
+                image: None,
+            }
+        })
+        .collect()
+}
+
+/// Partially watched items, last played first.
+fn in_progress_items<'a>(available: &[&'a jellyfin::types::BaseItemDto]) -> Vec<&'a jellyfin::types::BaseItemDto> {
+    let mut in_progress: Vec<_> = available
         .iter()
         .filter_map(|item| {
-            if let Some(LocationType::Virtual) = item.location_type {
+            let user_data = item.user_data.as_ref()?;
+            let played_percentage = user_data.played_percentage?;
+            // Never-started and fully played items don't need resuming
+            if played_percentage < 1.0 || played_percentage > 99.0 || user_data.played.unwrap_or_default() {
                 return None;
             }
-            Some(format!(
-                "{}/heresphere/{}",
-                host,
-                item.id.expect("No id in BaseItemDto").simple().to_string()
-            ))
+            Some((user_data.last_played_date, *item))
         })
         .collect();
+    in_progress.sort_by(|(a, _), (b, _)| b.cmp(a));
+    in_progress.into_iter().map(|(_, item)| item).collect()
+}
 
-    
-    vec![heresphere::Library {
-        name: "Everything".to_string(),
-        list: everything,
-    }]
+/// Next up episodes that made it into the index, anything else has no video to open.
+fn indexed_next_up<'a>(
+    available: &[&jellyfin::types::BaseItemDto],
+    next_up: &'a [jellyfin::types::BaseItemDto],
+) -> Vec<&'a jellyfin::types::BaseItemDto> {
+    next_up
+        .iter()
+        .filter(|episode| available.iter().any(|item| item.id.is_some() && item.id == episode.id))
+        .collect()
+}
+
+/// Most videos whose playback info is prefetched per index, every one of them opens a play session in Jellyfin.
+pub(crate) const PREFETCH_MAX_VIDEOS: usize = 20;
+
+/// Videos likely to be played next, the ones in progress and then the next up episodes, at most
+/// [`PREFETCH_MAX_VIDEOS`]. Live TV channels are never among them, their playback info opens the stream.
+fn prefetch_candidates(
+    config: &AppConfig,
+    items: &[jellyfin::types::BaseItemDto],
+    next_up: &[jellyfin::types::BaseItemDto],
+) -> Vec<String> {
+    let available: Vec<&jellyfin::types::BaseItemDto> = items
+        .iter()
+        .filter(|item| baseitem_skip_reason(config, item).is_none())
+        .collect();
+    let mut ids: Vec<String> = vec![];
+    for item in in_progress_items(&available).into_iter().chain(indexed_next_up(&available, next_up)) {
+        match item.id.map(|id| id.simple().to_string()) {
+            Some(id) if !ids.contains(&id) => ids.push(id),
+            _ => {}
+        }
+    }
+    ids.truncate(PREFETCH_MAX_VIDEOS);
+    ids
+}
+
+/// What `browse_by` can add libraries for.
+pub(crate) const BROWSE_BY: &[&str] = &["genres", "studios", "people"];
+
+/// A library per genre, studio or person (ex. "Actor: Jane Doe") with at least `min_videos` videos,
+/// in the order of `BROWSE_BY` and by name within each. With `images`, each library also links the
+/// Jellyfin image of its genre, studio or person, where Jellyfin has one.
+fn entity_libraries(
+    host: &str,
+    browse_by: &[String],
+    min_videos: usize,
+    images: Option<&ImageSource>,
+    items: &[&jellyfin::types::BaseItemDto],
+) -> Vec<heresphere::Library> {
+    let mut libraries = vec![];
+    for kind in BROWSE_BY.iter().filter(|kind| browse_by.iter().any(|listed| listed.as_str() == **kind)) {
+        // Name of the library, with the Jellyfin id of its entity when it has an image
+        let mut by_entity: BTreeMap<String, (Vec<String>, Option<uuid::Uuid>)> = BTreeMap::new();
+        for item in items {
+            let names: Vec<(String, Option<uuid::Uuid>)> = match *kind {
+                "genres" => item
+                    .genres
+                    .iter()
+                    .flatten()
+                    .map(|genre| {
+                        // `Genres` only has names, the ids are in `GenreItems`
+                        let id = item
+                            .genre_items
+                            .iter()
+                            .flatten()
+                            .find(|pair| pair.name.as_deref() == Some(genre.as_str()))
+                            .and_then(|pair| pair.id);
+                        (format!("Genre: {}", genre), id)
+                    })
+                    .collect(),
+                "studios" => item
+                    .studios
+                    .iter()
+                    .flatten()
+                    .filter_map(|studio| Some((format!("Studio: {}", studio.name.as_ref()?), studio.id)))
+                    .collect(),
+                _ => item
+                    .people
+                    .iter()
+                    .flatten()
+                    .filter_map(|person| {
+                        let name = person.name.as_ref()?;
+                        // People without a primary image would only link a broken one
+                        let id = person.primary_image_tag.as_ref().and(person.id);
+                        Some((format!("{}: {}", person.type_.as_deref().unwrap_or("Person"), name), id))
+                    })
+                    .collect(),
+            };
+            let link = item_link(host, item);
+            for (name, id) in names {
+                let (list, entity_id) = by_entity.entry(name).or_default();
+                // A person can be in a video more than once, ex. as director and writer
+                if !list.contains(&link) {
+                    list.push(link.clone());
+                }
+                *entity_id = entity_id.or(id);
+            }
+        }
+        libraries.extend(
+            by_entity
+                .into_iter()
+                .filter(|(_, (list, _))| list.len() >= min_videos.max(1))
+                .map(|(name, (list, id))| heresphere::Library {
+                    name,
+                    list,
+                    image: images.zip(id).map(|(images, id)| images.url(&id.simple().to_string(), "Primary")),
+                }),
+        );
+    }
+    libraries
+}
+
+fn item_link(host: &str, item: &jellyfin::types::BaseItemDto) -> String {
+    format!(
+        "{}/heresphere/{}",
+        host,
+        item.id.expect("No id in BaseItemDto").simple().to_string()
+    )
+}
+
+/// Reads the scan of an index from `videos`, `scan_chunk_size` videos per query with up to `scan_concurrency`
+/// queries at once, in the order of the index. Chunks come out as they're read, so a huge library
+/// is never all in memory at once.
+pub(crate) fn scan_chunks(
+    app: &AppState,
+    user_key: &str,
+    index: &HeresphereIndex,
+    host: &str,
+) -> impl futures::Stream<Item = eyre::Result<Vec<heresphere::ScanData>>> {
+    let chunk_size = match app.config.scan_chunk_size {
+        0 => index.videos.len().max(1),
+        chunk_size => chunk_size,
+    };
+    let chunks: Vec<Vec<String>> = index.videos.chunks(chunk_size).map(<[String]>::to_vec).collect();
+    let db = app.db.get();
+    let user_key = user_key.to_string();
+    let host = host.to_string();
+    futures::stream::iter(chunks)
+        .map(move |ids| scan_chunk(db.clone(), user_key.clone(), ids, host.clone()))
+        .buffered(app.config.scan_concurrency.max(1))
+}
+
+/// Record ids of every cached video of a user. Ids are `[user, video]` and videos are strings,
+/// which sort after NONE and before any object, so the range holds this user's videos and no one else's.
+fn user_videos_range(user_key: &str) -> std::ops::Range<surrealdb::sql::Id> {
+    let bound = |video: surrealdb::sql::Value| {
+        let key = vec![surrealdb::sql::Value::from(user_key), video];
+        surrealdb::sql::Id::Array(key.into())
+    };
+    bound(surrealdb::sql::Value::None)..bound(surrealdb::sql::Value::Object(Default::default()))
+}
+
+/// Swaps the cached videos of a user for `videos`. Deleting by id range only touches the
+/// records of this user instead of going through the videos of everyone.
+async fn replace_user_videos(db: &Surreal<Db>, user_key: &str, videos: Vec<VideoCache>) -> eyre::Result<()> {
+    let _: Vec<serde::de::IgnoredAny> =
+        retry_db(|| async move { db.delete("videos").range(user_videos_range(user_key)).await }).await?;
+    let videos = &videos;
+    retry_db(|| async move { db.query("INSERT INTO videos $data").bind(("data", videos)).await?.check() }).await?;
+    Ok(())
+}
+
+async fn scan_chunk(
+    db: Surreal<Db>,
+    user_key: String,
+    ids: Vec<String>,
+    host: String,
+) -> eyre::Result<Vec<heresphere::ScanData>> {
+    let things: Vec<surrealdb::sql::Thing> = ids
+        .iter()
+        .map(|id| {
+            let key = vec![surrealdb::sql::Value::from(user_key.as_str()), surrealdb::sql::Value::from(id.as_str())];
+            surrealdb::sql::Thing::from(("videos", surrealdb::sql::Id::Array(key.into())))
+        })
+        .collect();
+    let (db, things) = (&db, &things);
+    let videos: Vec<VideoCache> = retry_db(|| async move { db.query("SELECT * FROM $videos").bind(("videos", things)).await?.check() })
+        .await?
+        .take(0)?;
+    let mut videos: HashMap<String, VideoCache> = videos
+        .into_iter()
+        .filter_map(|video| Some((video.video_id()?, video)))
+        .collect();
+    // Videos dropped since the index was built (ex. trashed ones) are left out
+    let videos: Vec<VideoCache> = ids.iter().filter_map(|id| videos.remove(id)).collect();
+    Ok(video_cache_to_scan(&videos, &host).scan_data)
 }
 
 fn video_cache_to_scan(videos: &[VideoCache], host: &str) -> heresphere::Scan {
@@ -160,7 +869,8 @@ fn video_cache_to_scan(videos: &[VideoCache], host: &str) -> heresphere::Scan {
         .iter()
         .map(|video| {
             let mut v = video.data.clone();
-            v.access = None;
+            // Full access is the default, restricted videos have to say so in the scan too
+            v.access = v.access.filter(|access| *access != 1);
             heresphere::ScanData {
             link: match &video.id.id {
                 surrealdb::sql::Id::Array(ref id) => format!("{}/heresphere/{}", host, id[1].to_raw_string()),
@@ -184,122 +894,792 @@ pub struct VideoCache {
     last_updated: chrono::DateTime<chrono::Utc>,
 }
 
-fn baseitems_to_video_cache(
-    user_id: &str,
-    jf_host: &str,
-    jf_token: &str,
-    config: &AppConfig,
-    items: &[jellyfin::types::BaseItemDto],
-) -> Vec<VideoCache> {
-    items
-        .iter()
-        .filter_map(|item| {
-            let id = item.id.expect("No id in BaseItemDto").simple().to_string();
-            let data = baseitem_to_video(jf_host, jf_token, config, item)?;
-            Some(VideoCache {
-                id: surrealdb::sql::Thing::from((
-                    "videos",
-                    surrealdb::sql::Id::from(vec![user_id, &id]),
-                )),
-                data,
-                last_updated: chrono::Utc::now(),
-            })
-        })
-        .collect()
+impl VideoCache {
+    /// Jellyfin item id of this video.
+    fn video_id(&self) -> Option<String> {
+        match &self.id.id {
+            surrealdb::sql::Id::Array(id) => id.get(1).map(|id| id.to_raw_string()),
+            _ => None,
+        }
+    }
 }
 
-fn baseitem_to_video(
-    jf_host: &str,
-    jf_token: &str,
-    config: &AppConfig,
-    item: &jellyfin::types::BaseItemDto,
-) -> Option<heresphere::VideoData> {
-    if let Some(LocationType::Virtual) = item.location_type {
-        return None;
-    }
-    let id = item.id.expect("No id in BaseItemDto").simple().to_string();
-    let thumb = match item.type_.unwrap() {
-        BaseItemKind::Movie => format!(
-            "{}/Items/{}/Images/Backdrop?maxHeight=300&maxWidth=300&quality=90&api_key={}",
-            jf_host, id, jf_token
-        ),
-        _ => format!(
-            "{}/Items/{}/Images/Primary?maxHeight=300&maxWidth=300&quality=90&api_key={}",
-            jf_host, id, jf_token
-        ),
-    };
+/// Per-user, per-video settings saved from the headset, applied on top of what Jellyfin tells us.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub(crate) struct VideoOverride {
+    pub(crate) id: Option<surrealdb::sql::Thing>,
+    /// Base64 encoded HereSphere settings file, holds projection, stereo, lens and so on.
+    pub(crate) hsp: Option<String>,
+    /// Fixes videos encoded with the left and right eye swapped, or undoes a wrong marker match.
+    #[serde(default)]
+    pub(crate) is_eye_swapped: Option<bool>,
+}
 
-    let data = heresphere::VideoData {
-        access: Some(1),
-        title: match item.type_.unwrap() {
-                BaseItemKind::Episode => {
-                    let season = item.parent_index_number.clone().unwrap_or_default();
-                    let episode = item.index_number.clone().unwrap_or_default();
-                    let title = item.name.clone().unwrap_or_default();
-                    format!("S{:02}E{:02} - {}", season, episode, title)
-                }
-                _ => item.name.clone().unwrap_or_default(),
-            },
-        duration: (item.run_time_ticks.unwrap_or_default() as f64 / 10000.0),
-        tags: baseitem_to_tags(item),
-        media: baseitem_to_media(jf_host, jf_token, item),
-        date_released: baseitem_date_to_string(item.premiere_date),
-        date_added: baseitem_date_to_string(item.date_created),
-        projection: "perspective".to_string(),
-        stereo: "mono".to_string(),
-        is_favorite: item.user_data
-            .as_ref()
-            .and_then(|user_data| Some(!user_data.played.unwrap_or_default())),
-        thumbnail_image: thumb,
-        description: item.overview.clone(),
-        rating: Some(item.community_rating.unwrap_or_default() as f64 / 2.0), // 0-10 to 0-5
-        event_server: None,
-        subtitles: baseitem_to_subtitles(
-            item,
-            jf_host,
-            jf_token,
-            config.prefered_subtitles_language.as_deref(),
-        ),
-        write_hsp: Some(true),
-        ..Default::default()
-    };
-    Some(data)
+/// Fields of a [`VideoOverride`] to change, the ones left `None` keep their saved value.
+#[derive(Serialize, Debug, Default)]
+pub(crate) struct VideoOverridePatch {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) hsp: Option<String>,
+    /// `Some(None)` clears the override.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) is_eye_swapped: Option<Option<bool>>,
 }
 
-fn baseitem_to_tags(item: &jellyfin::types::BaseItemDto) -> Vec<heresphere::Tag> {
-    let mut tags = vec![];
-    if let Some(chapters) = &item.chapters {
-        let mut previous_tag: Option<usize> = None;
-        for chapter in chapters {
-            tags.push(heresphere::Tag {
-                name: format!(
-                    "Chapter:{}",
-                    chapter.name.as_ref().unwrap_or(&"Unknown".to_string())
-                ),
-                start: Some(chapter.start_position_ticks.unwrap_or_default() as f64 / 10000.0),
-                end: Some(item.run_time_ticks.unwrap_or_default() as f64 / 10000.0),
-                track: Some(0),
-                ..Default::default()
-            });
-            if let Some(previous_tag) = previous_tag {
-                tags[previous_tag].end =
-                    Some(chapter.start_position_ticks.unwrap_or_default() as f64 / 10000.0);
-            }
-            previous_tag = Some(tags.len() - 1);
-        }
+impl VideoOverride {
+    pub(crate) async fn all_for_user(
+        db: &Surreal<Db>,
+        user_key: &str,
+    ) -> Result<HashMap<String, VideoOverride>, AppError> {
+        let overrides: Vec<VideoOverride> = db
+            .query("SELECT * FROM videos_overrides WHERE meta::id(id)[0] = <string> $user")
+            .bind(("user", user_key))
+            .await?
+            .check()?
+            .take(0)?;
+        Ok(overrides
+            .into_iter()
+            .filter_map(|video_override| {
+                let id = match &video_override.id.as_ref()?.id {
+                    surrealdb::sql::Id::Array(id) => id.get(1)?.to_raw_string(),
+                    _ => return None,
+                };
+                Some((id, video_override))
+            })
+            .collect())
     }
 
-    if let Some(genres) = &item.genres {
-        for genre in genres {
-            tags.push(heresphere::Tag {
-                name: format!("Genre:{}", genre),
-                ..Default::default()
-            });
-        }
-    }
-    if let Some(tags_) = &item.tags {
-        for tag in tags_ {
-            tags.push(heresphere::Tag {
+    pub(crate) async fn get(
+        db: &Surreal<Db>,
+        user_key: &str,
+        video_id: &str,
+    ) -> Result<Option<VideoOverride>, AppError> {
+        let binds = HashMap::from([("user", user_key), ("video", video_id)]);
+        let video_override = db
+            .query("SELECT * FROM type::thing('videos_overrides', [<string> $user, $video])")
+            .bind(binds)
+            .await?
+            .check()?
+            .take(0)?;
+        Ok(video_override)
+    }
+
+    /// Writes only the fields set in `patch`, in a single `MERGE`, so requests changing different
+    /// fields of the same video at once (ex. the headset's settings and an admin fix) both stick.
+    pub(crate) async fn save(
+        db: &Surreal<Db>,
+        user_key: &str,
+        video_id: &str,
+        patch: &VideoOverridePatch,
+    ) -> Result<(), AppError> {
+        db.query("UPDATE type::thing('videos_overrides', [<string> $user, $video]) MERGE $patch")
+            .bind(("user", user_key))
+            .bind(("video", video_id))
+            .bind(("patch", patch))
+            .await?
+            .check()
+            .with_note(|| "Saving video override")?;
+        Ok(())
+    }
+
+    pub(crate) fn apply(&self, video: &mut heresphere::VideoData) {
+        if let Some(hsp) = &self.hsp {
+            video.hsp = Some(hsp.clone());
+        }
+        if let Some(is_eye_swapped) = self.is_eye_swapped {
+            video.is_eye_swapped = Some(is_eye_swapped);
+        }
+    }
+}
+
+/// Video a user deleted from the headset while soft delete is on. It's left out of their index,
+/// Jellyfin doesn't know about it until an admin purges it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct TrashedVideo {
+    pub(crate) id: Option<surrealdb::sql::Thing>,
+    pub(crate) title: String,
+    pub(crate) deleted_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl TrashedVideo {
+    pub(crate) async fn all(db: &Surreal<Db>) -> Result<Vec<TrashedVideo>, AppError> {
+        let trashed: Vec<TrashedVideo> = db.select("videos_trash").await?;
+        Ok(trashed)
+    }
+
+    /// Jellyfin ids of the videos the user trashed.
+    pub(crate) async fn ids_for_user(db: &Surreal<Db>, user_key: &str) -> Result<Vec<String>, AppError> {
+        let ids: Vec<String> = db
+            .query("SELECT VALUE <string> meta::id(id)[1] FROM videos_trash WHERE meta::id(id)[0] = <string> $user")
+            .bind(("user", user_key))
+            .await?
+            .check()?
+            .take(0)?;
+        Ok(ids)
+    }
+
+    /// Moves a video to the trash and drops the user's index, so it's rebuilt without the video.
+    pub(crate) async fn trash(db: &Surreal<Db>, user_key: &str, video_id: &str, title: &str) -> Result<(), AppError> {
+        db.query(
+            "UPDATE type::thing('videos_trash', [<string> $user, $video]) CONTENT { title: $title, deleted_at: time::now() };
+            DELETE type::thing('videos', [<string> $user, $video]);
+            DELETE type::thing('index', <string> $user);",
+        )
+        .bind(("user", user_key))
+        .bind(("video", video_id))
+        .bind(("title", title))
+        .await?
+        .check()
+        .with_note(|| "Trashing video")?;
+        Ok(())
+    }
+
+    /// Takes a video out of the trash, returns whether it was in there.
+    /// The user's index is dropped so the video shows up again.
+    pub(crate) async fn restore(db: &Surreal<Db>, user_key: &str, video_id: &str) -> Result<bool, AppError> {
+        let mut response = db
+            .query(
+                "DELETE type::thing('videos_trash', [<string> $user, $video]) RETURN BEFORE;
+                DELETE type::thing('index', <string> $user);",
+            )
+            .bind(("user", user_key))
+            .bind(("video", video_id))
+            .await?
+            .check()
+            .with_note(|| "Restoring video")?;
+        let restored: Vec<TrashedVideo> = response.take(0)?;
+        Ok(!restored.is_empty())
+    }
+}
+
+/// Playback info fetched while priming the index, so the first play doesn't wait on Jellyfin.
+/// Play sessions expire, entries older than `prefetch_playback_info_ttl` are never used.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct PrefetchedPlaybackInfo {
+    pub(crate) id: surrealdb::sql::Thing,
+    pub(crate) info: jellyfin::types::PlaybackInfoResponse,
+    pub(crate) fetched_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl PrefetchedPlaybackInfo {
+    /// Replaces the prefetched playback info of the user with fresh ones for these videos.
+    /// Videos Jellyfin fails to answer for are left out, they're fetched when played like before.
+    pub(crate) async fn prefetch(app: &AppState, user: &User, video_ids: &[String]) -> Result<(), AppError> {
+        let jellyfin_user = app.jellyfin_user(user)?;
+        let cache_key = user.cache_key();
+        let prefetched: Vec<PrefetchedPlaybackInfo> = futures::stream::iter(video_ids)
+            .map(|vid| {
+                let jellyfin_user = &jellyfin_user;
+                let cache_key = &cache_key;
+                async move {
+                    match jellyfin_user.playback_info(vid, false).await {
+                        Ok(info) => Some(PrefetchedPlaybackInfo {
+                            id: surrealdb::sql::Thing::from((
+                                "playback_info",
+                                surrealdb::sql::Id::from(vec![cache_key.as_str(), vid.as_str()]),
+                            )),
+                            info,
+                            fetched_at: chrono::Utc::now(),
+                        }),
+                        Err(e) => {
+                            tracing::debug!(video = %vid, error = ?e, "Failed to prefetch playback info");
+                            None
+                        }
+                    }
+                }
+            })
+            .buffer_unordered(app.config.max_concurrent_requests.max(1))
+            .filter_map(|prefetched| async move { prefetched })
+            .collect()
+            .await;
+        tracing::debug!(
+            prefetched = prefetched.len(),
+            videos = video_ids.len(),
+            "Prefetched playback info"
+        );
+        app.db.get()
+            .query("DELETE playback_info WHERE meta::id(id)[0] = <string> $user; INSERT INTO playback_info $data")
+            .bind(Binding {
+                user: cache_key,
+                data: prefetched,
+            })
+            .await?
+            .check()
+            .with_note(|| "Inserting prefetched playback info")?;
+        Ok(())
+    }
+
+    /// Takes the prefetched playback info of a video, if it's recent enough to still be played.
+    /// It's gone afterwards, so a play session is handed to the headset once at most.
+    pub(crate) async fn take(
+        db: &Surreal<Db>,
+        config: &AppConfig,
+        user_key: &str,
+        video_id: &str,
+    ) -> Result<Option<jellyfin::types::PlaybackInfoResponse>, AppError> {
+        let prefetched: Vec<PrefetchedPlaybackInfo> = db
+            .query("DELETE type::thing('playback_info', [<string> $user, $video]) RETURN BEFORE")
+            .bind(("user", user_key))
+            .bind(("video", video_id))
+            .await?
+            .check()?
+            .take(0)?;
+        let cutoff = chrono::Utc::now() - config.prefetch_playback_info_ttl;
+        Ok(prefetched
+            .into_iter()
+            .find(|prefetched| prefetched.fetched_at >= cutoff)
+            .map(|prefetched| prefetched.info))
+    }
+}
+
+fn baseitems_to_video_cache(
+    user_key: &str,
+    jf_host: &str,
+    jf_token: &str,
+    images: &ImageSource,
+    config: &AppConfig,
+    items: &[jellyfin::types::BaseItemDto],
+    trickplay: &HashMap<String, String>,
+) -> Vec<VideoCache> {
+    let mut skipped: HashMap<&str, usize> = HashMap::new();
+    let available: Vec<&jellyfin::types::BaseItemDto> = items
+        .iter()
+        .filter(|item| match baseitem_skip_reason(config, item) {
+            Some(reason) => {
+                *skipped.entry(reason).or_default() += 1;
+                false
+            }
+            None => true,
+        })
+        .collect();
+    let blocks_next_episode = config.tag_blocklist.iter().any(|blocked| blocked == "NextEpisode");
+    let mut next_episodes = match blocks_next_episode {
+        true => HashMap::new(),
+        false => next_episode_tags(config, &available),
+    };
+    let videos = available
+        .into_iter()
+        .filter_map(|item| {
+            let id = item.id.expect("No id in BaseItemDto").simple().to_string();
+            let mut data = baseitem_to_video(jf_host, jf_token, images, config, item)?;
+            data.trickplay = trickplay.get(&id).cloned();
+            data.tags.extend(next_episodes.remove(&id));
+            Some(VideoCache {
+                id: surrealdb::sql::Thing::from((
+                    "videos",
+                    surrealdb::sql::Id::from(vec![user_key, &id]),
+                )),
+                data,
+                last_updated: chrono::Utc::now(),
+            })
+        })
+        .collect();
+    if !skipped.is_empty() {
+        tracing::debug!(skipped = ?skipped, "Skipped unplayable items");
+    }
+    videos
+}
+
+/// Live TV channels have no fixed duration or media sources until their stream is opened,
+/// so they get a bare entry whose media is resolved through `PlaybackInfo` when HereSphere asks for it.
+/// A duration of 0 keeps the progress routine from ever marking them played.
+fn channels_to_video_cache(
+    user_key: &str,
+    jf_host: &str,
+    jf_token: &str,
+    images: &ImageSource,
+    config: &AppConfig,
+    channels: &[jellyfin::types::BaseItemDto],
+) -> Vec<VideoCache> {
+    channels
+        .iter()
+        .filter_map(|channel| {
+            let id = channel.id?.simple().to_string();
+            let name = channel.name.clone().unwrap_or_default();
+            let data = heresphere::VideoData {
+                access: Some(1),
+                title: match &channel.channel_number {
+                    Some(number) => format!("{} {}", number, name),
+                    None => name,
+                },
+                duration: 0.0,
+                media: vec![heresphere::Media {
+                    name: "Live".to_string(),
+                    sources: vec![heresphere::MediaSource {
+                        url: format!("{}/Videos/{}/master.m3u8?api_key={}", jf_host, id, jf_token),
+                        ..Default::default()
+                    }],
+                }],
+                tags: vec![heresphere::Tag {
+                    name: "Type:TvChannel".to_string(),
+                    ..Default::default()
+                }],
+                projection: config.default_projection.clone(),
+                stereo: config.default_stereo.clone(),
+                thumbnail_image: images.url(&id, "Primary"),
+                description: channel.overview.clone(),
+                ..Default::default()
+            };
+            Some(VideoCache {
+                id: surrealdb::sql::Thing::from((
+                    "videos",
+                    surrealdb::sql::Id::from(vec![user_key, &id]),
+                )),
+                data,
+                last_updated: chrono::Utc::now(),
+            })
+        })
+        .collect()
+}
+
+/// Where thumbnails are loaded from: Jellyfin itself with the token in the url, or
+/// the JellyVR image proxy (`image_proxy`) which authenticates on the server instead.
+pub(crate) enum ImageSource {
+    Direct { jf_host: String, jf_token: String },
+    Proxy { host: String, user_key: String, key: Secret },
+}
+
+impl ImageSource {
+    pub(crate) fn new(app: &AppState, host: &str, user: &User) -> eyre::Result<Self> {
+        if app.config.image_proxy {
+            return Ok(Self::Proxy {
+                host: host.to_string(),
+                user_key: user.cache_key(),
+                key: app.image_key.clone(),
+            });
+        }
+        Ok(Self::direct(&app.jellyfin.server(&user.server)?.remote_host, &user.token))
+    }
+
+    pub(crate) fn direct(jf_host: &str, jf_token: &str) -> Self {
+        Self::Direct {
+            jf_host: jf_host.to_string(),
+            jf_token: jf_token.to_string(),
+        }
+    }
+
+    fn url(&self, item_id: &str, image_type: &str) -> String {
+        let path = format!("Items/{}/Images/{}?maxHeight=300&maxWidth=300&quality=90", item_id, image_type);
+        match self {
+            Self::Direct { jf_host, jf_token } => format!("{}/{}&api_key={}", jf_host, path, jf_token),
+            Self::Proxy { host, user_key, key } => format!(
+                "{}/heresphere/images/{}/{}/{}",
+                host,
+                user_key,
+                image_signature(key, user_key, item_id, image_type),
+                path
+            ),
+        }
+    }
+}
+
+fn image_mac(key: &Secret, user_key: &str, item_id: &str, image_type: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.0.as_bytes()).expect("HMAC takes keys of any length");
+    // Separated so different splits of the same characters don't sign alike
+    for part in [user_key, item_id, image_type] {
+        mac.update(part.as_bytes());
+        mac.update(b"\0");
+    }
+    mac
+}
+
+/// Signature that lets the image proxy serve this one image of this user.
+pub(crate) fn image_signature(key: &Secret, user_key: &str, item_id: &str, image_type: &str) -> String {
+    hex::encode(image_mac(key, user_key, item_id, image_type).finalize().into_bytes())
+}
+
+/// Checks an image proxy signature in constant time.
+pub(crate) fn verify_image_signature(key: &Secret, user_key: &str, item_id: &str, image_type: &str, signature: &str) -> bool {
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    image_mac(key, user_key, item_id, image_type).verify_slice(&signature).is_ok()
+}
+
+/// Builds trickplay manifest urls keyed by item id, using the widest tiles of the primary media source.
+fn trickplay_urls(
+    jf_host: &str,
+    jf_token: &str,
+    primary_media_sources: &HashMap<String, String>,
+    items: &[jellyfin::TrickplayItem],
+) -> HashMap<String, String> {
+    items
+        .iter()
+        .filter_map(|item| {
+            let id = item.id.simple().to_string();
+            // The version that gets played, Jellyfin names the primary one after the item itself
+            let primary = primary_media_sources.get(&id).unwrap_or(&id);
+            let (media_source_id, resolutions) = item
+                .trickplay
+                .iter()
+                .find(|(media_source_id, _)| media_source_id.replace('-', "").eq_ignore_ascii_case(primary))?;
+            let info = resolutions.values().max_by_key(|info| info.width)?;
+            let url = format!(
+                "{}/Videos/{}/Trickplay/{}/tiles.m3u8?MediaSourceId={}&api_key={}",
+                jf_host, id, info.width, media_source_id, jf_token
+            );
+            Some((id, url))
+        })
+        .collect()
+}
+
+/// Id of the first media source of each item, keyed by item id.
+fn primary_media_sources(items: &[jellyfin::types::BaseItemDto]) -> HashMap<String, String> {
+    items
+        .iter()
+        .filter_map(|item| {
+            let source_id = item.media_sources.as_ref()?.first()?.id.as_ref()?;
+            Some((item.id?.simple().to_string(), source_id.replace('-', "").to_ascii_lowercase()))
+        })
+        .collect()
+}
+
+/// Why an item can't be played from HereSphere, if it can't.
+fn baseitem_skip_reason(config: &AppConfig, item: &jellyfin::types::BaseItemDto) -> Option<&'static str> {
+    if let Some(LocationType::Virtual) = item.location_type {
+        return Some("virtual");
+    }
+    if let Some(true) = item.is_place_holder {
+        return Some("placeholder");
+    }
+    if let Some(PlayAccess::None) = item.play_access {
+        return Some("no play access");
+    }
+    if let Some(min_rating) = config.min_rating {
+        match item.community_rating {
+            Some(rating) if (rating as f64) < min_rating => return Some("rated too low"),
+            None if !config.unrated_passes_min_rating => return Some("unrated"),
+            _ => {}
+        }
+    }
+    match &item.media_sources {
+        Some(sources) if config.skip_broken_media && !sources.is_empty() && sources.iter().all(media_source_is_broken) => {
+            Some("broken media sources")
+        }
+        Some(sources) if !sources.is_empty() => None,
+        _ => Some("no media sources"),
+    }
+}
+
+pub(crate) fn baseitem_to_video(
+    jf_host: &str,
+    jf_token: &str,
+    images: &ImageSource,
+    config: &AppConfig,
+    item: &jellyfin::types::BaseItemDto,
+) -> Option<heresphere::VideoData> {
+    if let Some(LocationType::Virtual) = item.location_type {
+        return None;
+    }
+    let id = item.id.expect("No id in BaseItemDto").simple().to_string();
+    let thumb = images.url(&id, baseitem_image_type(config, item));
+
+    let lens = baseitem_lens(config, item);
+    let stereo = baseitem_stereo(config, item);
+    let data = heresphere::VideoData {
+        access: Some(baseitem_access(config, item)),
+        title: match item.type_ {
+                Some(BaseItemKind::Episode) => render_episode_title(&config.episode_title_template, item),
+                _ => item.name.clone().unwrap_or_default(),
+            },
+        // Unknown durations stay at 0, which the progress routine treats as "never complete"
+        duration: jellyfin::ticks_to_ms(baseitem_run_time_ticks(item).unwrap_or_default()),
+        tags: baseitem_to_tags(config, item),
+        media: baseitem_to_media(config, jf_host, jf_token, item),
+        date_released: baseitem_date_to_string(item.premiere_date),
+        date_added: baseitem_date_to_string(item.date_created),
+        projection: baseitem_projection(config, lens),
+        stereo: stereo.clone(),
+        // IPD only means something when there are two eyes
+        camera_ipd: match stereo.as_str() {
+            "mono" => None,
+            _ => lens
+                .and_then(|preset| config.lens_camera_ipd.get(&preset.lens).copied())
+                .or(config.camera_ipd),
+        },
+        is_eye_swapped: baseitem_is_eye_swapped(config, item).then_some(true),
+        lens: lens.map(|preset| preset.lens.clone()),
+        fov: lens
+            .map(|preset| preset.fov)
+            .or_else(|| config.projection_fov.get(&baseitem_projection(config, lens)).copied()),
+        is_favorite: item.user_data
+            .as_ref()
+            .and_then(|user_data| Some(!user_data.played.unwrap_or_default())),
+        thumbnail_image: thumb,
+        description: match (config.technical_details.as_str(), baseitem_technical_details(item)) {
+            ("description", Some(details)) => Some(match &item.overview {
+                Some(overview) if !overview.is_empty() => format!("{}\n\n{}", overview, details.summary()),
+                _ => details.summary(),
+            }),
+            _ => item.overview.clone(),
+        },
+        // The user's own rating wins over the community one, both are 0-10 while HereSphere wants 0-5
+        rating: item
+            .user_data
+            .as_ref()
+            .and_then(|user_data| user_data.rating)
+            .or(item.community_rating.map(|rating| rating as f64))
+            .map(|rating| rating / 2.0),
+        // Jellyfin has no notion of favorite counts or comments,
+        // so the user's play count stands in for `favorites` and `comments` stays empty.
+        favorites: item.user_data.as_ref().and_then(|user_data| user_data.play_count),
+        comments: None,
+        event_server: None,
+        subtitles: baseitem_to_subtitles(config, item, jf_host, jf_token),
+        write_favorite: (config.write_played && !config.read_only).then_some(true),
+        write_tags: (config.write_tags && !config.read_only).then_some(true),
+        write_hsp: Some(true),
+        ..Default::default()
+    };
+    Some(data)
+}
+
+/// Image type used as the thumbnail, the configured one if the item has it and `Primary` otherwise.
+/// Without configuration movies use their backdrop, everything else the primary image.
+fn baseitem_image_type<'a>(config: &'a AppConfig, item: &jellyfin::types::BaseItemDto) -> &'a str {
+    let kind = item.type_.as_ref().map(|kind| kind.to_string()).unwrap_or_default();
+    let preferred = config
+        .image_types
+        .get(&kind)
+        .or(config.image_type.as_ref())
+        .map(String::as_str)
+        .unwrap_or(match item.type_ {
+            Some(BaseItemKind::Movie) => "Backdrop",
+            _ => "Primary",
+        });
+    let available = match preferred {
+        "Primary" => true,
+        "Backdrop" => item.backdrop_image_tags.as_ref().is_some_and(|tags| !tags.is_empty()),
+        image_type => item.image_tags.as_ref().is_some_and(|tags| tags.contains_key(image_type)),
+    };
+    if available {
+        preferred
+    } else {
+        "Primary"
+    }
+}
+
+/// Resolution, codecs and bitrate of the first media source, whatever of it Jellyfin knows.
+#[derive(Debug, Default, PartialEq)]
+struct TechnicalDetails {
+    resolution: Option<String>,
+    video_codec: Option<String>,
+    audio_codec: Option<String>,
+    /// Megabits per second.
+    bitrate: Option<f64>,
+}
+
+impl TechnicalDetails {
+    /// One line for the description, ex. `3840x1920 HEVC, AAC, 25.3 Mbps`.
+    fn summary(&self) -> String {
+        let video = [self.resolution.clone(), self.video_codec.clone()]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(" ");
+        [
+            Some(video).filter(|video| !video.is_empty()),
+            self.audio_codec.clone(),
+            self.bitrate.map(|bitrate| format!("{:.1} Mbps", bitrate)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(", ")
+    }
+
+    fn tags(&self) -> Vec<String> {
+        [
+            self.resolution.as_ref().map(|resolution| format!("Resolution:{}", resolution)),
+            self.video_codec.as_ref().map(|codec| format!("VideoCodec:{}", codec)),
+            self.audio_codec.as_ref().map(|codec| format!("AudioCodec:{}", codec)),
+            self.bitrate.map(|bitrate| format!("Bitrate:{:.0} Mbps", bitrate)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+fn baseitem_technical_details(item: &jellyfin::types::BaseItemDto) -> Option<TechnicalDetails> {
+    let source = item.media_sources.as_ref()?.first()?;
+    let streams = source.media_streams.as_deref().unwrap_or_default();
+    let video = streams
+        .iter()
+        .find(|stream| matches!(stream.type_, Some(jellyfin::types::MediaStreamType::Video)));
+    let audio = streams
+        .iter()
+        .find(|stream| matches!(stream.type_, Some(jellyfin::types::MediaStreamType::Audio)));
+    let details = TechnicalDetails {
+        resolution: video.and_then(|video| Some(format!("{}x{}", video.width?, video.height?))),
+        video_codec: video.and_then(|video| video.codec.as_ref()).map(|codec| codec.to_uppercase()),
+        audio_codec: audio.and_then(|audio| audio.codec.as_ref()).map(|codec| codec.to_uppercase()),
+        bitrate: source.bitrate.filter(|bitrate| *bitrate > 0).map(|bitrate| bitrate as f64 / 1_000_000.0),
+    };
+    (details != TechnicalDetails::default()).then_some(details)
+}
+
+/// How a projection reads in the tag browser, ex. `180` for `equirectangular`.
+fn projection_tag_name(projection: &str) -> &str {
+    match projection {
+        "equirectangular" => "180",
+        "equirectangular360" => "360",
+        "fisheye" => "Fisheye",
+        "perspective" => "Flat",
+        "cubemap" => "Cubemap",
+        "equiangularCubemap" => "EAC",
+        other => other,
+    }
+}
+
+fn baseitem_projection(config: &AppConfig, lens: Option<&heresphere::LensPreset>) -> String {
+    match lens {
+        Some(_) => "fisheye".to_string(),
+        None => config.default_projection.clone(),
+    }
+}
+
+/// Lens preset of the first marker found in the item's tags, file name or title.
+fn baseitem_lens<'a>(config: &'a AppConfig, item: &jellyfin::types::BaseItemDto) -> Option<&'a heresphere::LensPreset> {
+    let tags = item.tags.iter().flatten().map(|tag| tag.to_uppercase()).collect::<Vec<_>>();
+    let path = item.path.as_deref().unwrap_or_default().to_uppercase();
+    let name = item.name.as_deref().unwrap_or_default().to_uppercase();
+    config
+        .lens_presets
+        .iter()
+        .find(|(marker, _)| {
+            let marker = marker.to_uppercase();
+            tags.contains(&marker) || path.contains(&marker) || name.contains(&marker)
+        })
+        .map(|(_, preset)| preset)
+}
+
+/// Stereo layout of the first marker found among the item's tags and the words of its file name or title,
+/// `default_stereo` otherwise. Words are matched whole, so `TB` doesn't match inside `NTBC`.
+fn baseitem_stereo(config: &AppConfig, item: &jellyfin::types::BaseItemDto) -> String {
+    let mut words = item
+        .tags
+        .iter()
+        .flatten()
+        .map(String::as_str)
+        .chain(
+            [item.path.as_deref(), item.name.as_deref()]
+                .into_iter()
+                .flatten()
+                .flat_map(|text| text.split(|c: char| !c.is_alphanumeric())),
+        );
+    words
+        .find_map(|word| {
+            config
+                .stereo_markers
+                .iter()
+                .find(|(marker, _)| marker.eq_ignore_ascii_case(word))
+                .map(|(_, stereo)| stereo.clone())
+        })
+        .unwrap_or_else(|| config.default_stereo.clone())
+}
+
+/// Whether a tag or a word of the item's file name or title marks it as encoded with the eyes swapped.
+/// Words are matched whole, so a short marker like `RL` doesn't match inside `WORLD`.
+fn baseitem_is_eye_swapped(config: &AppConfig, item: &jellyfin::types::BaseItemDto) -> bool {
+    let is_marker = |word: &str| {
+        config
+            .eye_swap_markers
+            .iter()
+            .any(|marker| marker.eq_ignore_ascii_case(word))
+    };
+    let mut words = [item.path.as_deref(), item.name.as_deref()]
+        .into_iter()
+        .flatten()
+        .flat_map(|text| text.split(|c: char| !c.is_alphanumeric()));
+    item.tags.iter().flatten().any(|tag| is_marker(tag)) || words.any(is_marker)
+}
+
+/// HereSphere access level of an item, `0` (restricted) when its official rating or one of its tags is restricted,
+/// or its official rating is above `max_official_rating`.
+fn baseitem_access(config: &AppConfig, item: &jellyfin::types::BaseItemDto) -> i32 {
+    let is_restricted_rating = item.official_rating.as_deref().is_some_and(|rating| {
+        config
+            .restricted_ratings
+            .iter()
+            .any(|restricted| restricted.eq_ignore_ascii_case(rating))
+    });
+    let is_restricted_tag = item.tags.iter().flatten().any(|tag| {
+        config
+            .restricted_tags
+            .iter()
+            .any(|restricted| restricted.eq_ignore_ascii_case(tag))
+    });
+    let is_above_max_rating = config.max_official_rating.as_deref().is_some_and(|max_rating| {
+        let max_level = jellyfin::parental_rating_level(max_rating).expect("max_official_rating was validated");
+        match item.official_rating.as_deref().and_then(jellyfin::parental_rating_level) {
+            Some(level) => level > max_level,
+            None => config.restrict_unrated,
+        }
+    });
+    if is_restricted_rating || is_restricted_tag || is_above_max_rating {
+        0
+    } else {
+        1
+    }
+}
+
+/// Where a playback of the item picks up, a bit before Jellyfin's stored position so
+/// tick/ms rounding between HereSphere and Jellyfin doesn't skip past the last seen frames.
+pub(crate) fn resume_position(config: &AppConfig, item: &jellyfin::types::BaseItemDto) -> i64 {
+    let position = item
+        .user_data
+        .as_ref()
+        .and_then(|user_data| user_data.playback_position_ticks)
+        .unwrap_or_default();
+    let rewind = jellyfin::ms_to_ticks(config.resume_rewind.as_millis() as f64);
+    (position - rewind).max(0)
+}
+
+/// Run time of an item, falling back to its media source for items Jellyfin hasn't fully analyzed yet.
+fn baseitem_run_time_ticks(item: &jellyfin::types::BaseItemDto) -> Option<i64> {
+    item.run_time_ticks.filter(|ticks| *ticks > 0).or_else(|| {
+        item.media_sources
+            .iter()
+            .flatten()
+            .filter_map(|source| source.run_time_ticks)
+            .find(|ticks| *ticks > 0)
+    })
+}
+
+fn baseitem_to_tags(config: &AppConfig, item: &jellyfin::types::BaseItemDto) -> Vec<heresphere::Tag> {
+    let mut tags = vec![];
+    if let Some(chapters) = &item.chapters {
+        let mut previous_tag: Option<usize> = None;
+        for chapter in chapters {
+            let start = jellyfin::ticks_to_ms(chapter.start_position_ticks.unwrap_or_default());
+            // Every chapter ends where the next one starts, even if the next one isn't tagged
+            if let Some(previous_tag) = previous_tag.take() {
+                tags[previous_tag].end = Some(start);
+            }
+            let Some(name) = chapter_tag_name(config, chapter.name.as_deref()) else {
+                continue;
+            };
+            tags.push(heresphere::Tag {
+                name,
+                start: Some(start),
+                end: Some(jellyfin::ticks_to_ms(baseitem_run_time_ticks(item).unwrap_or_default())),
+                ..Default::default()
+            });
+            previous_tag = Some(tags.len() - 1);
+        }
+    }
+
+    if let Some(genres) = &item.genres {
+        for genre in genres {
+            tags.push(heresphere::Tag {
+                name: format!("Genre:{}", genre),
+                ..Default::default()
+            });
+        }
+    }
+    if let Some(tags_) = &item.tags {
+        for tag in tags_ {
+            tags.push(heresphere::Tag {
                 name: format!("Tag:{}", tag),
                 ..Default::default()
             });
@@ -311,8 +1691,8 @@ fn baseitem_to_tags(item: &jellyfin::types::BaseItemDto) -> Vec<heresphere::Tag>
             ..Default::default()
         });
     }
-    match item.type_.unwrap() {
-        BaseItemKind::Movie => {
+    match item.type_ {
+        Some(BaseItemKind::Movie) => {
             if let Some(name) = &item.name {
                 tags.push(heresphere::Tag {
                     name: format!("Movie:{}", name),
@@ -331,7 +1711,7 @@ fn baseitem_to_tags(item: &jellyfin::types::BaseItemDto) -> Vec<heresphere::Tag>
                 }
             }
         }
-        BaseItemKind::Episode => {
+        Some(BaseItemKind::Episode) => {
             if let Some(name) = &item.series_name {
                 tags.push(heresphere::Tag {
                     name: format!("Series:{}", name),
@@ -352,9 +1732,32 @@ fn baseitem_to_tags(item: &jellyfin::types::BaseItemDto) -> Vec<heresphere::Tag>
                 });
             }
         }
+        Some(BaseItemKind::MusicVideo) => {
+            if let Some(artists) = &item.artists {
+                for artist in artists {
+                    tags.push(heresphere::Tag {
+                        name: format!("Artist:{}", artist),
+                        ..Default::default()
+                    });
+                }
+            }
+            if let Some(album) = &item.album {
+                tags.push(heresphere::Tag {
+                    name: format!("Album:{}", album),
+                    ..Default::default()
+                });
+            }
+        }
         _ => {}
     }
 
+    if let Some(rating) = &item.official_rating {
+        tags.push(heresphere::Tag {
+            name: format!("OfficialRating:{}", rating),
+            ..Default::default()
+        });
+    }
+
     if let Some(season) = &item.season_name {
         tags.push(heresphere::Tag {
             name: format!("Season:{}", season),
@@ -363,7 +1766,7 @@ fn baseitem_to_tags(item: &jellyfin::types::BaseItemDto) -> Vec<heresphere::Tag>
     }
 
     if let Some(people) = &item.people {
-        for person in people {
+        for person in people.iter().take(config.max_people.unwrap_or(usize::MAX)) {
             if let Some(name) = &person.name {
                 match person.type_.as_deref() {
                     Some(type_) => {
@@ -388,25 +1791,142 @@ fn baseitem_to_tags(item: &jellyfin::types::BaseItemDto) -> Vec<heresphere::Tag>
             }
         }
     }
-    tags
+
+    if let ("tags", Some(details)) = (config.technical_details.as_str(), baseitem_technical_details(item)) {
+        tags.extend(details.tags().into_iter().map(|name| heresphere::Tag {
+            name,
+            ..Default::default()
+        }));
+    }
+
+    if config.format_tags {
+        let projection = baseitem_projection(config, baseitem_lens(config, item));
+        let stereo = baseitem_stereo(config, item);
+        tags.push(heresphere::Tag {
+            name: format!("Projection:{}", projection_tag_name(&projection)),
+            ..Default::default()
+        });
+        tags.push(heresphere::Tag {
+            name: format!("Stereo:{}", stereo.to_uppercase()),
+            ..Default::default()
+        });
+    }
+
+    if !config.tag_blocklist.is_empty() {
+        tags.retain(|tag| !config.tag_blocklist.iter().any(|blocked| blocked == tag_category(tag)));
+    }
+    for tag in &mut tags {
+        tag.name = sanitize_tag_name(config, &tag.name);
+        if let Some(track) = config.tag_tracks.get(tag_category(tag)) {
+            tag.track = Some(*track);
+        }
+    }
+    tags
+}
+
+/// Tags a user can edit from the headset: Jellyfin tags (`Tag:`) and uncategorized ones typed in HereSphere.
+/// Everything else (genres, people, chapters, ...) is generated by JellyVR and never written back.
+pub(crate) fn user_tags(colon_replacement: &str, tags: &[heresphere::Tag]) -> Vec<String> {
+    let mut user_tags: Vec<String> = vec![];
+    for tag in tags {
+        let name = match tag.name.split_once(':') {
+            Some(("Tag", name)) => name,
+            None if tag.start.is_none() => tag.name.as_str(),
+            _ => continue,
+        };
+        // Undo `sanitize_tag_name`, Jellyfin gets the tag as it was
+        let name = match colon_replacement {
+            "" => name.trim().to_string(),
+            replacement => name.trim().replace(replacement, ":"),
+        };
+        if !name.is_empty() && !user_tags.contains(&name) {
+            user_tags.push(name);
+        }
+    }
+    user_tags
+}
+
+/// Category of a tag, the part before the first `:`.
+fn tag_category(tag: &heresphere::Tag) -> &str {
+    tag.name.split_once(':').map_or("", |(category, _)| category)
+}
+
+/// HereSphere splits categories on `:`, so colons in the value of a `Category:value` tag are
+/// swapped for `tag_colon_replacement`, keeping ex. a studio named "Ratio: 16:9" in one piece.
+fn sanitize_tag_name(config: &AppConfig, name: &str) -> String {
+    match name.split_once(':') {
+        Some((category, value)) if value.contains(':') => {
+            format!("{}:{}", category, value.replace(':', &config.tag_colon_replacement))
+        }
+        _ => name.to_string(),
+    }
+}
+
+/// Names a chapter tag, intro and credits markers get their own `Skip` category
+/// and generic auto-generated chapters ("Chapter 3") are dropped if configured.
+/// Markers only count as whole words, "Introduction" or "Outro Beach" are regular chapters.
+fn chapter_tag_name(config: &AppConfig, name: Option<&str>) -> Option<String> {
+    let name = name.unwrap_or("Unknown");
+    let lowercase = name.to_lowercase();
+    let has_word = |markers: &[&str]| {
+        lowercase
+            .split(|c: char| !c.is_alphanumeric())
+            .any(|word| markers.contains(&word))
+    };
+    if has_word(&["intro"]) {
+        return Some("Skip:Intro".to_string());
+    }
+    if has_word(&["credits", "outro"]) {
+        return Some("Skip:Credits".to_string());
+    }
+    if config.skip_generic_chapters {
+        let number = lowercase.strip_prefix("chapter").unwrap_or(&lowercase).trim();
+        if !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+    }
+    Some(format!("Chapter:{}", name))
 }
 
 fn baseitem_to_media(
+    config: &AppConfig,
     jf_host: &str,
     jf_token: &str,
     item: &jellyfin::types::BaseItemDto,
 ) -> Vec<heresphere::Media> {
     let mut media = vec![];
     if let Some(files) = &item.media_sources {
-        for file in files {
+        let files: Vec<_> = files
+            .iter()
+            .filter(|file| !config.skip_broken_media || !media_source_is_broken(file))
+            .collect();
+        if files.len() < item.media_sources.as_ref().map_or(0, Vec::len) {
+            tracing::debug!(
+                item = ?item.id,
+                skipped = item.media_sources.as_ref().map_or(0, Vec::len) - files.len(),
+                "Skipped broken media sources"
+            );
+        }
+        // HereSphere lists media as alternate versions, so parts of a stacked movie need to say which part they are
+        let is_stacked = config.label_parts
+            && files.len() > 1
+            && (item.part_count.unwrap_or_default() > 1
+                || files.iter().all(|file| file.name.as_deref().and_then(stack_part_number).is_some()));
+        for (index, file) in files.iter().enumerate() {
             let url = format!(
                 "{}/Items/{}/Download?api_key={}",
                 jf_host,
                 &file.id.as_ref().expect("No id in MediaSourceInfo"),
                 jf_token
             );
+            let container = file.container.clone().unwrap_or("some mp4".to_string());
             media.push(heresphere::Media {
-                name: file.container.clone().unwrap_or("some mp4".to_string()),
+                name: if is_stacked {
+                    let part = file.name.as_deref().and_then(stack_part_number).unwrap_or(index as u32 + 1);
+                    format!("Part {} ({})", part, container)
+                } else {
+                    container
+                },
                 sources: vec![heresphere::MediaSource {
                     url,
                     ..Default::default()
@@ -417,11 +1937,38 @@ fn baseitem_to_media(
     media
 }
 
+/// Whether Jellyfin knows a media source to be empty, ex. after a failed import. Sources Jellyfin
+/// hasn't probed yet have no size, run time or streams at all, those are given the benefit of the doubt.
+fn media_source_is_broken(source: &jellyfin::types::MediaSourceInfo) -> bool {
+    let has_no_video = source.media_streams.as_ref().is_some_and(|streams| {
+        !streams
+            .iter()
+            .any(|stream| matches!(stream.type_, Some(jellyfin::types::MediaStreamType::Video)))
+    });
+    source.size == Some(0) || source.run_time_ticks == Some(0) || has_no_video
+}
+
+/// Part number of a stacked file, going by the markers Jellyfin stacks on, ex. `Movie - part2` or `Movie cd1`.
+fn stack_part_number(name: &str) -> Option<u32> {
+    let name = name.to_lowercase();
+    ["part", "pt", "cd", "disc", "disk", "dvd"].iter().find_map(|marker| {
+        name.match_indices(marker).find_map(|(start, _)| {
+            // The marker has to start a word, so `Counterpart 2` doesn't count
+            if name[..start].chars().last().is_some_and(char::is_alphanumeric) {
+                return None;
+            }
+            let rest = name[start + marker.len()..].trim_start_matches(|c: char| c == ' ' || c == '.' || c == '_' || c == '-');
+            let digits: String = rest.chars().take_while(char::is_ascii_digit).collect();
+            digits.parse().ok()
+        })
+    })
+}
+
 fn baseitem_to_subtitles(
+    config: &AppConfig,
     item: &jellyfin::types::BaseItemDto,
     jf_host: &str,
     jf_token: &str,
-    prefered_subtitles_language: Option<&str>,
 ) -> Option<Vec<heresphere::Subtitle>> {
     let mut subtitles = vec![];
     if let Some(media_sources) = &item.media_sources {
@@ -436,27 +1983,34 @@ fn baseitem_to_subtitles(
                                 }
                             }
 
+                            let is_forced = stream.is_forced.unwrap_or_default();
+                            let is_sdh = subtitle_is_sdh(stream);
+                            if (is_forced && !config.forced_subtitles) || (is_sdh && !config.sdh_subtitles) {
+                                continue;
+                            }
                             let language = stream.language.clone().unwrap_or_default();
-                            if let Some(prefered_subtitles_language) = prefered_subtitles_language {
-                                if language != prefered_subtitles_language {
-                                    continue;
+                            // External subtitle files come with their own url, which the pattern below doesn't always match
+                            let url = match (&stream.delivery_url, stream.is_external_url) {
+                                (Some(delivery_url), Some(true)) => delivery_url.clone(),
+                                (Some(delivery_url), _) => format!("{}{}", jf_host, delivery_url),
+                                // {host}/Videos/{routeItemId}/{routeMediaSourceId}/Subtitles/{routeIndex}/Stream.{routeFormat}?api_key={routeApiKey}
+                                (None, _) => format!(
+                                    "{}/Videos/{}/{}/Subtitles/{}/Stream.{}?api_key={}",
+                                    jf_host,
+                                    item.id.expect("No id in BaseItemDto").simple().to_string(),
+                                    media_source.id.as_ref().expect("No id in MediaSourceInfo"),
+                                    stream.index.unwrap_or_default(),
+                                    map_sub_codec_to_ext(&stream.codec.as_deref().unwrap_or_default()),
+                                    jf_token
+                                ),
+                            };
+                            let mut name = stream.display_title.clone().unwrap_or(language.clone());
+                            for (flagged, label) in [(is_forced, "Forced"), (is_sdh, "SDH")] {
+                                if flagged && !name.to_lowercase().contains(&label.to_lowercase()) {
+                                    name = format!("{} ({})", name, label);
                                 }
                             }
-                            // {host}/Videos/{routeItemId}/{routeMediaSourceId}/Subtitles/{routeIndex}/Stream.{routeFormat}?api_key={routeApiKey}
-                            let url = format!(
-                                "{}/Videos/{}/{}/Subtitles/{}/Stream.{}?api_key={}",
-                                jf_host,
-                                item.id.expect("No id in BaseItemDto").simple().to_string(),
-                                media_source.id.as_ref().expect("No id in MediaSourceInfo"),
-                                stream.index.unwrap_or_default(),
-                                map_sub_codec_to_ext(&stream.codec.as_deref().unwrap_or_default()),
-                                jf_token
-                            );
-                            subtitles.push(heresphere::Subtitle {
-                                language: language.clone(),
-                                name: stream.display_title.clone().unwrap_or(language),
-                                url,
-                            });
+                            subtitles.push(heresphere::Subtitle { language, name, url });
                         }
                         _ => {}
                     }
@@ -464,6 +2018,9 @@ fn baseitem_to_subtitles(
             }
         }
     }
+    if let Some(prefered_subtitles_language) = &config.prefered_subtitles_language {
+        sort_subtitles(&mut subtitles, &[prefered_subtitles_language.clone()]);
+    }
     if subtitles.is_empty() {
         None
     } else {
@@ -471,8 +2028,136 @@ fn baseitem_to_subtitles(
     }
 }
 
+/// Whether a subtitle stream is for the deaf and hard of hearing. Jellyfin 10.8 has no flag
+/// for it, so this goes by the usual markers in the stream title.
+fn subtitle_is_sdh(stream: &jellyfin::types::MediaStream) -> bool {
+    [stream.title.as_deref(), stream.display_title.as_deref()]
+        .into_iter()
+        .flatten()
+        .any(|title| {
+            let title = title.to_lowercase();
+            title.contains("hearing impaired")
+                || title
+                    .split(|c: char| !c.is_alphanumeric())
+                    .any(|word| word == "sdh" || word == "cc")
+        })
+}
+
+/// ISO 639-1 codes (what `Accept-Language` uses) of common languages, with the
+/// ISO 639-2 codes Jellyfin tags streams with.
+static LANGUAGE_CODES: &[(&str, &[&str])] = &[
+    ("ar", &["ara"]),
+    ("cs", &["cze", "ces"]),
+    ("da", &["dan"]),
+    ("de", &["ger", "deu"]),
+    ("en", &["eng"]),
+    ("es", &["spa"]),
+    ("fi", &["fin"]),
+    ("fr", &["fre", "fra"]),
+    ("he", &["heb"]),
+    ("hi", &["hin"]),
+    ("it", &["ita"]),
+    ("ja", &["jpn"]),
+    ("ko", &["kor"]),
+    ("nl", &["dut", "nld"]),
+    ("no", &["nor", "nob", "nno"]),
+    ("pl", &["pol"]),
+    ("pt", &["por"]),
+    ("ru", &["rus"]),
+    ("sv", &["swe"]),
+    ("tr", &["tur"]),
+    ("uk", &["ukr"]),
+    ("zh", &["chi", "zho"]),
+];
+
+/// Languages of an `Accept-Language` header, most preferred first, without regions (`en-US` is `en`).
+pub(crate) fn accept_languages(header: &str) -> Vec<String> {
+    let mut languages: Vec<(String, f64)> = header
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';');
+            let language = parts.next()?.trim().split('-').next()?.to_lowercase();
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .and_then(|q| q.parse().ok())
+                .unwrap_or(1.0);
+            (!language.is_empty() && language != "*" && quality > 0.0).then_some((language, quality))
+        })
+        .collect();
+    // Stable, so equally weighted languages keep the order they were sent in
+    languages.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    let mut ordered: Vec<String> = vec![];
+    for (language, _) in languages {
+        if !ordered.contains(&language) {
+            ordered.push(language);
+        }
+    }
+    ordered
+}
+
+/// Moves subtitles in the given languages to the front, in that order, keeping the rest as they were.
+/// Languages can be ISO 639-1 (`en`) or ISO 639-2 (`eng`) codes.
+pub(crate) fn sort_subtitles(subtitles: &mut [heresphere::Subtitle], languages: &[String]) {
+    let matches = |wanted: &str, language: &str| {
+        wanted.eq_ignore_ascii_case(language)
+            || LANGUAGE_CODES.iter().any(|(short, long)| {
+                short.eq_ignore_ascii_case(wanted) && long.iter().any(|long| long.eq_ignore_ascii_case(language))
+            })
+    };
+    subtitles.sort_by_key(|subtitle| {
+        languages
+            .iter()
+            .position(|wanted| matches(wanted, &subtitle.language))
+            .unwrap_or(languages.len())
+    });
+}
+
+/// Placeholders an episode title template may use.
+pub(crate) static EPISODE_TITLE_PLACEHOLDERS: &[&str] = &["series", "season", "episode", "title"];
+
+pub(crate) const DEFAULT_EPISODE_TITLE_TEMPLATE: &str = "S{season}E{episode} - {title}";
+
+/// Checks every `{...}` of an episode title template is closed and one of [`EPISODE_TITLE_PLACEHOLDERS`].
+pub(crate) fn is_valid_episode_title_template(template: &str) -> bool {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            return false;
+        };
+        if !EPISODE_TITLE_PLACEHOLDERS.contains(&&rest[start + 1..start + end]) {
+            return false;
+        }
+        rest = &rest[start + end + 1..];
+    }
+    !rest.contains('}')
+}
+
+/// Fills in a template checked by [`is_valid_episode_title_template`], season and episode are padded to two digits.
+fn render_episode_title(template: &str, item: &jellyfin::types::BaseItemDto) -> String {
+    let mut title = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        title.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        match &rest[start + 1..start + end] {
+            "series" => title.push_str(item.series_name.as_deref().unwrap_or_default()),
+            "season" => title.push_str(&format!("{:02}", item.parent_index_number.unwrap_or_default())),
+            "episode" => title.push_str(&format!("{:02}", item.index_number.unwrap_or_default())),
+            "title" => title.push_str(item.name.as_deref().unwrap_or_default()),
+            _ => {}
+        }
+        rest = &rest[start + end + 1..];
+    }
+    title.push_str(rest);
+    title
+}
+
+/// Formats a Jellyfin date for HereSphere, missing dates become an empty string so they're left out.
 pub(crate) fn baseitem_date_to_string(date: Option<chrono::DateTime<chrono::Utc>>) -> String {
-    date.unwrap_or_default().format("%Y-%m-%d").to_string()
+    date.map(|date| date.format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
 }
 
 fn map_sub_codec_to_ext(codec: &str) -> &str {
@@ -483,3 +2168,1145 @@ fn map_sub_codec_to_ext(codec: &str) -> &str {
         x => x,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> Secret {
+        Secret("image-key".to_string())
+    }
+
+    fn item(json: serde_json::Value) -> jellyfin::types::BaseItemDto {
+        serde_json::from_value(json).expect("Invalid BaseItemDto")
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn config() -> AppConfig {
+        crate::tests::test_config("https://jf.example")
+    }
+
+    /// HereSphere video of the item described by `json`, which gets an id if it has none.
+    fn video(config: &AppConfig, mut json: serde_json::Value) -> heresphere::VideoData {
+        if json.get("Id").is_none() {
+            json["Id"] = serde_json::json!(uuid::Uuid::from_u128(1));
+        }
+        let images = ImageSource::direct("https://jf.example", "token");
+        baseitem_to_video("https://jf.example", "token", &images, config, &item(json)).expect("Not a video")
+    }
+
+    #[test]
+    fn include_only_selects_listed_libraries() {
+        let include = names(&["Movies"]);
+        assert!(is_library_selected(&include, &[], "movies"));
+        assert!(!is_library_selected(&include, &[], "Shows"));
+    }
+
+    #[test]
+    fn exclude_only_selects_unlisted_libraries() {
+        let exclude = names(&["Shows"]);
+        assert!(is_library_selected(&[], &exclude, "Movies"));
+        assert!(is_library_selected(&[], &exclude, "Collections"));
+        assert!(!is_library_selected(&[], &exclude, "SHOWS"));
+    }
+
+    #[test]
+    fn exclude_wins_over_include() {
+        let include = names(&["Movies", "Shows"]);
+        let exclude = names(&["Shows"]);
+        assert!(is_library_selected(&include, &exclude, "Movies"));
+        assert!(!is_library_selected(&include, &exclude, "Shows"));
+        assert!(!is_library_selected(&include, &exclude, "Collections"));
+    }
+
+    #[test]
+    fn items_from_overlapping_libraries_are_kept_once() {
+        let movie = uuid::Uuid::new_v4();
+        let other = uuid::Uuid::new_v4();
+        let with_id = |id: uuid::Uuid| item(serde_json::json!({ "Id": id }));
+        let items = dedup_items(vec![with_id(movie), with_id(other), with_id(movie)]);
+        assert_eq!(items.iter().map(|item| item.id).collect::<Vec<_>>(), vec![Some(movie), Some(other)]);
+    }
+
+    async fn test_db() -> eyre::Result<(Surreal<Db>, std::path::PathBuf)> {
+        let path = std::env::temp_dir().join(format!("jellyvr-index-{}", uuid::Uuid::new_v4().simple()));
+        let db = Surreal::new::<surrealdb::engine::local::RocksDb>(path.to_str().expect("Temp dir isn't UTF-8")).await?;
+        db.use_ns("jellyvr").use_db("jellyvr").await?;
+        Ok((db, path))
+    }
+
+    fn cached_video(user_key: &str, vid: &str) -> VideoCache {
+        VideoCache {
+            id: surrealdb::sql::Thing::from(("videos", surrealdb::sql::Id::from(vec![user_key, vid]))),
+            data: heresphere::VideoData {
+                title: vid.to_string(),
+                ..Default::default()
+            },
+            last_updated: chrono::Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn users_only_replace_their_own_videos() -> eyre::Result<()> {
+        let (db, path) = test_db().await?;
+        // "default:alice2" sorts right after "default:alice", an open ended range would catch it
+        replace_user_videos(&db, "default:alice", vec![cached_video("default:alice", "a1"), cached_video("default:alice", "a2")])
+            .await?;
+        replace_user_videos(&db, "default:alice2", vec![cached_video("default:alice2", "b1")]).await?;
+        replace_user_videos(&db, "default:alice", vec![cached_video("default:alice", "a3")]).await?;
+
+        let ids = names(&["a1", "a2", "a3", "b1"]);
+        let links = |scan: Vec<heresphere::ScanData>| scan.into_iter().map(|data| data.link).collect::<Vec<_>>();
+        let alice = scan_chunk(db.clone(), "default:alice".to_string(), ids.clone(), "h".to_string()).await?;
+        assert_eq!(links(alice), vec!["h/heresphere/a3".to_string()]);
+        let alice2 = scan_chunk(db.clone(), "default:alice2".to_string(), ids, "h".to_string()).await?;
+        assert_eq!(links(alice2), vec!["h/heresphere/b1".to_string()]);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn warming_rebuilds_the_index_of_seeded_sessions() -> eyre::Result<()> {
+        use crate::tests::{create_user_session, library_routes, mock_jellyfin, test_app, test_config, VIDEO_ID};
+        let (db, path) = test_db().await?;
+        let (url, _) = mock_jellyfin(library_routes(serde_json::json!([
+            { "Id": VIDEO_ID, "Name": "Video", "MediaSources": [{}] },
+        ])))
+        .await?;
+        let app = test_app(db.clone(), test_config(&url));
+        let Session::User(user) = create_user_session(&db, "alice").await?.session else {
+            unreachable!("Not a user session");
+        };
+        // Only users that loaded their library before get warmed, the host comes from there
+        let _: Option<HeresphereIndex> = db
+            .create(("index", user.cache_key().as_str()))
+            .content(HeresphereIndex {
+                id: None,
+                libraries: vec![],
+                videos: vec![],
+                last_updated: chrono::Utc::now() - chrono::Duration::days(1),
+                host: Some("https://vr.example".to_string()),
+            })
+            .await?;
+
+        let summary = HeresphereIndex::reindex_all(&app, 1).await?;
+        assert_eq!(summary.reindexed, vec!["alice".to_string()]);
+        let index: Option<HeresphereIndex> = db.select(("index", user.cache_key().as_str())).await?;
+        let index = index.expect("No index after warming");
+        assert_eq!(index.videos, vec![VIDEO_ID.to_string()]);
+        assert!(index.last_updated > chrono::Utc::now() - chrono::Duration::minutes(1));
+        let video = HeresphereIndex::get_video(&db, &user.cache_key(), VIDEO_ID).await.map_err(|e| e.0)?;
+        assert_eq!(video.data.title, "Video");
+        drop(app);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn date_added_sort_preference_orders_the_scan() -> eyre::Result<()> {
+        use crate::tests::{create_user_session, mock_jellyfin, test_app, test_config};
+        let (db, path) = test_db().await?;
+        let ids: Vec<String> = (0..3).map(|n| uuid::Uuid::from_u128(n + 1).simple().to_string()).collect();
+        let items: Vec<serde_json::Value> = ids
+            .iter()
+            .map(|id| serde_json::json!({ "Id": id, "MediaSources": [{}] }))
+            .collect();
+        // Jellyfin sorts, items are added in id order here
+        let routes = axum::Router::new()
+            .route(
+                "/DisplayPreferences/usersettings",
+                axum::routing::get(|| async {
+                    axum::Json(serde_json::json!({ "SortBy": "DateCreated", "SortOrder": "Descending" }))
+                }),
+            )
+            .route(
+                "/Users/:user/Items",
+                axum::routing::get(move |axum::extract::Query(query): axum::extract::Query<HashMap<String, String>>| async move {
+                    let mut items = items.clone();
+                    if query.get("SortBy").map(String::as_str) == Some("DateCreated")
+                        && query.get("SortOrder").map(String::as_str) == Some("Descending")
+                    {
+                        items.reverse();
+                    }
+                    axum::Json(serde_json::json!({ "Items": items, "TotalRecordCount": 3 }))
+                }),
+            );
+        let (url, _) = mock_jellyfin(routes).await?;
+        let app = test_app(db.clone(), test_config(&url));
+        let Session::User(user) = create_user_session(&db, "alice").await?.session else {
+            unreachable!("Not a user session");
+        };
+
+        let index = HeresphereIndex::prime_data(&app, "https://vr.example", &user).await.map_err(|e| e.0)?;
+        assert_eq!(index.videos, ids.iter().rev().cloned().collect::<Vec<_>>());
+        drop(app);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn each_server_gets_its_own_libraries() -> eyre::Result<()> {
+        use crate::tests::{create_user_session, library_routes, mock_jellyfin, test_app, test_config};
+        let (db, path) = test_db().await?;
+        let first = uuid::Uuid::from_u128(1);
+        let second = uuid::Uuid::from_u128(2);
+        let (first_url, _) = mock_jellyfin(library_routes(serde_json::json!([{ "Id": first, "MediaSources": [{}] }]))).await?;
+        let (second_url, _) = mock_jellyfin(library_routes(serde_json::json!([{ "Id": second, "MediaSources": [{}] }]))).await?;
+        let mut config = test_config(&first_url);
+        config.jellyfin_servers.push(crate::JellyfinServerConfig {
+            name: "second".to_string(),
+            api_host: second_url.clone(),
+            remote_host: second_url,
+        });
+        let app = test_app(db.clone(), config);
+        let Session::User(user) = create_user_session(&db, "alice").await?.session else {
+            unreachable!("Not a user session");
+        };
+        let other = User { server: "second".to_string(), ..user.clone() };
+
+        let host = "https://vr.example";
+        let first_index = HeresphereIndex::prime_data(&app, host, &user).await.map_err(|e| e.0)?;
+        let second_index = HeresphereIndex::prime_data(&app, host, &other).await.map_err(|e| e.0)?;
+        let everything = |index: &HeresphereIndex, name: &str| library(&index.libraries, name).map(<[String]>::to_vec);
+        assert_eq!(everything(&first_index, "default: Everything"), Some(vec![link(first)]));
+        assert_eq!(everything(&second_index, "second: Everything"), Some(vec![link(second)]));
+        // Same user id on both servers, still two separate indexes
+        assert_ne!(user.cache_key(), other.cache_key());
+        drop(app);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn next_up_comes_from_jellyfin() -> eyre::Result<()> {
+        use crate::tests::{create_user_session, library_routes, mock_jellyfin, test_app, test_config};
+        let (db, path) = test_db().await?;
+        let ids: Vec<uuid::Uuid> = (0..3).map(|n| uuid::Uuid::from_u128(n + 1)).collect();
+        let episodes: Vec<serde_json::Value> = ids
+            .iter()
+            .zip(1..)
+            .map(|(id, number)| serde_json::json!({
+                "Id": id, "Type": "Episode", "SeriesName": "Show", "ParentIndexNumber": 1, "IndexNumber": number, "MediaSources": [{}],
+            }))
+            .collect();
+        // The last one isn't in the library, ex. it's of an excluded one
+        let next_up = serde_json::json!([{ "Id": ids[1] }, { "Id": uuid::Uuid::from_u128(9) }]);
+        let routes = library_routes(serde_json::Value::Array(episodes)).route(
+            "/Shows/NextUp",
+            axum::routing::get(move || async move { axum::Json(serde_json::json!({ "Items": next_up, "TotalRecordCount": 2 })) }),
+        );
+        let (url, calls) = mock_jellyfin(routes).await?;
+        let app = test_app(db.clone(), test_config(&url));
+        let Session::User(user) = create_user_session(&db, "alice").await?.session else {
+            unreachable!("Not a user session");
+        };
+
+        let index = HeresphereIndex::prime_data(&app, "https://vr.example", &user).await.map_err(|e| e.0)?;
+        assert_eq!(crate::tests::calls_to(&calls, "GET /Shows/NextUp"), 1);
+        assert_eq!(library(&index.libraries, "Next Up"), Some(&[link(ids[1])][..]));
+        drop(app);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn live_tv_channels_get_a_library() -> eyre::Result<()> {
+        use crate::tests::{create_user_session, library_routes, mock_jellyfin, test_app, test_config};
+        let (db, path) = test_db().await?;
+        let channel = uuid::Uuid::from_u128(1);
+        let channels = serde_json::json!({
+            "Items": [{ "Id": channel, "Name": "News", "ChannelNumber": "7", "Type": "TvChannel" }],
+            "TotalRecordCount": 1,
+        });
+        let routes = library_routes(serde_json::json!([]))
+            .route("/LiveTv/Channels", axum::routing::get(move || async move { axum::Json(channels) }));
+        let (url, _) = mock_jellyfin(routes).await?;
+        let app = test_app(db.clone(), AppConfig { live_tv: true, ..test_config(&url) });
+        let Session::User(user) = create_user_session(&db, "alice").await?.session else {
+            unreachable!("Not a user session");
+        };
+
+        let index = HeresphereIndex::prime_data(&app, "https://vr.example", &user).await.map_err(|e| e.0)?;
+        assert_eq!(library(&index.libraries, "Live TV"), Some(&[link(channel)][..]));
+        let video = HeresphereIndex::get_video(&db, &user.cache_key(), &channel.simple().to_string()).await.map_err(|e| e.0)?;
+        assert_eq!(video.data.title, "7 News");
+        assert_eq!(
+            video.data.media[0].sources[0].url,
+            format!("{}/Videos/{}/master.m3u8?api_key=token", url, channel.simple())
+        );
+        drop(app);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn configured_item_fields_reach_the_query() -> eyre::Result<()> {
+        use crate::tests::{create_user_session, mock_jellyfin, test_app, test_config};
+        let (db, path) = test_db().await?;
+        let fields: std::sync::Arc<std::sync::Mutex<Vec<String>>> = Default::default();
+        let requested = fields.clone();
+        let routes = axum::Router::new().route(
+            "/Users/:user/Items",
+            axum::routing::get(move |axum::extract::Query(query): axum::extract::Query<HashMap<String, String>>| {
+                let requested = requested.clone();
+                async move {
+                    requested.lock().expect("Fields poisoned").extend(query.get("Fields").cloned());
+                    // Only what was asked for, no people, studios or chapters
+                    axum::Json(serde_json::json!({
+                        "Items": [{ "Id": uuid::Uuid::from_u128(1), "Name": "Video", "Genres": ["Drama"], "MediaSources": [{ "Id": "a" }] }],
+                        "TotalRecordCount": 1,
+                    }))
+                }
+            }),
+        );
+        let (url, _) = mock_jellyfin(routes).await?;
+        let config = AppConfig { item_fields: names(&["Genres"]), ..test_config(&url) };
+        let app = test_app(db.clone(), config);
+        let Session::User(user) = create_user_session(&db, "alice").await?.session else {
+            unreachable!("Not a user session");
+        };
+
+        let index = HeresphereIndex::prime_data(&app, "https://vr.example", &user).await.map_err(|e| e.0)?;
+        // Media sources are always needed to play anything
+        assert_eq!(*fields.lock().expect("Fields poisoned"), names(&["Genres,MediaSources"]));
+        let video = HeresphereIndex::get_video(&db, &user.cache_key(), &index.videos[0]).await.map_err(|e| e.0)?;
+        let tags: Vec<_> = video.data.tags.iter().map(|tag| tag.name.as_str()).collect();
+        assert!(tags.contains(&"Genre:Drama"));
+        assert!(!tags.iter().any(|tag| tag.starts_with("Actor:") || tag.starts_with("Chapter:")));
+        drop(app);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn trashed_videos_are_hidden_until_restored() -> eyre::Result<()> {
+        let (db, path) = test_db().await?;
+        TrashedVideo::trash(&db, "alice", "video", "Title").await.map_err(|e| e.0)?;
+        assert_eq!(TrashedVideo::ids_for_user(&db, "alice").await.map_err(|e| e.0)?, vec!["video".to_string()]);
+        // Trash is per user
+        assert!(TrashedVideo::ids_for_user(&db, "bob").await.map_err(|e| e.0)?.is_empty());
+        let trashed = TrashedVideo::all(&db).await.map_err(|e| e.0)?;
+        assert_eq!(trashed.len(), 1);
+        assert_eq!(trashed[0].title, "Title");
+
+        assert!(TrashedVideo::restore(&db, "alice", "video").await.map_err(|e| e.0)?);
+        assert!(TrashedVideo::ids_for_user(&db, "alice").await.map_err(|e| e.0)?.is_empty());
+        // Nothing left to restore
+        assert!(!TrashedVideo::restore(&db, "alice", "video").await.map_err(|e| e.0)?);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[test]
+    fn present_dates_are_formatted() {
+        let date = chrono::DateTime::parse_from_rfc3339("2021-03-04T05:06:07Z")
+            .expect("Valid date")
+            .with_timezone(&chrono::Utc);
+        assert_eq!(baseitem_date_to_string(Some(date)), "2021-03-04");
+    }
+
+    #[test]
+    fn absent_dates_are_left_empty() {
+        assert_eq!(baseitem_date_to_string(None), "");
+        // Empty dates are skipped instead of showing up as 1970
+        let json = serde_json::to_value(heresphere::VideoData::default()).expect("VideoData serializes");
+        assert!(json.get("dateReleased").is_none());
+        assert!(json.get("dateAdded").is_none());
+    }
+
+    #[test]
+    fn synthetic_tags_are_not_written_back() {
+        let tag = |name: &str, start: Option<f64>| heresphere::Tag {
+            name: name.to_string(),
+            start,
+            ..Default::default()
+        };
+        let tags = [
+            tag("Genre:Drama", None),
+            tag("Studio:Ratio; 16; 9", None),
+            tag("Type:Movie", None),
+            tag("Chapter:Intro", Some(0.0)),
+            tag("Tag:Ratio; 16; 9", None),
+            tag("typed in the headset", None),
+            tag("marker", Some(1000.0)),
+            tag("Tag:typed in the headset", None),
+        ];
+        assert_eq!(user_tags("; ", &tags), ["Ratio:16:9", "typed in the headset"]);
+        assert_eq!(user_tags("", &tags), ["Ratio; 16; 9", "typed in the headset"]);
+    }
+
+    #[test]
+    fn trickplay_comes_from_the_primary_media_source() {
+        let with_trickplay = uuid::Uuid::new_v4();
+        let without_trickplay = uuid::Uuid::new_v4();
+        let primary = uuid::Uuid::new_v4().simple().to_string();
+        let other = uuid::Uuid::new_v4().simple().to_string();
+        let tiles = |width: i32| serde_json::json!({
+            "Width": width, "Height": width / 2, "TileWidth": 10, "TileHeight": 10, "ThumbnailCount": 100, "Interval": 10000,
+        });
+        let trickplay: Vec<jellyfin::TrickplayItem> = serde_json::from_value(serde_json::json!([
+            { "Id": with_trickplay, "Trickplay": {
+                other.clone(): { "640": tiles(640) },
+                primary.clone(): { "320": tiles(320), "480": tiles(480) },
+            } },
+            { "Id": without_trickplay },
+        ]))
+        .expect("Invalid trickplay items");
+        let items = vec![
+            item(serde_json::json!({ "Id": with_trickplay, "MediaSources": [{ "Id": primary }, { "Id": other }] })),
+            item(serde_json::json!({ "Id": without_trickplay, "MediaSources": [{ "Id": without_trickplay.simple().to_string() }] })),
+        ];
+        let urls = trickplay_urls("https://jf.example", "token", &primary_media_sources(&items), &trickplay);
+        assert_eq!(urls.len(), 1);
+        assert_eq!(
+            urls.get(&with_trickplay.simple().to_string()),
+            Some(&format!(
+                "https://jf.example/Videos/{}/Trickplay/480/tiles.m3u8?MediaSourceId={}&api_key=token",
+                with_trickplay.simple(),
+                primary
+            ))
+        );
+        assert!(!urls.contains_key(&without_trickplay.simple().to_string()));
+    }
+
+    #[test]
+    fn collapsed_series_list_episodes_in_order() {
+        let ids: Vec<uuid::Uuid> = (0..3).map(|_| uuid::Uuid::new_v4()).collect();
+        let episode = |id: uuid::Uuid, season: i32, number: i32| {
+            item(serde_json::json!({
+                "Id": id,
+                "Type": "Episode",
+                "SeriesName": "Show",
+                "ParentIndexNumber": season,
+                "IndexNumber": number,
+            }))
+        };
+        // Sorted by name, as a user's sort preference may have it
+        let items = vec![episode(ids[2], 2, 1), episode(ids[1], 1, 2), episode(ids[0], 1, 1)];
+        let items: Vec<_> = items.iter().collect();
+        let libraries = series_libraries("https://vr.example", &items);
+        assert_eq!(libraries.len(), 1);
+        assert_eq!(libraries[0].name, "Show");
+        assert_eq!(
+            libraries[0].list,
+            ids.iter().map(|id| format!("https://vr.example/heresphere/{}", id.simple())).collect::<Vec<_>>()
+        );
+    }
+
+    fn episode(id: uuid::Uuid, season: i32, number: i32) -> jellyfin::types::BaseItemDto {
+        item(serde_json::json!({
+            "Id": id,
+            "Type": "Episode",
+            "Name": format!("Episode {}", number),
+            "SeriesName": "Show",
+            "ParentIndexNumber": season,
+            "IndexNumber": number,
+        }))
+    }
+
+    #[test]
+    fn episodes_are_ordered_numerically_within_a_season() {
+        let ids: Vec<uuid::Uuid> = (0..4).map(|n| uuid::Uuid::from_u128(n + 1)).collect();
+        // "S01E10" sorts before "S01E02" by name
+        let items = vec![episode(ids[3], 1, 10), episode(ids[1], 1, 2), episode(ids[0], 1, 1), episode(ids[2], 1, 9)];
+        let items: Vec<_> = items.iter().collect();
+        let ordered: Vec<_> = episodes_by_series(&items)["Show"].iter().map(|episode| episode.id).collect();
+        assert_eq!(ordered, ids.iter().copied().map(Some).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn episodes_name_the_next_one() {
+        let ids: Vec<uuid::Uuid> = (0..3).map(|n| uuid::Uuid::from_u128(n + 1)).collect();
+        let items = vec![episode(ids[2], 2, 1), episode(ids[0], 1, 9), episode(ids[1], 1, 10)];
+        let items: Vec<_> = items.iter().collect();
+        let tags = next_episode_tags(&config(), &items);
+        let next = |id: uuid::Uuid| tags.get(&id.simple().to_string()).map(|tag| tag.name.as_str());
+        assert_eq!(next(ids[0]), Some("NextEpisode:S01E10 - Episode 10"));
+        assert_eq!(next(ids[1]), Some("NextEpisode:S02E01 - Episode 1"));
+        // The last one has nothing to go on with
+        assert_eq!(next(ids[2]), None);
+    }
+
+    fn library<'a>(libraries: &'a [heresphere::Library], name: &str) -> Option<&'a [String]> {
+        libraries.iter().find(|library| library.name == name).map(|library| library.list.as_slice())
+    }
+
+    fn link(id: uuid::Uuid) -> String {
+        format!("https://vr.example/heresphere/{}", id.simple())
+    }
+
+    #[test]
+    fn recently_added_holds_the_newest_videos() {
+        let ids: Vec<uuid::Uuid> = (0..3).map(|n| uuid::Uuid::from_u128(n + 1)).collect();
+        let added = |id: uuid::Uuid, date: &str| {
+            item(serde_json::json!({ "Id": id, "DateCreated": date, "MediaSources": [{}] }))
+        };
+        let items = vec![
+            added(ids[0], "2023-01-01T00:00:00Z"),
+            added(ids[1], "2023-03-01T00:00:00Z"),
+            added(ids[2], "2023-02-01T00:00:00Z"),
+        ];
+        let config = AppConfig { recently_added_count: 2, ..config() };
+        let images = ImageSource::direct("https://jf.example", "token");
+        let libraries = baseitems_to_libraries("https://vr.example", &config, &images, &items, &[]);
+        assert_eq!(library(&libraries, "Recently Added"), Some(&[link(ids[1]), link(ids[2])][..]));
+
+        let config = AppConfig { recently_added_count: 0, ..config };
+        let libraries = baseitems_to_libraries("https://vr.example", &config, &images, &items, &[]);
+        assert_eq!(library(&libraries, "Recently Added"), None);
+    }
+
+    #[test]
+    fn continue_watching_holds_partly_played_videos() {
+        let ids: Vec<uuid::Uuid> = (0..3).map(|n| uuid::Uuid::from_u128(n + 1)).collect();
+        let played = |id: uuid::Uuid, percentage: f64| {
+            item(serde_json::json!({
+                "Id": id,
+                "MediaSources": [{}],
+                "UserData": { "PlayedPercentage": percentage, "Played": percentage >= 100.0 },
+            }))
+        };
+        let items = vec![played(ids[0], 0.0), played(ids[1], 50.0), played(ids[2], 100.0)];
+        let images = ImageSource::direct("https://jf.example", "token");
+        let libraries = baseitems_to_libraries("https://vr.example", &config(), &images, &items, &[]);
+        assert_eq!(library(&libraries, "Continue Watching"), Some(&[link(ids[1])][..]));
+    }
+
+    #[test]
+    fn each_year_gets_a_library() {
+        let ids: Vec<uuid::Uuid> = (0..4).map(|n| uuid::Uuid::from_u128(n + 1)).collect();
+        let released = |id: uuid::Uuid, year: i32| item(serde_json::json!({ "Id": id, "ProductionYear": year, "MediaSources": [{}] }));
+        let items = vec![released(ids[0], 2019), released(ids[1], 2023), released(ids[2], 2021), released(ids[3], 2023)];
+        let images = ImageSource::direct("https://jf.example", "token");
+        let years = |browse_by_year: &str| {
+            let config = AppConfig { browse_by_year: browse_by_year.to_string(), recently_added_count: 0, continue_watching: false, ..config() };
+            baseitems_to_libraries("https://vr.example", &config, &images, &items, &[])
+                .into_iter()
+                .skip(1)
+                .map(|library| (library.name, library.list))
+                .collect::<Vec<_>>()
+        };
+        // Newest first
+        assert_eq!(
+            years("year"),
+            vec![
+                ("2023".to_string(), vec![link(ids[1]), link(ids[3])]),
+                ("2021".to_string(), vec![link(ids[2])]),
+                ("2019".to_string(), vec![link(ids[0])]),
+            ]
+        );
+        assert_eq!(
+            years("decade"),
+            vec![
+                ("2020s".to_string(), vec![link(ids[1]), link(ids[2]), link(ids[3])]),
+                ("2010s".to_string(), vec![link(ids[0])]),
+            ]
+        );
+        assert!(years("none").is_empty());
+    }
+
+    #[test]
+    fn person_libraries_list_the_videos_of_each_person() {
+        let jane = uuid::Uuid::new_v4();
+        let both = uuid::Uuid::new_v4();
+        let items = vec![
+            item(serde_json::json!({ "Id": jane, "People": [{ "Name": "Jane", "Type": "Actor" }] })),
+            item(serde_json::json!({ "Id": both, "People": [
+                { "Name": "Jane", "Type": "Actor" },
+                { "Name": "John", "Type": "Director" },
+            ] })),
+        ];
+        let items: Vec<_> = items.iter().collect();
+        let libraries = entity_libraries("https://vr.example", &names(&["people"]), 1, None, &items);
+        let link = |id: uuid::Uuid| format!("https://vr.example/heresphere/{}", id.simple());
+        assert_eq!(libraries.len(), 2);
+        assert_eq!(libraries[0].name, "Actor: Jane");
+        assert_eq!(libraries[0].list, vec![link(jane), link(both)]);
+        assert_eq!(libraries[1].name, "Director: John");
+        assert_eq!(libraries[1].list, vec![link(both)]);
+        // Only people with enough videos get a library
+        let libraries = entity_libraries("https://vr.example", &names(&["people"]), 2, None, &items);
+        assert_eq!(libraries.iter().map(|library| library.name.as_str()).collect::<Vec<_>>(), vec!["Actor: Jane"]);
+        assert!(entity_libraries("https://vr.example", &names(&["genres"]), 1, None, &items).is_empty());
+    }
+
+    #[test]
+    fn person_libraries_link_the_person_image() {
+        let jane = uuid::Uuid::new_v4();
+        let john = uuid::Uuid::new_v4();
+        let items = vec![item(serde_json::json!({ "Id": uuid::Uuid::new_v4(), "People": [
+            { "Name": "Jane", "Id": jane, "Type": "Actor", "PrimaryImageTag": "tag" },
+            { "Name": "John", "Id": john, "Type": "Director" },
+        ] }))];
+        let items: Vec<_> = items.iter().collect();
+        let images = ImageSource::direct("https://jf.example", "token");
+        let libraries = entity_libraries("https://vr.example", &names(&["people"]), 1, Some(&images), &items);
+        assert_eq!(libraries[0].name, "Actor: Jane");
+        let image = libraries[0].image.as_deref().expect("No image for Jane");
+        assert!(image.starts_with(&format!("https://jf.example/Items/{}/Images/Primary?", jane.simple())));
+        // No image to link for people Jellyfin has none of, and none at all when disabled
+        assert_eq!(libraries[1].name, "Director: John");
+        assert_eq!(libraries[1].image, None);
+        let libraries = entity_libraries("https://vr.example", &names(&["people"]), 1, None, &items);
+        assert!(libraries.iter().all(|library| library.image.is_none()));
+    }
+
+    #[test]
+    fn chapters_genres_and_people_get_their_own_tracks() {
+        let tags = baseitem_to_tags(
+            &config(),
+            &item(serde_json::json!({
+                "RunTimeTicks": 600_000_000,
+                "Chapters": [
+                    { "Name": "Intro", "StartPositionTicks": 0 },
+                    { "Name": "Beach", "StartPositionTicks": 100_000_000 },
+                ],
+                "Genres": ["Drama"],
+                "People": [{ "Name": "Jane", "Type": "Actor" }, { "Name": "John", "Type": "Director" }],
+            })),
+        );
+        let track = |name: &str| tags.iter().find(|tag| tag.name == name).and_then(|tag| tag.track);
+        assert_eq!(track("Chapter:Beach"), Some(0));
+        assert_eq!(track("Skip:Intro"), Some(1));
+        assert_eq!(track("Genre:Drama"), Some(2));
+        assert_eq!(track("Actor:Jane"), Some(3));
+        assert_eq!(track("Director:John"), Some(3));
+    }
+
+    #[test]
+    fn favorites_count_the_plays() {
+        let plays = |user_data: serde_json::Value| video(&config(), serde_json::json!({ "UserData": user_data })).favorites;
+        assert_eq!(plays(serde_json::json!({ "PlayCount": 3, "Played": true })), Some(3));
+        assert_eq!(plays(serde_json::json!({ "PlayCount": 0 })), Some(0));
+        assert_eq!(video(&config(), serde_json::json!({})).favorites, None);
+    }
+
+    #[test]
+    fn music_videos_are_videos_with_artist_tags() -> eyre::Result<()> {
+        let video = video(&config(), serde_json::json!({
+            "Type": "MusicVideo",
+            "Name": "Song",
+            "Artists": ["Band"],
+            "Album": "Record",
+            "RunTimeTicks": 2_400_000_000i64,
+            "MediaSources": [{ "Id": "source", "Container": "mp4" }],
+        }));
+        assert_eq!(video.title, "Song");
+        assert_eq!(video.duration, 240_000.0);
+        assert_eq!(video.media.len(), 1);
+        assert_eq!(video.media[0].sources[0].url, "https://jf.example/Items/source/Download?api_key=token");
+        let tags: Vec<_> = video.tags.iter().map(|tag| tag.name.as_str()).collect();
+        assert!(tags.contains(&"Artist:Band"));
+        assert!(tags.contains(&"Album:Record"));
+        // And HereSphere can read it back
+        serde_json::from_str::<heresphere::VideoData>(&serde_json::to_string(&video)?)?;
+        Ok(())
+    }
+
+    /// Ids of the items that make it into the scan.
+    fn scanned(config: &AppConfig, items: &[jellyfin::types::BaseItemDto]) -> Vec<String> {
+        let images = ImageSource::direct("https://jf.example", "token");
+        baseitems_to_video_cache("user", "https://jf.example", "token", &images, config, items, &HashMap::new())
+            .iter()
+            .filter_map(VideoCache::video_id)
+            .collect()
+    }
+
+    #[test]
+    fn only_playable_items_are_scanned() {
+        let ids: Vec<uuid::Uuid> = (0..5).map(|n| uuid::Uuid::from_u128(n + 1)).collect();
+        let items = vec![
+            item(serde_json::json!({ "Id": ids[0], "MediaSources": [{ "Id": "a" }] })),
+            item(serde_json::json!({ "Id": ids[1], "LocationType": "Virtual", "MediaSources": [{ "Id": "b" }] })),
+            item(serde_json::json!({ "Id": ids[2], "IsPlaceHolder": true, "MediaSources": [{ "Id": "c" }] })),
+            item(serde_json::json!({ "Id": ids[3], "MediaSources": [] })),
+            item(serde_json::json!({ "Id": ids[4] })),
+        ];
+        assert_eq!(scanned(&config(), &items), vec![ids[0].simple().to_string()]);
+    }
+
+    #[test]
+    fn defaults_apply_when_no_marker_matches() {
+        let config = AppConfig {
+            default_projection: "equirectangular".to_string(),
+            default_stereo: "sbs".to_string(),
+            ..config()
+        };
+        let plain = video(&config, serde_json::json!({ "Name": "Beach walk", "Path": "/media/beach_walk.mp4" }));
+        assert_eq!(plain.projection, "equirectangular");
+        assert_eq!(plain.stereo, "sbs");
+        // A marker wins over the defaults
+        let marked = video(&config, serde_json::json!({ "Name": "Beach walk", "Path": "/media/beach_walk_MKX200_TB.mp4" }));
+        assert_eq!(marked.projection, "fisheye");
+        assert_eq!(marked.stereo, "tb");
+    }
+
+    #[test]
+    fn items_rated_below_the_minimum_are_left_out() {
+        let ids: Vec<uuid::Uuid> = (0..3).map(|n| uuid::Uuid::from_u128(n + 1)).collect();
+        let items = vec![
+            item(serde_json::json!({ "Id": ids[0], "CommunityRating": 3.0, "MediaSources": [{ "Id": "a" }] })),
+            item(serde_json::json!({ "Id": ids[1], "CommunityRating": 7.0, "MediaSources": [{ "Id": "b" }] })),
+            item(serde_json::json!({ "Id": ids[2], "MediaSources": [{ "Id": "c" }] })),
+        ];
+        let simple = |ids: &[uuid::Uuid]| ids.iter().map(|id| id.simple().to_string()).collect::<Vec<_>>();
+        // Without a minimum everything stays
+        assert_eq!(scanned(&config(), &items), simple(&ids));
+        let min_rated = AppConfig { min_rating: Some(5.0), ..config() };
+        assert_eq!(scanned(&min_rated, &items), simple(&[ids[1], ids[2]]));
+        let rated_only = AppConfig { unrated_passes_min_rating: false, ..min_rated };
+        assert_eq!(scanned(&rated_only, &items), simple(&[ids[1]]));
+    }
+
+    #[test]
+    fn ratings_are_halved_and_left_empty_when_unrated() {
+        let rating = |json: serde_json::Value| video(&config(), json).rating;
+        assert_eq!(rating(serde_json::json!({ "CommunityRating": 7.0 })), Some(3.5));
+        // The user's own rating wins
+        assert_eq!(rating(serde_json::json!({ "CommunityRating": 7.0, "UserData": { "Rating": 10.0 } })), Some(5.0));
+        assert_eq!(rating(serde_json::json!({})), None);
+        assert_eq!(rating(serde_json::json!({ "UserData": { "PlayCount": 1 } })), None);
+    }
+
+    #[test]
+    fn run_time_falls_back_to_the_media_sources() {
+        let ticks = |json: serde_json::Value| baseitem_run_time_ticks(&item(json));
+        assert_eq!(ticks(serde_json::json!({ "RunTimeTicks": 600_000_000 })), Some(600_000_000));
+        assert_eq!(
+            ticks(serde_json::json!({ "MediaSources": [{ "RunTimeTicks": 0 }, { "RunTimeTicks": 1_200_000_000 }] })),
+            Some(1_200_000_000)
+        );
+        // A zero on the item itself doesn't hide the source's
+        assert_eq!(
+            ticks(serde_json::json!({ "RunTimeTicks": 0, "MediaSources": [{ "RunTimeTicks": 1_200_000_000 }] })),
+            Some(1_200_000_000)
+        );
+        assert_eq!(ticks(serde_json::json!({ "MediaSources": [{}] })), None);
+        let duration = video(&config(), serde_json::json!({ "MediaSources": [{ "Id": "a", "RunTimeTicks": 1_200_000_000 }] })).duration;
+        assert_eq!(duration, 120_000.0);
+    }
+
+    #[test]
+    fn lens_markers_pick_a_preset() {
+        let lens = |config: &AppConfig, name: &str| {
+            let video = video(config, serde_json::json!({ "Name": name }));
+            (video.projection, video.lens, video.fov)
+        };
+        assert_eq!(lens(&config(), "Trip MKX200"), ("fisheye".to_string(), Some("MKX200".to_string()), Some(200.0)));
+        assert_eq!(lens(&config(), "Trip fisheye190"), ("fisheye".to_string(), Some("Linear".to_string()), Some(190.0)));
+        assert_eq!(lens(&config(), "Trip").1, None);
+
+        let mut custom = config();
+        custom.lens_presets.insert(
+            0,
+            ("CANON".to_string(), heresphere::LensPreset { lens: "MKX220".to_string(), fov: 220.0 }),
+        );
+        assert_eq!(lens(&custom, "Trip canon"), ("fisheye".to_string(), Some("MKX220".to_string()), Some(220.0)));
+        // The built-in ones still work next to it
+        assert_eq!(lens(&custom, "Trip VRCA220").1, Some("VRCA220".to_string()));
+    }
+
+    #[test]
+    fn camera_ipd_is_only_set_for_stereo() {
+        let config = AppConfig {
+            camera_ipd: Some(0.064),
+            lens_camera_ipd: HashMap::from([("MKX200".to_string(), 0.06)]),
+            ..config()
+        };
+        let ipd = |name: &str| video(&config, serde_json::json!({ "Name": name })).camera_ipd;
+        assert_eq!(ipd("Trip SBS"), Some(0.064));
+        assert_eq!(ipd("Trip MKX200 SBS"), Some(0.06));
+        assert_eq!(ipd("Trip"), None);
+        assert_eq!(ipd("Trip MKX200"), None);
+    }
+
+    #[test]
+    fn eye_swap_overrides_win_over_markers() {
+        let swapped = |name: &str, is_eye_swapped: Option<bool>| {
+            let mut video = video(&config(), serde_json::json!({ "Name": name }));
+            VideoOverride { is_eye_swapped, ..Default::default() }.apply(&mut video);
+            video.is_eye_swapped
+        };
+        assert_eq!(swapped("Trip SBS RL", None), Some(true));
+        assert_eq!(swapped("Trip SBS", None), None);
+        // Markers are whole words
+        assert_eq!(swapped("World tour", None), None);
+        // The override undoes a wrong match or fixes a missing marker
+        assert_eq!(swapped("Trip SBS RL", Some(false)), Some(false));
+        assert_eq!(swapped("Trip SBS", Some(true)), Some(true));
+    }
+
+    #[test]
+    fn configured_image_type_is_used_for_thumbnails() {
+        let id = uuid::Uuid::from_u128(1);
+        let thumb = |config: &AppConfig, mut json: serde_json::Value| {
+            json["Id"] = serde_json::json!(id);
+            video(config, json).thumbnail_image
+        };
+        let image = |image_type: &str| {
+            format!(
+                "https://jf.example/Items/{}/Images/{}?maxHeight=300&maxWidth=300&quality=90&api_key=token",
+                id.simple(),
+                image_type
+            )
+        };
+        let with_thumb = serde_json::json!({ "Type": "Movie", "ImageTags": { "Thumb": "tag" }, "BackdropImageTags": ["tag"] });
+        let config = AppConfig { image_type: Some("Thumb".to_string()), ..config() };
+        assert_eq!(thumb(&config, with_thumb.clone()), image("Thumb"));
+        // Falls back to the primary image when the item doesn't have the configured one
+        assert_eq!(thumb(&config, serde_json::json!({ "Type": "Movie" })), image("Primary"));
+        // Per type wins over the general setting
+        let per_type = AppConfig {
+            image_types: HashMap::from([("Movie".to_string(), "Backdrop".to_string())]),
+            ..config
+        };
+        assert_eq!(thumb(&per_type, with_thumb), image("Backdrop"));
+    }
+
+    #[test]
+    fn colons_in_tag_values_are_replaced() {
+        let video = video(&config(), serde_json::json!({
+            "Type": "Movie",
+            "Name": "Aspect",
+            "Studios": [{ "Name": "Ratio: 16:9" }],
+            "Genres": ["Documentary"],
+        }));
+        let tags: Vec<_> = video.tags.iter().map(|tag| tag.name.as_str()).collect();
+        assert!(tags.contains(&"MovieStudio:Ratio\u{A789} 16\u{A789}9"));
+        // Values without colons are left alone
+        assert!(tags.contains(&"Movie:Aspect"));
+        let config = AppConfig { tag_colon_replacement: " -".to_string(), ..config() };
+        assert_eq!(sanitize_tag_name(&config, "MovieStudio:Ratio: 16:9"), "MovieStudio:Ratio - 16 -9");
+        assert_eq!(sanitize_tag_name(&config, "NoCategory"), "NoCategory");
+    }
+
+    /// Text subtitle stream of a media source, in `language` and titled `title`.
+    fn subtitle_stream(index: i32, language: &str, title: &str) -> serde_json::Value {
+        serde_json::json!({
+            "Type": "Subtitle",
+            "Index": index,
+            "Language": language,
+            "DisplayTitle": title,
+            "Codec": "subrip",
+            "IsTextSubtitleStream": true,
+        })
+    }
+
+    #[test]
+    fn accept_language_reorders_subtitles() {
+        let streams = vec![subtitle_stream(2, "fre", "French"), subtitle_stream(3, "eng", "English"), subtitle_stream(4, "ger", "German")];
+        let video = video(&config(), serde_json::json!({ "MediaSources": [{ "Id": "a", "MediaStreams": streams }] }));
+        let mut subtitles = video.subtitles.expect("No subtitles");
+        let order = |subtitles: &[heresphere::Subtitle]| subtitles.iter().map(|subtitle| subtitle.language.clone()).collect::<Vec<_>>();
+        // The configured language comes first without a header
+        assert_eq!(order(&subtitles), names(&["eng", "fre", "ger"]));
+
+        let languages = accept_languages("en;q=0.5, de-DE, fr;q=0.8, *;q=0.1");
+        assert_eq!(languages, names(&["de", "fr", "en"]));
+        sort_subtitles(&mut subtitles, &languages);
+        assert_eq!(order(&subtitles), names(&["ger", "fre", "eng"]));
+        // Languages the header doesn't name keep their place at the end
+        sort_subtitles(&mut subtitles, &names(&["fr"]));
+        assert_eq!(order(&subtitles), names(&["fre", "ger", "eng"]));
+    }
+
+    #[test]
+    fn forced_and_sdh_subtitles_are_named() {
+        let mut forced = subtitle_stream(2, "eng", "English");
+        forced["IsForced"] = serde_json::json!(true);
+        let sdh = subtitle_stream(3, "eng", "English [CC]");
+        let mut labelled = subtitle_stream(4, "eng", "English Forced");
+        labelled["IsForced"] = serde_json::json!(true);
+        let streams = vec![subtitle_stream(1, "eng", "English"), forced, sdh, labelled];
+        let item = serde_json::json!({ "MediaSources": [{ "Id": "a", "MediaStreams": streams }] });
+        let subtitle_names = |config: &AppConfig| {
+            video(config, item.clone())
+                .subtitles
+                .unwrap_or_default()
+                .into_iter()
+                .map(|subtitle| subtitle.name)
+                .collect::<Vec<_>>()
+        };
+        // Titles that already say so aren't labelled twice
+        assert_eq!(
+            subtitle_names(&config()),
+            names(&["English", "English (Forced)", "English [CC] (SDH)", "English Forced"])
+        );
+        let config = AppConfig { forced_subtitles: false, sdh_subtitles: false, ..config() };
+        assert_eq!(subtitle_names(&config), names(&["English"]));
+    }
+
+    #[test]
+    fn subtitle_delivery_urls_are_used() {
+        let mut external = subtitle_stream(2, "eng", "English");
+        external["DeliveryUrl"] = serde_json::json!("https://subs.example/english.srt");
+        external["IsExternalUrl"] = serde_json::json!(true);
+        let mut relative = subtitle_stream(3, "eng", "English");
+        relative["DeliveryUrl"] = serde_json::json!("/Videos/x/a/Subtitles/3/0/Stream.vtt?api_key=token");
+        let streams = vec![external, relative, subtitle_stream(4, "eng", "English")];
+        let id = uuid::Uuid::from_u128(1);
+        let video = video(&config(), serde_json::json!({ "Id": id, "MediaSources": [{ "Id": "a", "MediaStreams": streams }] }));
+        let urls: Vec<_> = video.subtitles.expect("No subtitles").into_iter().map(|subtitle| subtitle.url).collect();
+        assert_eq!(
+            urls,
+            vec![
+                "https://subs.example/english.srt".to_string(),
+                "https://jf.example/Videos/x/a/Subtitles/3/0/Stream.vtt?api_key=token".to_string(),
+                format!("https://jf.example/Videos/{}/a/Subtitles/4/Stream.srt?api_key=token", id.simple()),
+            ]
+        );
+    }
+
+    #[test]
+    fn items_rated_above_the_maximum_are_restricted() {
+        let access = |config: &AppConfig, json: serde_json::Value| video(config, json).access;
+        // No maximum, nothing is restricted
+        assert_eq!(access(&config(), serde_json::json!({ "OfficialRating": "XXX" })), Some(1));
+        let up_to_pg13 = AppConfig { max_official_rating: Some("PG-13".to_string()), ..config() };
+        assert_eq!(access(&up_to_pg13, serde_json::json!({ "OfficialRating": "R" })), Some(0));
+        assert_eq!(access(&up_to_pg13, serde_json::json!({ "OfficialRating": "US-NC-17" })), Some(0));
+        assert_eq!(access(&up_to_pg13, serde_json::json!({ "OfficialRating": "PG-13" })), Some(1));
+        assert_eq!(access(&up_to_pg13, serde_json::json!({ "OfficialRating": "G" })), Some(1));
+        assert_eq!(access(&up_to_pg13, serde_json::json!({})), Some(1));
+        let strict = AppConfig { restrict_unrated: true, ..up_to_pg13 };
+        assert_eq!(access(&strict, serde_json::json!({})), Some(0));
+    }
+
+    #[test]
+    fn items_with_only_corrupt_sources_are_left_out() {
+        let ids: Vec<uuid::Uuid> = (0..5).map(|n| uuid::Uuid::from_u128(n + 1)).collect();
+        let video_stream = serde_json::json!([{ "Type": "Video" }]);
+        let items = vec![
+            item(serde_json::json!({ "Id": ids[0], "MediaSources": [{ "Id": "a", "Size": 1000, "MediaStreams": video_stream }] })),
+            item(serde_json::json!({ "Id": ids[1], "MediaSources": [{ "Id": "b", "Size": 0 }] })),
+            item(serde_json::json!({ "Id": ids[2], "MediaSources": [{ "Id": "c", "RunTimeTicks": 0 }] })),
+            item(serde_json::json!({ "Id": ids[3], "MediaSources": [{ "Id": "d", "MediaStreams": [{ "Type": "Audio" }] }] })),
+            // One good version is enough, the broken one is dropped from its media
+            item(serde_json::json!({ "Id": ids[4], "MediaSources": [{ "Id": "e", "Size": 0 }, { "Id": "f" }] })),
+        ];
+        let simple = |ids: &[uuid::Uuid]| ids.iter().map(|id| id.simple().to_string()).collect::<Vec<_>>();
+        assert_eq!(scanned(&config(), &items), simple(&[ids[0], ids[4]]));
+        let last = video(&config(), serde_json::json!({ "Id": ids[4], "MediaSources": [{ "Id": "e", "Size": 0 }, { "Id": "f" }] }));
+        assert_eq!(last.media.len(), 1);
+        assert!(last.media[0].sources[0].url.contains("/Items/f/"));
+        // Unless broken ones are kept on purpose
+        let keep_broken = AppConfig { skip_broken_media: false, ..config() };
+        assert_eq!(scanned(&keep_broken, &items), simple(&ids));
+    }
+
+    #[test]
+    fn fov_follows_the_projection() {
+        let fov = |config: &AppConfig, projection: &str, name: &str| {
+            let config = AppConfig { default_projection: projection.to_string(), ..config.clone() };
+            video(&config, serde_json::json!({ "Name": name })).fov
+        };
+        assert_eq!(fov(&config(), "equirectangular", "Trip"), Some(180.0));
+        assert_eq!(fov(&config(), "equirectangular360", "Trip"), Some(360.0));
+        assert_eq!(fov(&config(), "perspective", "Trip"), None);
+        let mut wider = config();
+        wider.projection_fov.insert("equirectangular".to_string(), 200.0);
+        assert_eq!(fov(&wider, "equirectangular", "Trip"), Some(200.0));
+        // A lens preset knows better
+        assert_eq!(fov(&wider, "equirectangular", "Trip MKX220"), Some(220.0));
+    }
+
+    #[test]
+    fn half_and_full_width_markers_share_a_layout() {
+        let stereo = |json: serde_json::Value| video(&config(), json).stereo;
+        // HereSphere tells half from full width by the video's aspect ratio
+        assert_eq!(stereo(serde_json::json!({ "Path": "/vr/trip_HSBS.mp4" })), "sbs");
+        assert_eq!(stereo(serde_json::json!({ "Path": "/vr/trip_FSBS.mp4" })), "sbs");
+        assert_eq!(stereo(serde_json::json!({ "Path": "/vr/trip.HTB.mkv" })), "tb");
+        assert_eq!(stereo(serde_json::json!({ "Path": "/vr/trip.FTB.mkv" })), "tb");
+        assert_eq!(stereo(serde_json::json!({ "Tags": ["hou"] })), "tb");
+        // Markers inside other words don't count
+        assert_eq!(stereo(serde_json::json!({ "Name": "HSBSX" })), "mono");
+    }
+
+    #[tokio::test]
+    async fn concurrent_partial_overrides_both_stick() -> eyre::Result<()> {
+        let (db, path) = test_db().await?;
+        let settings = VideoOverridePatch { hsp: Some("aHNw".to_string()), ..Default::default() };
+        let swapped = VideoOverridePatch { is_eye_swapped: Some(Some(true)), ..Default::default() };
+
+        let (first, second) = tokio::join!(
+            VideoOverride::save(&db, "user", "vid", &settings),
+            VideoOverride::save(&db, "user", "vid", &swapped),
+        );
+        first.map_err(|e| e.0)?;
+        second.map_err(|e| e.0)?;
+        let saved = VideoOverride::get(&db, "user", "vid").await.map_err(|e| e.0)?.expect("Override wasn't saved");
+        assert_eq!(saved.hsp.as_deref(), Some("aHNw"));
+        assert_eq!(saved.is_eye_swapped, Some(true));
+
+        // Clearing one leaves the other alone
+        let unswapped = VideoOverridePatch { is_eye_swapped: Some(None), ..Default::default() };
+        VideoOverride::save(&db, "user", "vid", &unswapped).await.map_err(|e| e.0)?;
+        let saved = VideoOverride::get(&db, "user", "vid").await.map_err(|e| e.0)?.expect("Override was dropped");
+        assert_eq!(saved.hsp.as_deref(), Some("aHNw"));
+        assert_eq!(saved.is_eye_swapped, None);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[test]
+    fn technical_summary_matches_the_streams() {
+        let source = serde_json::json!({
+            "Id": "a",
+            "Bitrate": 25_300_000,
+            "MediaStreams": [
+                { "Type": "Audio", "Codec": "aac", "Index": 1 },
+                { "Type": "Video", "Codec": "hevc", "Width": 3840, "Height": 1920, "Index": 0 },
+            ],
+        });
+        let description = |technical_details: &str, overview: Option<&str>| {
+            let config = AppConfig { technical_details: technical_details.to_string(), ..config() };
+            video(&config, serde_json::json!({ "Overview": overview, "MediaSources": [source.clone()] })).description
+        };
+        let summary = "3840x1920 HEVC, AAC, 25.3 Mbps";
+        assert_eq!(description("description", None).as_deref(), Some(summary));
+        assert_eq!(description("description", Some("A trip")), Some(format!("A trip\n\n{}", summary)));
+        assert_eq!(description("none", Some("A trip")).as_deref(), Some("A trip"));
+        // Without streams there's nothing to sum up
+        let bare = AppConfig { technical_details: "description".to_string(), ..config() };
+        let bare = video(&bare, serde_json::json!({ "Overview": "A trip", "MediaSources": [{ "Id": "a" }] }));
+        assert_eq!(bare.description.as_deref(), Some("A trip"));
+    }
+
+    #[test]
+    fn format_tags_carry_projection_and_stereo() {
+        let tags = |format_tags: bool| {
+            let config = AppConfig { format_tags, default_projection: "equirectangular".to_string(), ..config() };
+            let tags = baseitem_to_tags(&config, &item(serde_json::json!({ "Name": "Trip", "Path": "/vr/trip_180_SBS.mp4" })));
+            tags.into_iter().map(|tag| tag.name).filter(|name| name.starts_with("Projection:") || name.starts_with("Stereo:")).collect::<Vec<_>>()
+        };
+        assert_eq!(tags(true), names(&["Projection:180", "Stereo:SBS"]));
+        assert!(tags(false).is_empty());
+    }
+
+    #[test]
+    fn blocked_tag_categories_are_left_out() {
+        let mut config = config();
+        config.tag_blocklist = names(&["Writer", "Type"]);
+        let tags = baseitem_to_tags(
+            &config,
+            &item(serde_json::json!({
+                "Type": "Movie",
+                "Name": "Film",
+                "Genres": ["Drama"],
+                "People": [{ "Name": "Jane", "Type": "Actor" }, { "Name": "John", "Type": "Writer" }],
+            })),
+        );
+        let names: Vec<&str> = tags.iter().map(|tag| tag.name.as_str()).collect();
+        assert!(names.contains(&"Genre:Drama"));
+        assert!(names.contains(&"Actor:Jane"));
+        assert!(names.contains(&"Movie:Film"));
+        assert!(!names.iter().any(|name| name.starts_with("Writer:") || name.starts_with("Type:")));
+    }
+
+    #[test]
+    fn chapter_markers_match_whole_words() {
+        let mut config = config();
+        config.skip_generic_chapters = true;
+        let name = |name: &str| chapter_tag_name(&config, Some(name));
+        assert_eq!(name("Intro").as_deref(), Some("Skip:Intro"));
+        assert_eq!(name("Intro (Part 1)").as_deref(), Some("Skip:Intro"));
+        assert_eq!(name("End Credits").as_deref(), Some("Skip:Credits"));
+        assert_eq!(name("outro").as_deref(), Some("Skip:Credits"));
+        assert_eq!(name("Introduction").as_deref(), Some("Chapter:Introduction"));
+        assert_eq!(name("Creditswap Heist").as_deref(), Some("Chapter:Creditswap Heist"));
+        assert_eq!(name("Chapter 3"), None);
+        assert_eq!(name("12"), None);
+        assert_eq!(name("Chapter 3b").as_deref(), Some("Chapter:Chapter 3b"));
+
+        config.skip_generic_chapters = false;
+        assert_eq!(chapter_tag_name(&config, Some("Chapter 3")).as_deref(), Some("Chapter:Chapter 3"));
+    }
+
+    #[test]
+    fn only_videos_likely_played_next_are_prefetched() {
+        let id = |n: u128| uuid::Uuid::from_u128(n);
+        let sources = serde_json::json!([{ "Id": "source" }]);
+        let in_progress = |n: u128, last_played: &str| {
+            item(serde_json::json!({
+                "Id": id(n),
+                "MediaSources": sources,
+                "UserData": { "PlayedPercentage": 50.0, "LastPlayedDate": last_played },
+            }))
+        };
+        let items = vec![
+            item(serde_json::json!({ "Id": id(1), "MediaSources": sources })),
+            in_progress(2, "2024-01-01T00:00:00Z"),
+            in_progress(3, "2024-02-01T00:00:00Z"),
+            item(serde_json::json!({ "Id": id(4), "MediaSources": sources })),
+        ];
+        // The next episode of a series, plus one that isn't indexed and can't be played from here
+        let next_up = vec![
+            item(serde_json::json!({ "Id": id(4) })),
+            item(serde_json::json!({ "Id": id(5) })),
+        ];
+        let simple = |n: u128| id(n).simple().to_string();
+        assert_eq!(prefetch_candidates(&config(), &items, &next_up), vec![simple(3), simple(2), simple(4)]);
+
+        let many: Vec<_> = (100..200).map(|n| in_progress(n, "2024-01-01T00:00:00Z")).collect();
+        assert_eq!(prefetch_candidates(&config(), &many, &[]).len(), PREFETCH_MAX_VIDEOS);
+    }
+
+    #[test]
+    fn image_signature_verifies_for_the_signed_image_only() {
+        let signature = image_signature(&key(), "alice", "item", "Primary");
+        assert!(verify_image_signature(&key(), "alice", "item", "Primary", &signature));
+        assert!(!verify_image_signature(&key(), "bob", "item", "Primary", &signature));
+        assert!(!verify_image_signature(&key(), "alice", "other", "Primary", &signature));
+        assert!(!verify_image_signature(&key(), "alice", "item", "Backdrop", &signature));
+        assert!(!verify_image_signature(&Secret("other-key".to_string()), "alice", "item", "Primary", &signature));
+        assert!(!verify_image_signature(&key(), "alice", "item", "Primary", "not hex"));
+    }
+
+    #[test]
+    fn proxied_image_urls_carry_a_signature_and_no_token() {
+        let images = ImageSource::Proxy {
+            host: "https://vr.example".to_string(),
+            user_key: "alice".to_string(),
+            key: key(),
+        };
+        let url = images.url("item", "Primary");
+        let signature = image_signature(&key(), "alice", "item", "Primary");
+        assert!(url.starts_with(&format!("https://vr.example/heresphere/images/alice/{}/Items/item/Images/Primary?", signature)));
+        assert!(!url.contains("api_key"));
+    }
+
+    #[test]
+    fn stacked_parts_are_numbered_by_their_marker() {
+        assert_eq!(stack_part_number("Movie - part2"), Some(2));
+        assert_eq!(stack_part_number("Movie CD1"), Some(1));
+        assert_eq!(stack_part_number("Movie.Disc.3"), Some(3));
+        assert_eq!(stack_part_number("Movie_pt_12"), Some(12));
+        assert_eq!(stack_part_number("Counterpart 2"), None);
+        assert_eq!(stack_part_number("Movie"), None);
+    }
+
+    #[test]
+    fn episode_titles_fill_in_their_template() {
+        let episode = item(serde_json::json!({
+            "Name": "Pilot", "SeriesName": "Show", "ParentIndexNumber": 1, "IndexNumber": 3,
+        }));
+        assert_eq!(render_episode_title("{series} S{season}E{episode} - {title}", &episode), "Show S01E03 - Pilot");
+        assert_eq!(render_episode_title("{title}", &item(serde_json::json!({}))), "");
+        assert!(is_valid_episode_title_template("{series} - {title}"));
+        assert!(!is_valid_episode_title_template("{series"));
+        assert!(!is_valid_episode_title_template("{year}"));
+    }
+}
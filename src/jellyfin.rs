@@ -1,68 +1,395 @@
-use std::vec;
+use std::{collections::HashMap, sync::Arc, vec};
+
+use tokio::sync::{Semaphore, SemaphorePermit};
 
 use chrono::Utc;
 use progenitor::generate_api;
+use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
 use self::types::{ResponseProfile, SubtitleProfile, TranscodingProfile};
 
 generate_api!("jellyfin-openapi-stable-models-only.json");
 
+/// Jellyfin version of the OpenAPI spec the models were generated from.
+pub const SPEC_VERSION: &str = "10.8.13";
+
+/// Checks whether a Jellyfin version string (`10.8.13`) is older than [`SPEC_VERSION`].
+pub fn is_older_than_spec(version: &str) -> bool {
+    let parse = |v: &str| -> Vec<u32> { v.split('.').map(|part| part.parse().unwrap_or_default()).collect() };
+    parse(version) < parse(SPEC_VERSION)
+}
+
+/// Image types that work as thumbnails.
+pub static IMAGE_TYPES: &[&str] = &["Primary", "Backdrop", "Thumb", "Banner", "Logo", "Art"];
+
+/// Item fields requested from Jellyfin unless configured otherwise, everything JellyVR has a use for.
+pub static DEFAULT_ITEM_FIELDS: &[&str] = &[
+    "DateCreated",
+    "MediaSources",
+    "BasicSyncInfo",
+    "Genres",
+    "Tags",
+    "Studios",
+    "SeriesStudio",
+    "People",
+    "Chapters",
+    "Path",
+];
+
+/// Levels of the US ratings, as Jellyfin ranks them for parental control. `NR`/`UR` mean unrated and aren't listed.
+pub static PARENTAL_RATINGS: &[(&str, i32)] = &[
+    ("APPROVED", 0),
+    ("G", 1),
+    ("TV-G", 1),
+    ("TV-Y", 2),
+    ("TV-Y7", 3),
+    ("TV-Y7-FV", 4),
+    ("PG", 5),
+    ("TV-PG", 5),
+    ("PG-13", 7),
+    ("TV-14", 8),
+    ("R", 9),
+    ("TV-MA", 9),
+    ("NC-17", 10),
+    ("X", 15),
+    ("XXX", 100),
+];
+
+/// Parental control level of an official rating, `None` for unrated items and ratings we don't know.
+pub fn parental_rating_level(rating: &str) -> Option<i32> {
+    let rating = rating.trim();
+    let rating = rating
+        .get(..3)
+        .filter(|prefix| prefix.eq_ignore_ascii_case("US-"))
+        .map_or(rating, |_| &rating[3..]);
+    PARENTAL_RATINGS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(rating))
+        .map(|(_, level)| *level)
+}
+
+/// Values of the `Filters` item query parameter.
+pub static ITEM_FILTERS: &[&str] = &[
+    "IsFolder",
+    "IsNotFolder",
+    "IsUnplayed",
+    "IsPlayed",
+    "IsFavorite",
+    "IsResumable",
+    "Likes",
+    "Dislikes",
+    "IsFavoriteOrLikes",
+];
+
+/// Jellyfin positions and run times are in ticks of 100 nanoseconds.
+pub const TICKS_PER_MILLISECOND: f64 = 10_000.0;
+
+/// Converts milliseconds (what HereSphere speaks) to Jellyfin ticks.
+pub fn ms_to_ticks(ms: f64) -> i64 {
+    (ms * TICKS_PER_MILLISECOND).round() as i64
+}
+
+/// Converts Jellyfin ticks to milliseconds (what HereSphere speaks).
+pub fn ticks_to_ms(ticks: i64) -> f64 {
+    ticks as f64 / TICKS_PER_MILLISECOND
+}
+
 #[derive(Clone)]
 pub struct JellyfinConfig {
     pub base_url: String,
+    pub codec_profile: CodecProfile,
+    /// Upper bound on requests in flight to this server at once.
+    pub max_concurrent_requests: usize,
+    /// Never change anything on the server: no progress, played marks, tags or capabilities.
+    pub read_only: bool,
 }
 
 impl JellyfinConfig {
-    pub fn new(base_url: String) -> Self {
-        Self { base_url }
+    pub fn new(base_url: String, codec_profile: CodecProfile, max_concurrent_requests: usize, read_only: bool) -> Self {
+        Self {
+            base_url: normalize_base_url(&base_url),
+            codec_profile,
+            max_concurrent_requests,
+            read_only,
+        }
+    }
+
+    /// Joins an API path onto the base URL, keeping any subpath Jellyfin is served under.
+    pub fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url, path.trim_start_matches('/'))
+    }
+}
+
+/// Strips trailing slashes so `https://host/jellyfin/` and `https://host/jellyfin` behave the same.
+pub fn normalize_base_url(base_url: &str) -> String {
+    base_url.trim().trim_end_matches('/').to_string()
+}
+
+/// Drops `api_key`/`ApiKey` parameters from a raw query string, leaving the rest untouched.
+pub fn strip_api_key(query: &str) -> String {
+    query
+        .split('&')
+        .filter(|pair| {
+            let key = pair.split('=').next().unwrap_or_default();
+            !key.is_empty() && !key.eq_ignore_ascii_case("api_key") && !key.eq_ignore_ascii_case("apikey")
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Points a Jellyfin stream url at the given play session, replacing any `PlaySessionId` it came with.
+pub fn with_play_session_id(url: &str, play_session_id: &str) -> String {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let mut pairs: Vec<&str> = query
+        .split('&')
+        .filter(|pair| {
+            let key = pair.split('=').next().unwrap_or_default();
+            !key.is_empty() && !key.eq_ignore_ascii_case("PlaySessionId")
+        })
+        .collect();
+    let play_session = format!("PlaySessionId={}", play_session_id);
+    pairs.push(&play_session);
+    format!("{}?{}", path, pairs.join("&"))
+}
+
+/// Whether a proxied request stays below `/Videos/{vid}/` on Jellyfin. Route parameters arrive
+/// decoded, and `..` or `.` segments and backslashes would otherwise be resolved into any other API endpoint.
+pub fn is_video_file_path(vid: &str, path: &str) -> bool {
+    Uuid::parse_str(vid).is_ok()
+        && !path.contains(['\\', '?', '#'])
+        && path.split('/').all(|segment| !segment.is_empty() && segment != "." && segment != "..")
+}
+
+/// Rewrites every URI in an HLS playlist Jellyfin served for `/Videos/{video_id}/{playlist_path}`
+/// to point below `proxy_base` instead, without any `api_key`.
+pub fn rewrite_hls_playlist(playlist: &str, video_id: &str, playlist_path: &str, proxy_base: &str) -> String {
+    // Relative URIs resolve against the directory of the playlist itself
+    let dir = playlist_path.rfind('/').map_or("", |i| &playlist_path[..=i]);
+    let video_root = format!("/videos/{}/", video_id.to_ascii_lowercase());
+    let rewrite_uri = |uri: &str| -> String {
+        let (path, query) = uri.split_once('?').unwrap_or((uri, ""));
+        let path = match path.to_ascii_lowercase().find(&video_root) {
+            Some(i) => format!("{}/{}", proxy_base, &path[i + video_root.len()..]),
+            None if path.starts_with('/') || path.contains("://") => return uri.to_string(),
+            None => format!("{}/{}{}", proxy_base, dir, path),
+        };
+        match strip_api_key(query) {
+            query if query.is_empty() => path,
+            query => format!("{}?{}", path, query),
+        }
+    };
+    let mut rewritten = String::with_capacity(playlist.len());
+    for line in playlist.lines() {
+        let line = line.trim_end();
+        if line.starts_with('#') {
+            // Tags like EXT-X-MEDIA and EXT-X-MAP carry their URIs in an attribute
+            let mut rest = line;
+            while let Some(start) = rest.find("URI=\"") {
+                let start = start + "URI=\"".len();
+                let Some(len) = rest[start..].find('"') else { break };
+                rewritten.push_str(&rest[..start]);
+                rewritten.push_str(&rewrite_uri(&rest[start..start + len]));
+                rest = &rest[start + len..];
+            }
+            rewritten.push_str(rest);
+        } else if !line.is_empty() {
+            rewritten.push_str(&rewrite_uri(line));
+        }
+        rewritten.push('\n');
+    }
+    rewritten
+}
+
+/// What the headset can play directly, sent to Jellyfin as part of the device profile.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct CodecProfile {
+    /// Container groups, each one becomes a direct play profile (ex. `mp4,m4v`).
+    pub containers: Vec<String>,
+    pub video_codecs: String,
+    pub audio_codecs: String,
+    /// Codecs Jellyfin may transcode to when direct play isn't possible.
+    pub transcode_video_codecs: String,
+    pub transcode_audio_codecs: String,
+    /// Transcoding target, `hls` segments or a plain progressive `http` stream.
+    pub transcode_protocol: String,
+    pub transcode_container: String,
+    /// Ask for subtitles as WebVTT renditions in HLS manifests instead of external files.
+    pub subtitles_in_manifest: bool,
+}
+
+impl CodecProfile {
+    pub const PRESETS: &'static [&'static str] = &["default", "quest", "pico", "generic"];
+
+    pub fn preset(name: &str) -> Option<Self> {
+        let profile = |containers: &[&str], video_codecs: &str, audio_codecs: &str| Self {
+            containers: containers.iter().map(|c| c.to_string()).collect(),
+            video_codecs: video_codecs.to_string(),
+            audio_codecs: audio_codecs.to_string(),
+            transcode_video_codecs: "hevc,h264".to_string(),
+            transcode_audio_codecs: "aac,mp3,vorbis".to_string(),
+            transcode_protocol: "hls".to_string(),
+            transcode_container: "ts".to_string(),
+            subtitles_in_manifest: false,
+        };
+        match name {
+            "default" => Some(profile(
+                &["webm", "mp4,m4v", "mkv"],
+                "hevc,h264,vp8,vp9,av1",
+                "aac,mp3,opus,flac,vorbis",
+            )),
+            "quest" => Some(profile(
+                &["webm", "mp4,m4v", "mkv"],
+                "hevc,h264,vp9,av1",
+                "aac,mp3,opus,flac,vorbis",
+            )),
+            "pico" => Some(profile(
+                &["webm", "mp4,m4v", "mkv"],
+                "hevc,h264,vp9",
+                "aac,mp3,opus,flac",
+            )),
+            "generic" => Some(Self {
+                transcode_video_codecs: "h264".to_string(),
+                transcode_audio_codecs: "aac,mp3".to_string(),
+                ..profile(&["mp4,m4v"], "h264", "aac,mp3")
+            }),
+            _ => None,
+        }
     }
+
+    /// Protocols Jellyfin can transcode to, with the containers each of them supports.
+    pub const TRANSCODE_TARGETS: &'static [(&'static str, &'static [&'static str])] = &[
+        ("hls", &["ts", "mp4"]),
+        ("http", &["mkv", "mp4", "webm", "ts"]),
+    ];
+
+    pub fn is_supported_transcode_target(protocol: &str, container: &str) -> bool {
+        Self::TRANSCODE_TARGETS
+            .iter()
+            .any(|(p, containers)| *p == protocol && containers.contains(&container))
+    }
+
+    /// Whether transcodes carry the subtitles in their manifest, only HLS can.
+    pub fn embeds_subtitles(&self) -> bool {
+        self.subtitles_in_manifest && self.transcode_protocol == "hls"
+    }
+
+    /// Path (and leading query) of the stream Jellyfin serves for the configured transcoding target.
+    pub fn stream_path(&self, video_id: &str) -> String {
+        match self.transcode_protocol.as_str() {
+            "http" => format!("/Videos/{}/stream.{}?static=false", video_id, self.transcode_container),
+            _ => format!("/Videos/{}/master.m3u8?segmentContainer={}", video_id, self.transcode_container),
+        }
+    }
+}
+
+/// Device name shown in the Jellyfin session list when the headset didn't tell us what it is.
+const UNKNOWN_DEVICE: &str = "Unknown VR HMD";
+
+/// DeviceId of sessions from before each got their own, their tokens belong to it.
+const DEVICE_ID: &str = "placeholder";
+
+/// Jellyfin keys its sessions (and the tokens handed out at login) by DeviceId, so every
+/// JellyVR session gets one of its own instead of all headsets and users sharing a session.
+pub fn session_device_id(session_id: &str) -> String {
+    let digest = Sha256::digest(format!("jellyvr:{}", session_id).as_bytes());
+    format!("jellyvr-{}", &hex::encode(digest)[..32])
 }
 
-fn emby_authorization(token: Option<&str>) -> String {
+/// Socket connection Jellyfin pushes session messages through, see [`JellyfinUser::session_socket`].
+pub type SessionSocket = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+fn emby_authorization(token: Option<&str>, device: Option<&str>, device_id: Option<&str>) -> String {
     format!(
-        r#"MediaBrowser Client="jellyvr", Device="Unknown VR HMD", DeviceId="placeholder", Version="0.0.1"{}"#,
+        r#"MediaBrowser Client="jellyvr", Device="{}", DeviceId="{}", Version="0.0.1"{}"#,
+        device.unwrap_or(UNKNOWN_DEVICE).replace(['"', ',', '='], ""),
+        device_id.unwrap_or(DEVICE_ID),
         token.map_or("".to_string(), |t| format!(r#", Token="{}""#, t))
     )
 }
 
+/// Cheap to clone, every clone (and every `JellyfinUser` resumed from it) shares the config
+/// and the connection pool of the underlying `reqwest::Client`.
 #[derive(Clone)]
 pub struct JellyfinClient {
-    pub config: JellyfinConfig,
+    pub config: Arc<JellyfinConfig>,
     client: reqwest::Client,
+    limiter: Arc<Semaphore>,
 }
 
 impl JellyfinClient {
-    pub fn new(config: JellyfinConfig) -> Self {
+    /// Builds a client on top of an existing `reqwest::Client`, so several servers can share one pool.
+    pub fn new(config: JellyfinConfig, client: reqwest::Client) -> Self {
         Self {
-            config,
-            client: reqwest::Client::new(),
+            limiter: Arc::new(Semaphore::new(config.max_concurrent_requests.max(1))),
+            config: Arc::new(config),
+            client,
         }
     }
 
-    pub async fn new_quick_connect(&self) -> Result<QuickConnectSession, reqwest::Error> {
-        let url = format!("{}/QuickConnect/Initiate", self.config.base_url);
-        let response: types::QuickConnectResult = self
+    /// Waits for a free request slot, the slot is given back when the permit is dropped.
+    async fn permit(&self) -> SemaphorePermit<'_> {
+        self.limiter.acquire().await.expect("Jellyfin request limiter is never closed")
+    }
+
+    /// `reqwest::Client` tuned for talking to Jellyfin, meant to be built once and shared.
+    pub fn http_client() -> reqwest::Result<reqwest::Client> {
+        reqwest::Client::builder()
+            .pool_idle_timeout(std::time::Duration::from_secs(90))
+            .pool_max_idle_per_host(16)
+            .tcp_keepalive(std::time::Duration::from_secs(60))
+            .build()
+    }
+
+    pub async fn public_system_info(&self) -> Result<types::PublicSystemInfo, reqwest::Error> {
+        let _permit = self.permit().await;
+        let url = self.config.url("/System/Info/Public");
+        let response: types::PublicSystemInfo = self
             .client
             .get(&url)
-            .header("X-Emby-Authorization", emby_authorization(None))
             .send()
             .await?
             .error_for_status()?
             .json()
             .await?;
-        Ok(QuickConnectSession {
-            client: self.clone(),
-            secret: response.secret.expect("No secret in QuickConnectResult"),
-            code: response.code.expect("No code in QuickConnectResult"),
-        })
+        Ok(response)
     }
 
-    pub fn resume_quick_connect(&self, secret: &str, code: &str) -> QuickConnectSession {
+    pub async fn new_quick_connect(&self) -> Result<QuickConnectSession, QuickConnectError> {
+        let _permit = self.permit().await;
+        let url = self.config.url("/QuickConnect/Initiate");
+        let response = self
+            .client
+            .get(&url)
+            .header("X-Emby-Authorization", emby_authorization(None, None, None))
+            .send()
+            .await?;
+        // Jellyfin refuses to initiate with 401 when Quick Connect is turned off
+        if matches!(
+            response.status(),
+            reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN
+        ) {
+            return Err(QuickConnectError::Disabled);
+        }
+        let response: types::QuickConnectResult = response.error_for_status()?.json().await?;
+        match (response.secret, response.code) {
+            (Some(secret), Some(code)) => Ok(QuickConnectSession {
+                client: self.clone(),
+                secret,
+                code,
+                device_id: None,
+            }),
+            _ => Err(QuickConnectError::Disabled),
+        }
+    }
+
+    /// `device_id` is what the user logs in as, see `session_device_id`.
+    pub fn resume_quick_connect(&self, secret: &str, code: &str, device_id: Option<&str>) -> QuickConnectSession {
         QuickConnectSession {
             client: self.clone(),
             secret: secret.to_string(),
             code: code.to_string(),
+            device_id: device_id.map(|device_id| device_id.to_string()),
         }
     }
 
@@ -72,23 +399,61 @@ impl JellyfinClient {
             id: id.to_string(),
             token: token.to_string(),
             username: "".to_string(),
+            device: None,
+            device_id: None,
         }
     }
 }
 
+#[derive(Debug)]
+pub enum QuickConnectError {
+    /// Quick Connect is turned off on the Jellyfin server.
+    Disabled,
+    Request(reqwest::Error),
+    /// Jellyfin answered the login without something it always sends, ex. the access token.
+    IncompleteAuthentication(&'static str),
+}
+
+impl std::fmt::Display for QuickConnectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QuickConnectError::Disabled => f.write_str("Quick Connect is not enabled on the Jellyfin server"),
+            QuickConnectError::Request(err) => write!(f, "Quick Connect request failed: {}", err),
+            QuickConnectError::IncompleteAuthentication(missing) => {
+                write!(f, "Quick Connect login came back without {}", missing)
+            }
+        }
+    }
+}
+
+impl std::error::Error for QuickConnectError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            QuickConnectError::Disabled | QuickConnectError::IncompleteAuthentication(_) => None,
+            QuickConnectError::Request(err) => Some(err),
+        }
+    }
+}
+
+impl From<reqwest::Error> for QuickConnectError {
+    fn from(err: reqwest::Error) -> Self {
+        QuickConnectError::Request(err)
+    }
+}
+
 #[derive(Clone)]
 pub struct QuickConnectSession {
     client: JellyfinClient,
     pub secret: String,
     pub code: String,
+    /// DeviceId the token gets handed out for, `None` for the shared legacy one.
+    pub device_id: Option<String>,
 }
 
 impl QuickConnectSession {
     pub async fn poll(&self) -> Result<bool, reqwest::Error> {
-        let url = format!(
-            "{}/QuickConnect/Connect?Secret={}",
-            self.client.config.base_url, self.secret
-        );
+        let _permit = self.client.permit().await;
+        let url = self.client.config.url(&format!("/QuickConnect/Connect?Secret={}", self.secret));
         let response: types::QuickConnectResult = self
             .client
             .client
@@ -101,15 +466,14 @@ impl QuickConnectSession {
         Ok(response.authenticated.unwrap_or_default())
     }
 
-    pub async fn auth(&self) -> Result<JellyfinUser, reqwest::Error> {
-        let url = format!(
-            "{}/Users/AuthenticateWithQuickConnect",
-            self.client.config.base_url
-        );
+    pub async fn auth(&self) -> Result<JellyfinUser, QuickConnectError> {
+        let _permit = self.client.permit().await;
+        let url = self.client.config.url("/Users/AuthenticateWithQuickConnect");
         let response: types::AuthenticationResult = self
             .client
             .client
             .post(&url)
+            .header("X-Emby-Authorization", emby_authorization(None, None, self.device_id.as_deref()))
             .json(&types::QuickConnectDto {
                 secret: self.secret.clone(),
             })
@@ -118,26 +482,34 @@ impl QuickConnectSession {
             .error_for_status()?
             .json()
             .await?;
+        let id = response
+            .user
+            .as_ref()
+            .and_then(|user| user.id)
+            .ok_or(QuickConnectError::IncompleteAuthentication("a user id"))?
+            .to_string();
+        // Some account setups come back without a name, the id still tells users apart
+        let username = match response.user.and_then(|user| user.name) {
+            Some(name) => name,
+            None => {
+                tracing::warn!(user_id = %id, "Jellyfin returned no user name, using the user id instead");
+                id.clone()
+            }
+        };
         let user = JellyfinUser {
             client: self.client.clone(),
-            id: response
-                .user
-                .as_ref()
-                .expect("No user_id in AuthenticationResult")
-                .id
-                .expect("No id in User")
-                .to_string(),
+            id,
             token: response
                 .access_token
-                .expect("No access_token in AuthenticationResult"),
-            username: response
-                .user
-                .expect("No user in AuthenticationResult")
-                .name
-                .expect("No name in User")
-                .to_string(),
+                .ok_or(QuickConnectError::IncompleteAuthentication("an access token"))?,
+            username,
+            device: None,
+            device_id: self.device_id.clone(),
         };
-        let caps_url = format!("{}/Sessions/Capabilities/Full", self.client.config.base_url);
+        if self.client.config.read_only {
+            return Ok(user);
+        }
+        let caps_url = self.client.config.url("/Sessions/Capabilities/Full");
         self.client.client.post(&caps_url).json(&types::ClientCapabilitiesDto{
             // These don't actually seem to do anything at all...
             app_store_url: Some("https://github.com/alyti/jellyvr/".to_string()),
@@ -150,41 +522,345 @@ impl QuickConnectSession {
             supports_media_control: Some(false),
             supports_persistent_identifier: Some(false),
             supports_sync: Some(false),
-        }).header("X-Emby-Authorization", emby_authorization(Some(&user.token))).send().await?.error_for_status()?;
+        }).header("X-Emby-Authorization", user.authorization()).send().await?.error_for_status()?;
         Ok(user)
     }
 }
 
+/// Order of items returned by [`JellyfinUser::items`].
+#[derive(Debug, Clone)]
+pub struct ItemSort {
+    pub sort_by: String,
+    pub sort_order: String,
+}
+
+impl Default for ItemSort {
+    fn default() -> Self {
+        Self {
+            // Episodes are grouped by series and ordered numerically by season and episode,
+            // movies have no series or index numbers so they fall back to SortName.
+            sort_by: "SeriesSortName,ParentIndexNumber,IndexNumber,SortName,ProductionYear".to_string(),
+            sort_order: "Ascending".to_string(),
+        }
+    }
+}
+
+/// Playstate command sent to a session from another Jellyfin client, ex. the web UI's remote control.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RemoteCommand {
+    Pause,
+    Unpause,
+    PlayPause,
+    Stop,
+    /// Position in ticks.
+    Seek(i64),
+}
+
+impl RemoteCommand {
+    /// Reads a message from the session socket, anything but a playstate command we understand is `None`.
+    pub fn from_session_message(message: &str) -> Option<Self> {
+        #[derive(serde::Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct SessionMessage {
+            message_type: String,
+            #[serde(default)]
+            data: serde_json::Value,
+        }
+        let message: SessionMessage = serde_json::from_str(message).ok()?;
+        if message.message_type != "Playstate" {
+            return None;
+        }
+        match message.data.get("Command")?.as_str()? {
+            "Pause" => Some(Self::Pause),
+            "Unpause" => Some(Self::Unpause),
+            "PlayPause" => Some(Self::PlayPause),
+            "Stop" => Some(Self::Stop),
+            "Seek" => Some(Self::Seek(message.data.get("SeekPositionTicks")?.as_i64()?)),
+            _ => None,
+        }
+    }
+}
+
+/// User-specific filters for [`JellyfinUser::items`], unset ones aren't sent.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+pub struct ItemFilters {
+    pub is_played: Option<bool>,
+    pub is_favorite: Option<bool>,
+    /// Any of [`ITEM_FILTERS`].
+    pub filters: Vec<String>,
+}
+
+impl ItemFilters {
+    fn query(&self) -> Vec<(&'static str, String)> {
+        let mut query = vec![];
+        if let Some(is_played) = self.is_played {
+            query.push(("IsPlayed", is_played.to_string()));
+        }
+        if let Some(is_favorite) = self.is_favorite {
+            query.push(("IsFavorite", is_favorite.to_string()));
+        }
+        if !self.filters.is_empty() {
+            query.push(("Filters", self.filters.join(",")));
+        }
+        query
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+struct TrickplayQueryResult {
+    #[serde(default)]
+    items: Vec<TrickplayItem>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct TrickplayItem {
+    pub id: Uuid,
+    /// Trickplay resolutions keyed by media source id, then by tile width.
+    #[serde(default)]
+    pub trickplay: HashMap<String, HashMap<String, TrickplayInfo>>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct TrickplayInfo {
+    pub width: i32,
+    pub height: i32,
+    pub tile_width: i32,
+    pub tile_height: i32,
+    pub thumbnail_count: i32,
+    pub interval: i32,
+}
+
 #[derive(Clone)]
 pub struct JellyfinUser {
     client: JellyfinClient,
     pub id: String,
     pub token: String,
     pub username: String,
+    /// Headset model reported to Jellyfin, see `with_device`.
+    pub device: Option<String>,
+    /// DeviceId this user's token belongs to, see `session_device_id`.
+    pub device_id: Option<String>,
 }
 
 impl JellyfinUser {
-    pub async fn items(&self) -> Result<types::BaseItemDtoQueryResult, reqwest::Error> {
-        let url = format!("{}/Users/{}/Items", self.client.config.base_url, self.id);
-        let query: &[(&str, &str)] = &[
-            ("SortBy", "SortName,ProductionYear".into()),
-            ("SortOrder", "Ascending".into()),
-            ("IncludeItemTypes", "Movie,Episode".into()),
+    /// Reports requests as coming from `device` (ex. the headset's User-Agent) with the DeviceId
+    /// the user logged in as, so the Jellyfin session list shows each headset on its own.
+    pub fn with_device(mut self, device: Option<&str>, device_id: Option<&str>) -> Self {
+        self.device = device.map(|device| device.to_string());
+        self.device_id = device_id.map(|device_id| device_id.to_string());
+        self
+    }
+
+    fn authorization(&self) -> String {
+        emby_authorization(Some(&self.token), self.device.as_deref(), self.device_id.as_deref())
+    }
+
+    /// Whether a write has to be dropped because the server is configured read-only.
+    fn skips_write(&self, write: &str) -> bool {
+        if self.client.config.read_only {
+            tracing::debug!(write, "Read-only, not writing to Jellyfin");
+        }
+        self.client.config.read_only
+    }
+
+    /// Fetches every playable item this user can see, optionally only the ones inside one library.
+    /// Goes through `/Users/{id}/Items` so Jellyfin applies the user's library access and parental controls.
+    /// `fields` trims what Jellyfin sends along, `MediaSources` is always requested since nothing plays without it.
+    pub async fn items(
+        &self,
+        item_types: &[String],
+        fields: &[String],
+        sort: &ItemSort,
+        filters: &ItemFilters,
+        parent_id: Option<&str>,
+    ) -> Result<types::BaseItemDtoQueryResult, reqwest::Error> {
+        let _permit = self.client.permit().await;
+        let url = self.client.config.url(&format!("/Users/{}/Items", self.id));
+        let item_types = item_types.join(",");
+        let mut fields = fields.to_vec();
+        if !fields.iter().any(|field| field == "MediaSources") {
+            fields.push("MediaSources".to_string());
+        }
+        let fields = fields.join(",");
+        let mut query: Vec<(&str, &str)> = vec![
+            ("SortBy", sort.sort_by.as_str()),
+            ("SortOrder", sort.sort_order.as_str()),
+            ("IncludeItemTypes", item_types.as_str()),
             ("Recursive", "true".into()),
-            ("Fields", "DateCreated,MediaSources,BasicSyncInfo,Genres,Tags,Studios,SeriesStudio,People,Chapters".into()),
+            ("Fields", fields.as_str()),
             ("ImageTypeLimit", "1".into()),
-            ("EnableImageTypes", "Primary,Backdrop".into()),
+            ("EnableImageTypes", "Primary,Backdrop,Thumb,Banner,Logo,Art".into()),
             ("StartIndex", "0".into()),
-            ("IsMissing", "false".into())
+            ("IsMissing", "false".into()),
+            ("EnableUserData", "true".into())
         ];
+        if let Some(parent_id) = parent_id {
+            query.push(("ParentId", parent_id));
+        }
+        let response: types::BaseItemDtoQueryResult = self
+            .client
+            .client
+            .get(&url)
+            .query(&query)
+            .query(&filters.query())
+            .header(
+                "X-Emby-Authorization",
+                self.authorization(),
+            )
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(response)
+    }
+
+    /// Fetches the next unwatched episode of every series the user is watching.
+    pub async fn next_up(&self) -> Result<types::BaseItemDtoQueryResult, reqwest::Error> {
+        let _permit = self.client.permit().await;
+        let url = self.client.config.url("/Shows/NextUp");
+        let response: types::BaseItemDtoQueryResult = self
+            .client
+            .client
+            .get(&url)
+            .query(&[
+                ("UserId", self.id.as_str()),
+                ("EnableImages", "false"),
+                ("EnableUserData", "false"),
+                ("Limit", "100"),
+            ])
+            .header(
+                "X-Emby-Authorization",
+                self.authorization(),
+            )
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(response)
+    }
+
+    /// Fetches the live TV channels this user can watch.
+    pub async fn live_tv_channels(&self) -> Result<types::BaseItemDtoQueryResult, reqwest::Error> {
+        let _permit = self.client.permit().await;
+        let url = self.client.config.url("/LiveTv/Channels");
+        let response: types::BaseItemDtoQueryResult = self
+            .client
+            .client
+            .get(&url)
+            .query(&[
+                ("UserId", self.id.as_str()),
+                ("Type", "TV"),
+                ("EnableUserData", "false"),
+                ("EnableImageTypes", "Primary"),
+            ])
+            .header(
+                "X-Emby-Authorization",
+                self.authorization(),
+            )
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(response)
+    }
+
+    /// Fetches the libraries (user views) this user can see.
+    pub async fn views(&self) -> Result<types::BaseItemDtoQueryResult, reqwest::Error> {
+        let _permit = self.client.permit().await;
+        let url = self.client.config.url(&format!("/Users/{}/Views", self.id));
         let response: types::BaseItemDtoQueryResult = self
+            .client
+            .client
+            .get(&url)
+            .header(
+                "X-Emby-Authorization",
+                self.authorization(),
+            )
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(response)
+    }
+
+    /// Fetches the user's sort preference from the display preferences Jellyfin web stores.
+    /// Returns `None` if the user never picked one.
+    pub async fn sort_preference(&self) -> Result<Option<ItemSort>, reqwest::Error> {
+        let _permit = self.client.permit().await;
+        let url = self.client.config.url("/DisplayPreferences/usersettings");
+        let response: types::DisplayPreferencesDto = self
+            .client
+            .client
+            .get(&url)
+            .query(&[("userId", self.id.as_str()), ("client", "emby")])
+            .header(
+                "X-Emby-Authorization",
+                self.authorization(),
+            )
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(response
+            .sort_by
+            .filter(|sort_by| !sort_by.is_empty())
+            .map(|sort_by| ItemSort {
+                sort_by,
+                sort_order: response
+                    .sort_order
+                    .map_or("Ascending".to_string(), |order| order.to_string()),
+            }))
+    }
+
+    /// Fetches trickplay info for the same items as [`JellyfinUser::items`].
+    /// Trickplay is only available on Jellyfin 10.9+, so it's not part of the generated models.
+    pub async fn trickplay(&self, item_types: &[String]) -> Result<Vec<TrickplayItem>, reqwest::Error> {
+        let _permit = self.client.permit().await;
+        let url = self.client.config.url(&format!("/Users/{}/Items", self.id));
+        let item_types = item_types.join(",");
+        let query: &[(&str, &str)] = &[
+            ("IncludeItemTypes", item_types.as_str()),
+            ("Recursive", "true"),
+            ("Fields", "Trickplay"),
+            ("EnableImages", "false"),
+            ("EnableUserData", "false"),
+            ("IsMissing", "false"),
+        ];
+        let response: TrickplayQueryResult = self
             .client
             .client
             .get(&url)
             .query(query)
             .header(
                 "X-Emby-Authorization",
-                emby_authorization(Some(&self.token)),
+                self.authorization(),
+            )
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(response.items)
+    }
+
+    pub async fn item(&self, item: &str) -> Result<types::BaseItemDto, reqwest::Error> {
+        let _permit = self.client.permit().await;
+        let url = self.client.config.url(&format!("/Users/{}/Items/{}", self.id, item));
+        let response: types::BaseItemDto = self
+            .client
+            .client
+            .get(&url)
+            .header(
+                "X-Emby-Authorization",
+                self.authorization(),
             )
             .send()
             .await?
@@ -194,61 +870,54 @@ impl JellyfinUser {
         Ok(response)
     }
 
+    /// Playback info of an item with the device profile of `codec_profile`. With `open_live_stream`,
+    /// live TV channels get their stream opened to have a media source, leave it off when nothing is played yet.
     pub async fn playback_info(
         &self,
         item: &str,
+        open_live_stream: bool,
     ) -> Result<types::PlaybackInfoResponse, reqwest::Error> {
-        let url = format!(
-            "{}/Items/{}/PlaybackInfo",
-            self.client.config.base_url, item
-        );
+        let _permit = self.client.permit().await;
+        let url = self.client.config.url(&format!("/Items/{}/PlaybackInfo", item));
+        let codec_profile = &self.client.config.codec_profile;
         let response: types::PlaybackInfoResponse = self
             .client
             .client
             .get(&url)
-            .query(&[("UserId", &self.id)])
+            // Live TV channels only get a media source once their stream is opened
+            .query(&[("UserId", self.id.as_str()), ("AutoOpenLiveStream", if open_live_stream { "true" } else { "false" })])
             .json(&types::PlaybackInfoDto {
                 user_id: Some(Uuid::parse_str(&self.id).expect("Invalid UUID")),
                 allow_audio_stream_copy: None,
                 allow_video_stream_copy: None,
                 audio_stream_index: None,
-                auto_open_live_stream: None,
+                auto_open_live_stream: Some(open_live_stream),
                 device_profile: Some(types::DeviceProfile {
-                    direct_play_profiles: vec![
-                        types::DirectPlayProfile{
-                            container: Some("webm".to_string()),
+                    direct_play_profiles: codec_profile
+                        .containers
+                        .iter()
+                        .map(|container| types::DirectPlayProfile {
+                            container: Some(container.clone()),
                             type_: Some(types::DlnaProfileType::Video),
-                            video_codec: Some("hevc,h264,vp8,vp9,av1".to_string()),
-                            audio_codec: Some("aac,mp3,opus,flac,vorbis".to_string()),
-                        },
-                        types::DirectPlayProfile{
-                            container: Some("mp4,m4v".to_string()),
-                            type_: Some(types::DlnaProfileType::Video),
-                            video_codec: Some("hevc,h264,vp8,vp9,av1".to_string()),
-                            audio_codec: Some("aac,mp3,opus,flac,vorbis".to_string()),
-                        },
-                        types::DirectPlayProfile{
-                            container: Some("mkv".to_string()),
-                            type_: Some(types::DlnaProfileType::Video),
-                            video_codec: Some("hevc,h264,vp8,vp9,av1".to_string()),
-                            audio_codec: Some("aac,mp3,opus,flac,vorbis".to_string()),
-                        },
-                    ],
+                            video_codec: Some(codec_profile.video_codecs.clone()),
+                            audio_codec: Some(codec_profile.audio_codecs.clone()),
+                        })
+                        .collect(),
                     codec_profiles: vec![],
                     transcoding_profiles: vec![
                         TranscodingProfile{
-                            container: Some("ts".to_string()),
+                            container: Some(codec_profile.transcode_container.clone()),
                             type_: Some(types::DlnaProfileType::Video),
-                            audio_codec: Some("aac,mp3,vorbis".to_string()),
-                            video_codec: Some("hvec,h264".to_string()),
+                            audio_codec: Some(codec_profile.transcode_audio_codecs.clone()),
+                            video_codec: Some(codec_profile.transcode_video_codecs.clone()),
                             context: types::EncodingContext::Streaming,
-                            protocol: Some("hls".to_string()),
+                            protocol: Some(codec_profile.transcode_protocol.clone()),
                             max_audio_channels: Some("2".to_string()),
                             min_segments: 1,
                             break_on_non_key_frames: true,
                             conditions: vec![],
                             copy_timestamps: false,
-                            enable_subtitles_in_manifest: false,
+                            enable_subtitles_in_manifest: codec_profile.embeds_subtitles(),
                             enable_mpegts_m2_ts_mode: false,
                             estimate_content_length: true,
                             segment_length: 0,
@@ -288,7 +957,16 @@ impl JellyfinUser {
                             container: None,
                             language: None,
                         },
-                    ],
+                    ]
+                    .into_iter()
+                    .chain(codec_profile.embeds_subtitles().then(|| SubtitleProfile{
+                        format: Some("vtt".into()),
+                        method: Some(types::SubtitleDeliveryMethod::Hls),
+                        didl_mode: None,
+                        container: None,
+                        language: None,
+                    }))
+                    .collect(),
                     album_art_pn: None,
                     enable_album_art_in_didl: false,
                     enable_ms_media_receiver_registrar: false,
@@ -335,7 +1013,7 @@ impl JellyfinUser {
             })
             .header(
                 "X-Emby-Authorization",
-                emby_authorization(Some(&self.token)),
+                self.authorization(),
             )
             .send()
             .await?
@@ -345,8 +1023,80 @@ impl JellyfinUser {
         Ok(response)
     }
 
+    /// Replaces the Jellyfin tags of an item, needs a user allowed to edit metadata.
+    /// Jellyfin only updates whole items, so the item is fetched and posted back with the new tags.
+    pub async fn update_tags(&self, vid: &str, tags: Vec<String>) -> Result<(), reqwest::Error> {
+        if self.skips_write("update_tags") {
+            return Ok(());
+        }
+        let mut item = self.item(vid).await?;
+        item.tags = Some(tags);
+        let _permit = self.client.permit().await;
+        let url = self.client.config.url(&format!("/Items/{}", vid));
+        self.client.client.post(&url).json(&item).header("X-Emby-Authorization", self.authorization()).send().await?.error_for_status()?;
+        Ok(())
+    }
+
+    /// Deletes an item and its files from the server, needs a user allowed to delete content.
+    pub async fn delete_item(&self, vid: &str) -> Result<(), reqwest::Error> {
+        if self.skips_write("delete_item") {
+            return Ok(());
+        }
+        let _permit = self.client.permit().await;
+        let url = self.client.config.url(&format!("/Items/{}", vid));
+        self.client.client.delete(&url).header("X-Emby-Authorization", self.authorization()).send().await?.error_for_status()?;
+        Ok(())
+    }
+
+    pub async fn mark_played(&self, vid: &str) -> Result<(), reqwest::Error> {
+        if self.skips_write("mark_played") {
+            return Ok(());
+        }
+        let _permit = self.client.permit().await;
+        let url = self.client.config.url(&format!("/Users/{}/PlayedItems/{}", self.id, vid));
+        self.client.client.post(&url).header("X-Emby-Authorization", self.authorization()).send().await?.error_for_status()?;
+        Ok(())
+    }
+
+    pub async fn mark_unplayed(&self, vid: &str) -> Result<(), reqwest::Error> {
+        if self.skips_write("mark_unplayed") {
+            return Ok(());
+        }
+        let _permit = self.client.permit().await;
+        let url = self.client.config.url(&format!("/Users/{}/PlayedItems/{}", self.id, vid));
+        self.client.client.delete(&url).header("X-Emby-Authorization", self.authorization()).send().await?.error_for_status()?;
+        Ok(())
+    }
+
+    /// Fetches an item image, authenticated by header rather than an `api_key` in the url.
+    pub async fn image(&self, item: &str, image_type: &str, query: &str) -> Result<reqwest::Response, reqwest::Error> {
+        let _permit = self.client.permit().await;
+        let mut url = self.client.config.url(&format!("/Items/{}/Images/{}", item, image_type));
+        if !query.is_empty() {
+            url.push('?');
+            url.push_str(query);
+        }
+        self.client.client.get(&url).header("X-Emby-Authorization", self.authorization()).send().await?.error_for_status()
+    }
+
+    /// Fetches a playlist, segment or stream below `/Videos/{vid}/`, authenticated by header so
+    /// the token never ends up in a URL. The body is left to the caller to stream.
+    pub async fn video_file(&self, vid: &str, path: &str, query: &str) -> Result<reqwest::Response, reqwest::Error> {
+        let _permit = self.client.permit().await;
+        let mut url = self.client.config.url(&format!("/Videos/{}/{}", vid, path));
+        if !query.is_empty() {
+            url.push('?');
+            url.push_str(query);
+        }
+        self.client.client.get(&url).header("X-Emby-Authorization", self.authorization()).send().await?.error_for_status()
+    }
+
     pub async fn playback_start(&self, vid: &str, play_session_id: &str) -> Result<(), reqwest::Error> {
-        let url = format!("{}/Sessions/Playing", self.client.config.base_url);
+        if self.skips_write("playback_start") {
+            return Ok(());
+        }
+        let _permit = self.client.permit().await;
+        let url = self.client.config.url("/Sessions/Playing");
         self.client.client.post(&url).json(&types::PlaybackStartInfo{
             aspect_ratio: None,
             audio_stream_index: None,
@@ -368,12 +1118,16 @@ impl JellyfinUser {
             session_id: None,
             subtitle_stream_index: None,
             volume_level: None,
-        }).header("X-Emby-Authorization", emby_authorization(Some(&self.token))).send().await?.error_for_status()?;
+        }).header("X-Emby-Authorization", self.authorization()).send().await?.error_for_status()?;
         Ok(())
     }
 
     pub async fn playback_progress(&self, vid: &str, play_session_id: &str, position: i64, is_paused: bool, started_at: chrono::DateTime<Utc>) -> Result<(), reqwest::Error> {
-        let url = format!("{}/Sessions/Playing/Progress", self.client.config.base_url);
+        if self.skips_write("playback_progress") {
+            return Ok(());
+        }
+        let _permit = self.client.permit().await;
+        let url = self.client.config.url("/Sessions/Playing/Progress");
         self.client.client.post(&url).json(&types::PlaybackProgressInfo{
             item_id: Some(Uuid::parse_str(vid).expect("Invalid UUID")),
             play_session_id: Some(play_session_id.to_string()),
@@ -396,12 +1150,51 @@ impl JellyfinUser {
             session_id: None,
             subtitle_stream_index: None,
             volume_level: None,
-        }).header("X-Emby-Authorization", emby_authorization(Some(&self.token))).send().await?.error_for_status()?;
+        }).header("X-Emby-Authorization", self.authorization()).send().await?.error_for_status()?;
         Ok(())
     }
 
+    /// Tells Jellyfin this session takes playstate commands, so the web UI offers its remote control for it.
+    pub async fn enable_remote_control(&self) -> Result<(), reqwest::Error> {
+        if self.skips_write("enable_remote_control") {
+            return Ok(());
+        }
+        let _permit = self.client.permit().await;
+        let url = self.client.config.url("/Sessions/Capabilities/Full");
+        self.client.client.post(&url).json(&types::ClientCapabilitiesDto{
+            app_store_url: Some("https://github.com/alyti/jellyvr/".to_string()),
+            icon_url: Some("https://raw.githubusercontent.com/alyti/jellyvr/main/assets/images/jellyfin-jellyvr-logo.svg".to_string()),
+            device_profile: None,
+            message_callback_url: None,
+            playable_media_types: vec!["Video".to_string()],
+            supported_commands: vec![],
+            supports_content_uploading: Some(false),
+            supports_media_control: Some(true),
+            supports_persistent_identifier: Some(false),
+            supports_sync: Some(false),
+        }).header("X-Emby-Authorization", self.authorization()).send().await?.error_for_status()?;
+        Ok(())
+    }
+
+    /// Opens the socket Jellyfin sends this session's messages through, remote control commands included.
+    /// Jellyfin drops it unless a `KeepAlive` message comes in every minute or so.
+    pub async fn session_socket(&self) -> Result<SessionSocket, tokio_tungstenite::tungstenite::Error> {
+        let url = format!(
+            "{}?api_key={}&deviceId={}",
+            self.client.config.url("/socket").replacen("http", "ws", 1),
+            self.token,
+            self.device_id.as_deref().unwrap_or(DEVICE_ID)
+        );
+        let (socket, _) = tokio_tungstenite::connect_async(url).await?;
+        Ok(socket)
+    }
+
     pub async fn playback_stopped(&self, vid: &str, play_session_id: &str, position: i64) -> Result<(), reqwest::Error> {
-        let url = format!("{}/Sessions/Playing/Stopped", self.client.config.base_url);
+        if self.skips_write("playback_stopped") {
+            return Ok(());
+        }
+        let _permit = self.client.permit().await;
+        let url = self.client.config.url("/Sessions/Playing/Stopped");
         self.client.client.post(&url).json(&types::PlaybackStopInfo{
             failed: Some(false),
             item_id: Some(Uuid::parse_str(vid).expect("Invalid UUID")),
@@ -414,7 +1207,352 @@ impl JellyfinUser {
             now_playing_queue: None,
             playlist_item_id: None,
             session_id: None,
-        }).header("X-Emby-Authorization", emby_authorization(Some(&self.token))).send().await?.error_for_status()?;
+        }).header("X-Emby-Authorization", self.authorization()).send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sessions_report_their_own_device_id() {
+        let first = session_device_id("first");
+        let second = session_device_id("second");
+        assert_ne!(first, second);
+        assert_eq!(first, session_device_id("first"));
+        let authorization = emby_authorization(Some("token"), Some("Quest 3"), Some(&first));
+        assert!(authorization.contains(&format!(r#"DeviceId="{}""#, first)));
+        assert!(authorization.contains(r#"Device="Quest 3""#));
+        // Sessions from before keep the DeviceId their token was handed out for
+        assert!(emby_authorization(Some("token"), None, None).contains(&format!(r#"DeviceId="{}""#, DEVICE_ID)));
+    }
+
+    #[test]
+    fn device_names_cant_break_out_of_the_header() {
+        let authorization = emby_authorization(None, Some(r#"Quest", DeviceId="other, x=y"#), Some("id"));
+        assert_eq!(authorization.matches("DeviceId=").count(), 1);
+        assert!(authorization.contains(r#"Device="Quest DeviceIdother xy""#));
+    }
+
+    const VID: &str = "0123456789abcdef0123456789abcdef";
+
+    #[test]
+    fn video_file_paths_stay_below_the_video() {
+        assert!(is_video_file_path(VID, "main.m3u8"));
+        assert!(is_video_file_path(VID, "hls1/main/0.ts"));
+        assert!(!is_video_file_path("../Users", "main.m3u8"));
+        assert!(!is_video_file_path(VID, "../../Users/abc/Items"));
+        assert!(!is_video_file_path(VID, "hls1/./main.m3u8"));
+        assert!(!is_video_file_path(VID, "/Users/abc/Items"));
+        assert!(!is_video_file_path(VID, "hls1//main.m3u8"));
+        assert!(!is_video_file_path(VID, "..\\..\\Users"));
+        assert!(!is_video_file_path(VID, "main.m3u8?api_key=x"));
+    }
+
+    #[test]
+    fn hls_playlists_point_at_the_proxy_without_tokens() {
+        let proxy = format!("https://jellyvr/heresphere/hls/sid/{}", VID);
+        let playlist = format!(
+            "#EXTM3U\n#EXT-X-MEDIA:TYPE=SUBTITLES,URI=\"subs/0.m3u8?api_key=secret&x=1\"\nmain.m3u8?api_key=secret&PlaySessionId=abc\n/Videos/{}/hls1/main/0.ts?ApiKey=secret\n",
+            VID.to_ascii_uppercase()
+        );
+        let rewritten = rewrite_hls_playlist(&playlist, VID, "master.m3u8", &proxy);
+        assert!(!rewritten.contains("secret"));
+        assert_eq!(
+            rewritten.lines().collect::<Vec<_>>(),
+            [
+                "#EXTM3U".to_string(),
+                format!("#EXT-X-MEDIA:TYPE=SUBTITLES,URI=\"{}/subs/0.m3u8?x=1\"", proxy),
+                format!("{}/main.m3u8?PlaySessionId=abc", proxy),
+                format!("{}/hls1/main/0.ts", proxy),
+            ]
+        );
+        // Nested playlists resolve relative URIs against their own directory
+        assert_eq!(rewrite_hls_playlist("0.ts\n", VID, "hls1/main.m3u8", &proxy), format!("{}/hls1/0.ts\n", proxy));
+    }
+
+    #[test]
+    fn stream_urls_get_the_play_session_as_a_parameter() {
+        let url = format!("/videos/{}/master.m3u8?DeviceId=x&playsessionid=old&api_key=token", VID);
+        assert_eq!(
+            with_play_session_id(&url, "new"),
+            format!("/videos/{}/master.m3u8?DeviceId=x&api_key=token&PlaySessionId=new", VID)
+        );
+        // Not a substring replace, an id that happens to show up elsewhere in the url stays put
+        assert_eq!(with_play_session_id("/stream?MediaSourceId=abc&PlaySessionId=abc", "new"), "/stream?MediaSourceId=abc&PlaySessionId=new");
+        assert_eq!(with_play_session_id("/stream", "new"), "/stream?PlaySessionId=new");
+    }
+
+    #[test]
+    fn api_paths_keep_the_base_path() {
+        let config = |base_url: &str| JellyfinConfig::new(base_url.to_string(), CodecProfile::preset("default").unwrap(), 1, false);
+        assert_eq!(config("https://host/jellyfin/").url("/Items"), "https://host/jellyfin/Items");
+        assert_eq!(config(" https://host/jellyfin ").url("Items"), "https://host/jellyfin/Items");
+        assert_eq!(config("http://host:8096").url("/System/Info/Public"), "http://host:8096/System/Info/Public");
+    }
+
+    #[test]
+    fn versions_are_compared_numerically() {
+        assert!(is_older_than_spec("10.7.7"));
+        assert!(is_older_than_spec("10.8.9"));
+        assert!(!is_older_than_spec(SPEC_VERSION));
+        assert!(!is_older_than_spec("10.8.100"));
+        assert!(!is_older_than_spec("10.10.0"));
+    }
+
+    #[test]
+    fn parental_ratings_map_to_levels() {
+        assert_eq!(parental_rating_level("PG-13"), Some(7));
+        assert_eq!(parental_rating_level(" us-tv-ma "), Some(9));
+        assert_eq!(parental_rating_level("R"), parental_rating_level("US-R"));
+        assert_eq!(parental_rating_level("NR"), None);
+        assert_eq!(parental_rating_level("DE-16"), None);
+        assert_eq!(parental_rating_level(""), None);
+    }
+
+    /// Device profile JellyVR sends when asking for the playback info of a video with `codec_profile`.
+    async fn outgoing_device_profile(codec_profile: CodecProfile) -> eyre::Result<serde_json::Value> {
+        let bodies: Arc<std::sync::Mutex<Vec<serde_json::Value>>> = Default::default();
+        let captured = bodies.clone();
+        let routes = axum::Router::new().route(
+            "/Items/:id/PlaybackInfo",
+            axum::routing::any(move |axum::Json(body): axum::Json<serde_json::Value>| {
+                let captured = captured.clone();
+                async move {
+                    captured.lock().expect("Bodies poisoned").push(body);
+                    axum::Json(serde_json::json!({ "MediaSources": [] }))
+                }
+            }),
+        );
+        let (url, _) = crate::tests::mock_jellyfin(routes).await?;
+        let client = JellyfinClient::new(JellyfinConfig::new(url, codec_profile, 1, false), reqwest::Client::new());
+        client.resume_user(crate::tests::USER_ID, "token").playback_info(VID, false).await?;
+        let body = bodies.lock().expect("Bodies poisoned").pop().expect("No PlaybackInfo request");
+        Ok(body["DeviceProfile"].clone())
+    }
+
+    #[tokio::test]
+    async fn hevc_is_a_transcoding_target() -> eyre::Result<()> {
+        let profile = outgoing_device_profile(CodecProfile::preset("default").unwrap()).await?;
+        assert_eq!(profile["TranscodingProfiles"][0]["VideoCodec"], "hevc,h264");
+        // Jellyfin matches codecs by name, the misspelled one never matched anything
+        assert!(!profile.to_string().contains("hvec"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn presets_shape_the_device_profile() -> eyre::Result<()> {
+        let containers = |profile: &serde_json::Value| {
+            profile["DirectPlayProfiles"]
+                .as_array()
+                .map(|profiles| profiles.iter().map(|profile| profile["Container"].clone()).collect::<Vec<_>>())
+        };
+        let generic = outgoing_device_profile(CodecProfile::preset("generic").unwrap()).await?;
+        assert_eq!(generic["TranscodingProfiles"][0]["VideoCodec"], "h264");
+        assert_eq!(containers(&generic), Some(vec![serde_json::json!("mp4,m4v")]));
+        assert_eq!(generic["DirectPlayProfiles"][0]["VideoCodec"], "h264");
+
+        let default = outgoing_device_profile(CodecProfile::preset("default").unwrap()).await?;
+        assert_eq!(
+            containers(&default),
+            Some(vec![serde_json::json!("webm"), serde_json::json!("mp4,m4v"), serde_json::json!("mkv")])
+        );
+        assert!(CodecProfile::preset("toaster").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn stream_path_follows_the_transcoding_target() {
+        let target = |protocol: &str, container: &str| CodecProfile {
+            transcode_protocol: protocol.to_string(),
+            transcode_container: container.to_string(),
+            ..CodecProfile::preset("default").unwrap()
+        };
+        assert_eq!(target("hls", "ts").stream_path(VID), format!("/Videos/{}/master.m3u8?segmentContainer=ts", VID));
+        assert_eq!(target("hls", "mp4").stream_path(VID), format!("/Videos/{}/master.m3u8?segmentContainer=mp4", VID));
+        assert_eq!(target("http", "mkv").stream_path(VID), format!("/Videos/{}/stream.mkv?static=false", VID));
+        assert_eq!(target("http", "webm").stream_path(VID), format!("/Videos/{}/stream.webm?static=false", VID));
+    }
+
+    #[tokio::test]
+    async fn clones_share_the_connection_pool() -> eyre::Result<()> {
+        // Remembers the client port of every request, requests over the same connection share one
+        let ports: Arc<std::sync::Mutex<Vec<u16>>> = Default::default();
+        let seen = ports.clone();
+        let app = axum::Router::new().route(
+            "/System/Info/Public",
+            axum::routing::get(
+                move |axum::extract::ConnectInfo(peer): axum::extract::ConnectInfo<std::net::SocketAddr>| {
+                    let seen = seen.clone();
+                    async move {
+                        seen.lock().expect("Ports poisoned").push(peer.port());
+                        axum::Json(serde_json::json!({ "ServerName": "Jellyfin" }))
+                    }
+                },
+            ),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service_with_connect_info::<std::net::SocketAddr>()).await
+        });
+        let config = || JellyfinConfig::new(url.clone(), CodecProfile::preset("default").unwrap(), 1, false);
+
+        let client = JellyfinClient::new(config(), JellyfinClient::http_client()?);
+        let clone = client.clone();
+        client.public_system_info().await?;
+        clone.public_system_info().await?;
+        // A client of its own opens its own connection
+        JellyfinClient::new(config(), JellyfinClient::http_client()?).public_system_info().await?;
+
+        let ports = ports.lock().expect("Ports poisoned").clone();
+        assert_eq!(ports.len(), 3);
+        assert_eq!(ports[0], ports[1]);
+        assert_ne!(ports[0], ports[2]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn requests_in_flight_are_limited() -> eyre::Result<()> {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let in_flight: Arc<AtomicUsize> = Default::default();
+        let most: Arc<AtomicUsize> = Default::default();
+        let (counting, highest) = (in_flight.clone(), most.clone());
+        let routes = axum::Router::new().route(
+            "/System/Info/Public",
+            axum::routing::get(move || {
+                let (counting, highest) = (counting.clone(), highest.clone());
+                async move {
+                    let now = counting.fetch_add(1, Ordering::SeqCst) + 1;
+                    highest.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                    counting.fetch_sub(1, Ordering::SeqCst);
+                    axum::Json(serde_json::json!({ "ServerName": "Jellyfin" }))
+                }
+            }),
+        );
+        let (url, calls) = crate::tests::mock_jellyfin(routes).await?;
+        let client = JellyfinClient::new(
+            JellyfinConfig::new(url, CodecProfile::preset("default").unwrap(), 2, false),
+            JellyfinClient::http_client()?,
+        );
+
+        let results = futures::future::join_all((0..6).map(|_| client.public_system_info())).await;
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(crate::tests::calls_to(&calls, "GET /System/Info/Public"), 6);
+        assert_eq!(most.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn ticks_convert_to_milliseconds_and_back() {
+        assert_eq!(ms_to_ticks(0.0), 0);
+        assert_eq!(ms_to_ticks(1.0), 10_000);
+        assert_eq!(ms_to_ticks(90_000.0), 900_000_000);
+        // HereSphere sends fractional milliseconds, ticks are rounded
+        assert_eq!(ms_to_ticks(1.23456), 12_346);
+        assert_eq!(ticks_to_ms(10_000), 1.0);
+        assert_eq!(ticks_to_ms(900_000_000), 90_000.0);
+        assert_eq!(ticks_to_ms(5_000), 0.5);
+        assert_eq!(ms_to_ticks(ticks_to_ms(123_456_789)), 123_456_789);
+    }
+
+    #[test]
+    fn item_filters_only_add_what_is_set() {
+        let query = |is_played: Option<bool>, is_favorite: Option<bool>, filters: &[&str]| {
+            ItemFilters { is_played, is_favorite, filters: filters.iter().map(|filter| filter.to_string()).collect() }.query()
+        };
+        let pairs = |pairs: &[(&'static str, &str)]| pairs.iter().map(|(key, value)| (*key, value.to_string())).collect::<Vec<_>>();
+        assert!(query(None, None, &[]).is_empty());
+        assert_eq!(query(Some(false), None, &[]), pairs(&[("IsPlayed", "false")]));
+        assert_eq!(query(None, Some(true), &[]), pairs(&[("IsFavorite", "true")]));
+        assert_eq!(
+            query(Some(true), Some(false), &["IsResumable", "Likes"]),
+            pairs(&[("IsPlayed", "true"), ("IsFavorite", "false"), ("Filters", "IsResumable,Likes")])
+        );
+    }
+
+    #[test]
+    fn playstate_messages_become_commands() {
+        let message = |data: serde_json::Value| {
+            RemoteCommand::from_session_message(&serde_json::json!({ "MessageType": "Playstate", "Data": data }).to_string())
+        };
+        assert_eq!(message(serde_json::json!({ "Command": "Pause" })), Some(RemoteCommand::Pause));
+        assert_eq!(message(serde_json::json!({ "Command": "Unpause" })), Some(RemoteCommand::Unpause));
+        assert_eq!(message(serde_json::json!({ "Command": "PlayPause" })), Some(RemoteCommand::PlayPause));
+        assert_eq!(message(serde_json::json!({ "Command": "Stop" })), Some(RemoteCommand::Stop));
+        assert_eq!(
+            message(serde_json::json!({ "Command": "Seek", "SeekPositionTicks": 600_000_000 })),
+            Some(RemoteCommand::Seek(600_000_000))
+        );
+        assert_eq!(message(serde_json::json!({ "Command": "Seek" })), None);
+        assert_eq!(message(serde_json::json!({ "Command": "NextTrack" })), None);
+        assert_eq!(RemoteCommand::from_session_message(r#"{"MessageType":"KeepAlive"}"#), None);
+        assert_eq!(RemoteCommand::from_session_message("not json"), None);
+    }
+
+    /// Quick Connect login against a Jellyfin answering the authentication with `result`.
+    async fn quick_connect_login(result: serde_json::Value) -> eyre::Result<Result<JellyfinUser, QuickConnectError>> {
+        let routes = axum::Router::new()
+            .route(
+                "/Users/AuthenticateWithQuickConnect",
+                axum::routing::post(move || async move { axum::Json(result) }),
+            )
+            .route(
+                "/Sessions/Capabilities/Full",
+                axum::routing::post(|| async { axum::http::StatusCode::NO_CONTENT }),
+            );
+        let (url, _) = crate::tests::mock_jellyfin(routes).await?;
+        let client = JellyfinClient::new(
+            JellyfinConfig::new(url, CodecProfile::preset("default").unwrap(), 1, false),
+            reqwest::Client::new(),
+        );
+        Ok(client.resume_quick_connect("secret", "123456", None).auth().await)
+    }
+
+    /// Starts Quick Connect against a Jellyfin answering the initiation with `status` and `result`.
+    async fn quick_connect_initiate(
+        status: axum::http::StatusCode,
+        result: serde_json::Value,
+    ) -> eyre::Result<Result<QuickConnectSession, QuickConnectError>> {
+        let routes = axum::Router::new().route(
+            "/QuickConnect/Initiate",
+            axum::routing::get(move || async move { (status, axum::Json(result)) }),
+        );
+        let (url, _) = crate::tests::mock_jellyfin(routes).await?;
+        let client = JellyfinClient::new(
+            JellyfinConfig::new(url, CodecProfile::preset("default").unwrap(), 1, false),
+            reqwest::Client::new(),
+        );
+        Ok(client.new_quick_connect().await)
+    }
+
+    #[tokio::test]
+    async fn quick_connect_without_a_secret_is_disabled() -> eyre::Result<()> {
+        let ok = axum::http::StatusCode::OK;
+        let session = quick_connect_initiate(ok, serde_json::json!({ "Secret": "secret", "Code": "123456" })).await??;
+        assert_eq!(session.code, "123456");
+        let missing = quick_connect_initiate(ok, serde_json::json!({ "Code": "123456" })).await?;
+        assert!(matches!(missing, Err(QuickConnectError::Disabled)));
+        let refused = quick_connect_initiate(axum::http::StatusCode::UNAUTHORIZED, serde_json::json!({})).await?;
+        assert!(matches!(refused, Err(QuickConnectError::Disabled)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn users_without_a_name_still_log_in() -> eyre::Result<()> {
+        let id = Uuid::from_u128(1);
+        let user = quick_connect_login(serde_json::json!({ "User": { "Id": id }, "AccessToken": "token" })).await??;
+        assert_eq!(user.id, id.to_string());
+        assert_eq!(user.username, id.to_string());
+        assert_eq!(user.token, "token");
+
+        let incomplete = quick_connect_login(serde_json::json!({ "User": { "Id": id, "Name": "alice" } })).await?;
+        assert!(matches!(incomplete, Err(QuickConnectError::IncompleteAuthentication(_))));
+        let incomplete = quick_connect_login(serde_json::json!({ "AccessToken": "token" })).await?;
+        assert!(matches!(incomplete, Err(QuickConnectError::IncompleteAuthentication(_))));
         Ok(())
     }
 }
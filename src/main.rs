@@ -8,34 +8,42 @@ use axum::{
     async_trait,
     body::{Body, Bytes},
     extract::{
-        FromRef, FromRequest, FromRequestParts, Host, MatchedPath, Path, Request as ExtractRequest,
-        State,
+        FromRef, FromRequest, FromRequestParts, Host, MatchedPath, Path, Query, RawQuery,
+        Request as ExtractRequest, State,
     },
     http::{request::Parts, HeaderMap, Request, StatusCode},
-    response::{Html, IntoResponse, Response},
+    response::{Html, IntoResponse, Redirect, Response},
     routing::{get, post},
-    Json, Router,
+    Form, Json, Router,
 };
 use axum_embed::ServeEmbed;
-use axum_extra::extract::cookie::{Cookie, CookieJar};
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
 use http_body_util::BodyExt;
 use listenfd::ListenFd;
 use rust_embed::RustEmbed;
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
+use std::{collections::HashMap, time::Duration};
 use surrealdb::{
     engine::local::{Db, RocksDb},
     Surreal,
 };
 use tokio::{net::TcpListener, signal};
-use tower_http::{trace::TraceLayer, timeout::TimeoutLayer};
+use tower_http::{compression::CompressionLayer, trace::TraceLayer, timeout::TimeoutLayer};
 use tracing::{info_span, Span};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use eyre::WrapErr;
+use futures::{StreamExt, TryStreamExt};
 
+mod admin;
+mod debug;
 mod heresphere;
 mod index;
 mod jellyfin;
+mod migrate;
+#[cfg(feature = "openapi")]
+mod openapi;
+mod remote;
+mod template;
 
 #[tokio::main]
 async fn main() -> eyre::Result<()> {
@@ -52,42 +60,196 @@ async fn main() -> eyre::Result<()> {
         .init();
 
     // Create database connection
-    let db = Surreal::new::<RocksDb>(".jellyvr-db").await?;
-    db.use_ns("jellyvr").use_db("jellyvr").await?;
+    let database = Database::open(".jellyvr-db").await?;
+    let db = database.get();
+    migrate::run(&db).await?;
+    let image_key = image_key(&db).await?;
 
     // Sorry it's mostly hardcoded for now
-    let config = AppConfig {
-        jellyfin_api_host: std::env::var("JELLYFIN_HOST").wrap_err("JELLYFIN_HOST not set")?,
-        jellyfin_remote_host: std::env::var("JELLYFIN_REMOTE_HOST").or(std::env::var("JELLYFIN_HOST")).wrap_err("JELLYFIN_HOST not set")?,
+    let mut config = AppConfig {
+        jellyfin_servers: jellyfin_servers_from_env()?,
         cache_lifetime: Duration::from_secs(60 * 5), // 5 minutes for now
+        cache_retention: Duration::from_secs(env_or("JELLYVR_CACHE_RETENTION_SECS", 7 * 24 * 60 * 60)?),
+        cache_vacuum_interval: Duration::from_secs(env_or("JELLYVR_CACHE_VACUUM_INTERVAL_SECS", 24 * 60 * 60)?),
         prefered_subtitles_language: Some("eng".to_string()),
-        watchtime_tracking: true, // Doesn't do anything rn anyway
+        watchtime_tracking: env_or("JELLYVR_WATCHTIME_TRACKING", true)?,
+        read_only: env_or("JELLYVR_READONLY", false)?,
+        progress_interval: Duration::from_secs(env_or("JELLYVR_PROGRESS_INTERVAL_SECS", 30)?),
+        codec_profile: codec_profile_from_env()?,
+        hls_proxy: env_or("JELLYVR_HLS_PROXY", true)?,
+        image_proxy: env_or("JELLYVR_IMAGE_PROXY", false)?,
+        max_concurrent_requests: env_or("JELLYVR_MAX_CONCURRENT_REQUESTS", 8)?,
+        completion_threshold: env_or("JELLYVR_COMPLETION_THRESHOLD", 0.9)?,
+        resume_rewind: Duration::from_secs(env_or("JELLYVR_RESUME_REWIND_SECS", 5)?),
+        max_playbacks_per_user: env_or("JELLYVR_MAX_PLAYBACKS_PER_USER", 2)?,
+        remote_control: env_or("JELLYVR_REMOTE_CONTROL", false)?,
+        item_types: match env_list("JELLYVR_ITEM_TYPES") {
+            item_types if item_types.is_empty() => vec!["Movie".to_string(), "Episode".to_string()],
+            item_types => item_types,
+        },
+        prefetch_playback_info: env_or("JELLYVR_PREFETCH_PLAYBACK_INFO", false)?,
+        prefetch_playback_info_ttl: Duration::from_secs(env_or("JELLYVR_PREFETCH_PLAYBACK_INFO_TTL_SECS", 300)?),
+        item_fields: match env_list("JELLYVR_ITEM_FIELDS") {
+            fields if fields.is_empty() => jellyfin::DEFAULT_ITEM_FIELDS.iter().map(|field| field.to_string()).collect(),
+            fields => fields,
+        },
+        item_filters: jellyfin::ItemFilters {
+            is_played: env_opt("JELLYVR_IS_PLAYED")?,
+            is_favorite: env_opt("JELLYVR_IS_FAVORITE")?,
+            filters: env_list("JELLYVR_ITEM_FILTERS"),
+        },
+        include_libraries: env_list("JELLYVR_INCLUDE_LIBRARIES"),
+        exclude_libraries: env_list("JELLYVR_EXCLUDE_LIBRARIES"),
+        min_rating: env_opt("JELLYVR_MIN_RATING")?,
+        unrated_passes_min_rating: env_or("JELLYVR_MIN_RATING_ALLOW_UNRATED", true)?,
+        restricted_ratings: env_list("JELLYVR_RESTRICTED_RATINGS"),
+        restricted_tags: env_list("JELLYVR_RESTRICTED_TAGS"),
+        max_official_rating: match env_opt::<String>("JELLYVR_MAX_OFFICIAL_RATING")? {
+            Some(rating) if jellyfin::parental_rating_level(&rating).is_none() => eyre::bail!(
+                "JELLYVR_MAX_OFFICIAL_RATING is invalid: {}, expected one of {}",
+                rating,
+                jellyfin::PARENTAL_RATINGS.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(", ")
+            ),
+            rating => rating,
+        },
+        restrict_unrated: env_or("JELLYVR_RESTRICT_UNRATED", false)?,
+        recently_added_count: env_or("JELLYVR_RECENTLY_ADDED_COUNT", 50)?,
+        continue_watching: env_or("JELLYVR_CONTINUE_WATCHING", true)?,
+        next_up: env_or("JELLYVR_NEXT_UP", true)?,
+        collapse_series: env_or("JELLYVR_COLLAPSE_SERIES", false)?,
+        browse_by_year: env_choice("JELLYVR_BROWSE_BY_YEAR", "none", &["none", "year", "decade"])?,
+        browse_by: env_list("JELLYVR_BROWSE_BY"),
+        browse_by_min_videos: env_or("JELLYVR_BROWSE_BY_MIN_VIDEOS", 2)?,
+        browse_by_images: env_or("JELLYVR_BROWSE_BY_IMAGES", false)?,
+        live_tv: env_or("JELLYVR_LIVE_TV", false)?,
+        enable_trickplay: env_or("JELLYVR_ENABLE_TRICKPLAY", false)?,
+        default_projection: env_choice("JELLYVR_DEFAULT_PROJECTION", "perspective", heresphere::PROJECTIONS)?,
+        default_stereo: env_choice("JELLYVR_DEFAULT_STEREO", "mono", heresphere::STEREO_MODES)?,
+        image_type: match std::env::var("JELLYVR_IMAGE_TYPE") {
+            Ok(_) => Some(env_choice("JELLYVR_IMAGE_TYPE", "Primary", jellyfin::IMAGE_TYPES)?),
+            Err(_) => None,
+        },
+        image_types: image_types_from_env()?,
+        lens_presets: lens_presets_from_env()?,
+        projection_fov: projection_fovs_from_env()?,
+        camera_ipd: env_opt("JELLYVR_CAMERA_IPD")?,
+        stereo_markers: stereo_markers_from_env()?,
+        format_tags: env_or("JELLYVR_FORMAT_TAGS", false)?,
+        technical_details: env_choice("JELLYVR_TECHNICAL_DETAILS", "none", &["none", "description", "tags"])?,
+        eye_swap_markers: match env_list("JELLYVR_EYE_SWAP_MARKERS") {
+            markers if markers.is_empty() => vec!["RL".to_string(), "SWAPPED".to_string()],
+            markers => markers,
+        },
+        lens_camera_ipd: env_map("JELLYVR_LENS_CAMERA_IPD")?,
+        skip_generic_chapters: env_or("JELLYVR_SKIP_GENERIC_CHAPTERS", false)?,
+        skip_broken_media: env_or("JELLYVR_SKIP_BROKEN_MEDIA", true)?,
+        forced_subtitles: env_or("JELLYVR_FORCED_SUBTITLES", true)?,
+        sdh_subtitles: env_or("JELLYVR_SDH_SUBTITLES", true)?,
+        label_parts: env_or("JELLYVR_LABEL_PARTS", true)?,
+        tag_tracks: match env_map("JELLYVR_TAG_TRACKS")? {
+            tag_tracks if tag_tracks.is_empty() => default_tag_tracks(),
+            tag_tracks => tag_tracks,
+        },
+        tag_blocklist: env_list("JELLYVR_TAG_BLOCKLIST"),
+        episode_title_template: match env_or("JELLYVR_EPISODE_TITLE", index::DEFAULT_EPISODE_TITLE_TEMPLATE.to_string())? {
+            template if index::is_valid_episode_title_template(&template) => template,
+            template => {
+                tracing::warn!(
+                    template = %template,
+                    "JELLYVR_EPISODE_TITLE only knows {{{}}}, using the default",
+                    index::EPISODE_TITLE_PLACEHOLDERS.join("}, {")
+                );
+                index::DEFAULT_EPISODE_TITLE_TEMPLATE.to_string()
+            }
+        },
+        tag_colon_replacement: env_or("JELLYVR_TAG_COLON_REPLACEMENT", "\u{A789}".to_string())?,
+        max_people: env_opt("JELLYVR_MAX_PEOPLE")?,
+        write_tags: env_or("JELLYVR_WRITE_TAGS", false)?,
+        write_played: env_or("JELLYVR_WRITE_PLAYED", false)?,
+        soft_delete: env_or("JELLYVR_SOFT_DELETE", false)?,
+        warm_cache_on_start: env_or("JELLYVR_WARM_CACHE_ON_START", false)?,
+        scan_chunk_size: env_or("JELLYVR_SCAN_CHUNK_SIZE", 1000)?,
+        scan_concurrency: env_or("JELLYVR_SCAN_CONCURRENCY", 4)?,
+        pretty_json: env_or("JELLYVR_PRETTY_JSON", false)?,
+        debug_endpoints: env_or("JELLYVR_DEBUG_ENDPOINTS", false)?,
+        strict_startup: env_or("JELLYVR_STRICT_STARTUP", false)?,
+        admin_token: std::env::var("JELLYVR_ADMIN_TOKEN").ok().map(Secret),
+        status_pin: std::env::var("JELLYVR_STATUS_PIN").ok().map(Secret),
+        login_max_failures: env_or("JELLYVR_LOGIN_MAX_FAILURES", 5)?,
+        login_lockout: Duration::from_secs(env_or("JELLYVR_LOGIN_LOCKOUT_SECS", 300)?),
+        cookie_secure: env_opt("JELLYVR_COOKIE_SECURE")?,
+        cookie_http_only: env_or("JELLYVR_COOKIE_HTTP_ONLY", true)?,
+        cookie_same_site: env_choice("JELLYVR_COOKIE_SAME_SITE", "lax", &["lax", "strict", "none"])?,
     };
+    if let Some(filter) = config
+        .item_filters
+        .filters
+        .iter()
+        .find(|filter| !jellyfin::ITEM_FILTERS.contains(&filter.as_str()))
+    {
+        eyre::bail!(
+            "JELLYVR_ITEM_FILTERS is invalid: {}, expected any of {}",
+            filter,
+            jellyfin::ITEM_FILTERS.join(", ")
+        );
+    }
+
+    if config.write_tags && config.tag_colon_replacement.is_empty() {
+        // Dropped colons can't be put back, editing a tag would rewrite `a:b` as `ab` in Jellyfin
+        tracing::warn!("JELLYVR_WRITE_TAGS needs a JELLYVR_TAG_COLON_REPLACEMENT, not writing tags back");
+        config.write_tags = false;
+    }
+    if let Some(kind) = config.browse_by.iter().find(|kind| !index::BROWSE_BY.contains(&kind.as_str())) {
+        eyre::bail!(
+            "JELLYVR_BROWSE_BY is invalid: {}, expected any of {}",
+            kind,
+            index::BROWSE_BY.join(", ")
+        );
+    }
 
     tracing::info!(config = ?config, "Loaded config");
 
+    // One pooled HTTP client for every server and user, so connections to Jellyfin get reused
+    let http_client = jellyfin::JellyfinClient::http_client()?;
     let app_state = AppState {
-        jellyfin: JellyfinState {
-            client: jellyfin::JellyfinClient::new(jellyfin::JellyfinConfig::new(
-                config.jellyfin_api_host.clone(),
-            )),
-        },
-        db: db.clone(),
+        jellyfin: JellyfinState::new(&config, &http_client),
+        db: database,
         config,
+        login_throttle: LoginThrottle::default(),
+        remote_listeners: remote::RemoteListeners::default(),
+        image_key,
     };
 
-    let heresphere_api = Router::new()
-        .route("/", post(heresphere_libraries))
-        .route("/scan", post(heresphere_scan))
-        .route("/:id", post(heresphere_video))
-        .route("/events/:sid/:vid", post(heresphere_event));
+    // Check that the Jellyfin servers are there before accepting any requests
+    for server in &app_state.config.jellyfin_servers {
+        match app_state.jellyfin.server(&server.name)?.client.public_system_info().await {
+            Ok(info) => {
+                let version = info.version.unwrap_or_default();
+                tracing::info!(
+                    server = %server.name,
+                    server_name = ?info.server_name,
+                    version = &version,
+                    "Connected to Jellyfin"
+                );
+                if jellyfin::is_older_than_spec(&version) {
+                    tracing::warn!(
+                        server = %server.name,
+                        version = &version,
+                        spec_version = jellyfin::SPEC_VERSION,
+                        "Jellyfin server is older than the API version JellyVR was built against"
+                    );
+                }
+            }
+            Err(e) if app_state.config.strict_startup => {
+                return Err(e).wrap_err(JellyVrError::JellyfinUnavailable(server.api_host.clone()));
+            }
+            Err(e) => {
+                tracing::warn!(server = %server.name, error = ?e, "Jellyfin server is unreachable, continuing anyway");
+            }
+        }
+    }
 
-    let app = Router::new()
-        .route("/", get(root))
-        .route("/health", get(|| async { "OK" } ))
-        .nest("/heresphere", heresphere_api)
-        .nest_service("/assets", ServeEmbed::<Assets>::new())
-        // .route("/heresphere/scan", post(heresphere_scan))
+    let app = app_router()
         .with_state(app_state.clone())
         .layer((
             TraceLayer::new_for_http()
@@ -119,17 +281,47 @@ async fn main() -> eyre::Result<()> {
         None => TcpListener::bind("0.0.0.0:3000").await?,
     };
 
-    // start a background task that updates the progress of the current playback
-    tokio::spawn(async move {
-        let app_state_local = app_state.clone();
-        let mut interval = tokio::time::interval(Duration::from_secs(30));
-        loop {
-            interval.tick().await;
-            if let Err(e) = progress_update_routine(&app_state_local).await {
-                tracing::error!(error = ?e, "Failed to update progress");
+    if app_state.config.warm_cache_on_start {
+        let app_state = app_state.clone();
+        tokio::spawn(async move {
+            match index::HeresphereIndex::reindex_all(&app_state, app_state.config.max_concurrent_requests).await {
+                Ok(summary) => tracing::info!(summary = ?summary, "Warmed cache"),
+                Err(e) => tracing::error!(error = ?e, "Failed to warm cache"),
             }
-        }
-    });
+        });
+    }
+
+    {
+        let database = app_state.db.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(DB_HEALTH_INTERVAL);
+            loop {
+                interval.tick().await;
+                match database.reopen_if_lost().await {
+                    Ok(true) => tracing::warn!("Lost the database, reopened it"),
+                    Ok(false) => {}
+                    Err(e) => tracing::error!(error = ?e, "Lost the database and failed to reopen it"),
+                }
+            }
+        });
+    }
+
+    if !app_state.config.cache_vacuum_interval.is_zero() {
+        let app_state = app_state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(app_state.config.cache_vacuum_interval);
+            loop {
+                interval.tick().await;
+                match index::HeresphereIndex::vacuum(&app_state, false).await {
+                    Ok(summary) => tracing::info!(summary = ?summary, "Vacuumed cache"),
+                    Err(e) => tracing::error!(error = ?e, "Failed to vacuum cache"),
+                }
+            }
+        });
+    }
+
+    // start a background task that updates the progress of the current playback
+    spawn_progress_updates(app_state);
 
     // run it
     tracing::debug!("listening on {}", listener.local_addr()?);
@@ -137,6 +329,24 @@ async fn main() -> eyre::Result<()> {
     Ok(())
 }
 
+/// Starts the task reporting the progress of running playbacks every `progress_interval`,
+/// `None` when progress updates are disabled.
+fn spawn_progress_updates(app_state: AppState) -> Option<tokio::task::JoinHandle<()>> {
+    if app_state.config.progress_interval.is_zero() {
+        tracing::info!("Progress updates are disabled, Jellyfin only hears about playback events");
+        return None;
+    }
+    Some(tokio::spawn(async move {
+        let mut interval = tokio::time::interval(app_state.config.progress_interval);
+        loop {
+            interval.tick().await;
+            if let Err(e) = progress_update_routine(&app_state).await {
+                tracing::error!(error = ?e, "Failed to update progress");
+            }
+        }
+    }))
+}
+
 async fn shutdown_signal() {
     let ctrl_c = async {
         signal::ctrl_c()
@@ -161,31 +371,548 @@ async fn shutdown_signal() {
     }
 }
 
+/// Parses an optional environment variable, falling back to `default` when it's unset.
+fn env_or<T>(name: &str, default: T) -> eyre::Result<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match std::env::var(name) {
+        Ok(value) => value.parse().wrap_err_with(|| format!("{} is invalid", name)),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Parses an optional environment variable, returning `None` when it's unset.
+fn env_opt<T>(name: &str) -> eyre::Result<Option<T>>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match std::env::var(name) {
+        Ok(value) => Ok(Some(value.parse().wrap_err_with(|| format!("{} is invalid", name))?)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Reads an optional environment variable that has to be one of `allowed`.
+fn env_choice(name: &str, default: &str, allowed: &[&str]) -> eyre::Result<String> {
+    match std::env::var(name) {
+        Ok(value) if allowed.contains(&value.as_str()) => Ok(value),
+        Ok(value) => Err(eyre::eyre!(
+            "{} is invalid: {}, expected one of {}",
+            name,
+            value,
+            allowed.join(", ")
+        )),
+        Err(_) => Ok(default.to_string()),
+    }
+}
+
+/// Parses an optional comma separated environment variable, ignoring empty entries.
+fn env_list(name: &str) -> Vec<String> {
+    std::env::var(name)
+        .map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|entry| !entry.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Parses an optional `key=value,key=value` environment variable.
+fn env_map<T>(name: &str) -> eyre::Result<HashMap<String, T>>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    env_list(name)
+        .into_iter()
+        .map(|entry| {
+            let (key, value) = entry
+                .split_once('=')
+                .ok_or_else(|| eyre::eyre!("{} is invalid: expected key=value, got {}", name, entry))?;
+            let value = value
+                .trim()
+                .parse()
+                .wrap_err_with(|| format!("{} is invalid", name))?;
+            Ok((key.trim().to_string(), value))
+        })
+        .collect()
+}
+
+/// Timeline rows when `JELLYVR_TAG_TRACKS` isn't set: chapters, intro/credits markers, genres and people each get their own.
+fn default_tag_tracks() -> HashMap<String, i32> {
+    let people = ["Actor", "GuestStar", "Director", "Writer", "Producer", "Composer"];
+    [("Chapter", 0), ("Skip", 1), ("Genre", 2)]
+        .into_iter()
+        .chain(people.into_iter().map(|category| (category, 3)))
+        .map(|(category, track)| (category.to_string(), track))
+        .collect()
+}
+
+/// Routes HereSphere talks to, below `/heresphere`.
+fn heresphere_router() -> Router<AppState> {
+    Router::new()
+        .route("/", post(heresphere_libraries))
+        .route("/scan", post(heresphere_scan))
+        .route("/:id", post(heresphere_video))
+        .route("/events/:sid/:vid", post(heresphere_event))
+        // Scans of big libraries are multiple megabytes of very repetitive JSON
+        .layer(CompressionLayer::new())
+        // Added after the compression layer, video segments and images don't compress
+        .route("/hls/:sid/:vid/*path", get(heresphere_hls))
+        .route("/images/:user/:signature/Items/:id/Images/:image_type", get(heresphere_image))
+}
+
+/// Every route of JellyVR, routes added here belong in `openapi::document` too.
+#[cfg_attr(not(feature = "openapi"), allow(clippy::let_and_return))]
+fn app_router() -> Router<AppState> {
+    let app = Router::new()
+        .route("/", get(root).post(unlock_root))
+        .route("/health", get(|| async { "OK" } ))
+        .route("/version", get(version))
+        .nest("/heresphere", heresphere_router())
+        .nest("/admin", admin::router())
+        .nest("/debug", debug::router())
+        .nest_service("/assets", ServeEmbed::<Assets>::new());
+    #[cfg(feature = "openapi")]
+    let app = app.route("/openapi.json", get(openapi::openapi_json));
+    app
+}
+
+/// Name of the server configured through `JELLYFIN_HOST`.
+const DEFAULT_SERVER: &str = "default";
+
+fn default_server() -> String {
+    DEFAULT_SERVER.to_string()
+}
+
+/// Reads the default server from `JELLYFIN_HOST`/`JELLYFIN_REMOTE_HOST`
+/// and any extra ones from `JELLYFIN_SERVERS` (`name=api_host|remote_host,...`).
+fn jellyfin_servers_from_env() -> eyre::Result<Vec<JellyfinServerConfig>> {
+    let api_host = std::env::var("JELLYFIN_HOST").wrap_err("JELLYFIN_HOST not set")?;
+    let mut servers = vec![JellyfinServerConfig {
+        name: default_server(),
+        remote_host: jellyfin::normalize_base_url(&std::env::var("JELLYFIN_REMOTE_HOST").unwrap_or(api_host.clone())),
+        api_host: jellyfin::normalize_base_url(&api_host),
+    }];
+    let mut extra: Vec<(String, String)> = env_map("JELLYFIN_SERVERS")?.into_iter().collect();
+    extra.sort();
+    for (name, hosts) in extra {
+        if name == DEFAULT_SERVER {
+            return Err(eyre::eyre!("JELLYFIN_SERVERS is invalid: {} is reserved for JELLYFIN_HOST", DEFAULT_SERVER));
+        }
+        let (api_host, remote_host) = hosts.split_once('|').unwrap_or((hosts.as_str(), hosts.as_str()));
+        servers.push(JellyfinServerConfig {
+            name,
+            api_host: jellyfin::normalize_base_url(api_host),
+            remote_host: jellyfin::normalize_base_url(remote_host),
+        });
+    }
+    Ok(servers)
+}
+
+fn image_types_from_env() -> eyre::Result<HashMap<String, String>> {
+    let image_types: HashMap<String, String> = env_map("JELLYVR_IMAGE_TYPES")?;
+    if let Some(image_type) = image_types.values().find(|image_type| !jellyfin::IMAGE_TYPES.contains(&image_type.as_str())) {
+        return Err(eyre::eyre!(
+            "JELLYVR_IMAGE_TYPES is invalid: {} is not one of {:?}",
+            image_type,
+            jellyfin::IMAGE_TYPES
+        ));
+    }
+    Ok(image_types)
+}
+
+/// Custom `JELLYVR_LENS_MARKERS` come first so they can shadow the built-in ones.
+fn lens_presets_from_env() -> eyre::Result<Vec<(String, heresphere::LensPreset)>> {
+    let mut custom = env_map::<String>("JELLYVR_LENS_MARKERS")?
+        .into_iter()
+        .map(|(marker, preset)| {
+            let (lens, fov) = preset
+                .split_once(':')
+                .ok_or_else(|| eyre::eyre!("JELLYVR_LENS_MARKERS is invalid: expected marker=lens:fov, got {}", preset))?;
+            let fov = fov.trim().parse().wrap_err("JELLYVR_LENS_MARKERS is invalid")?;
+            Ok((marker, heresphere::LensPreset { lens: lens.trim().to_string(), fov }))
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+    custom.sort_by(|(a, _), (b, _)| a.cmp(b));
+    custom.extend(heresphere::builtin_lens_presets());
+    Ok(custom)
+}
+
+/// Custom `JELLYVR_STEREO_MARKERS` come first so they can shadow the built-in ones.
+fn stereo_markers_from_env() -> eyre::Result<Vec<(String, String)>> {
+    let mut custom = env_map::<String>("JELLYVR_STEREO_MARKERS")?.into_iter().collect::<Vec<_>>();
+    if let Some((_, stereo)) = custom.iter().find(|(_, stereo)| !heresphere::STEREO_MODES.contains(&stereo.as_str())) {
+        eyre::bail!(
+            "JELLYVR_STEREO_MARKERS is invalid: {}, expected one of {}",
+            stereo,
+            heresphere::STEREO_MODES.join(", ")
+        );
+    }
+    custom.sort();
+    custom.extend(heresphere::builtin_stereo_markers());
+    Ok(custom)
+}
+
+/// `JELLYVR_PROJECTION_FOV` entries on top of the built-in field of view per projection.
+fn projection_fovs_from_env() -> eyre::Result<HashMap<String, f64>> {
+    let custom = env_map::<f64>("JELLYVR_PROJECTION_FOV")?;
+    if let Some(projection) = custom.keys().find(|projection| !heresphere::PROJECTIONS.contains(&projection.as_str())) {
+        eyre::bail!(
+            "JELLYVR_PROJECTION_FOV is invalid: {}, expected one of {}",
+            projection,
+            heresphere::PROJECTIONS.join(", ")
+        );
+    }
+    let mut fovs: HashMap<String, f64> = heresphere::builtin_projection_fovs().into_iter().collect();
+    fovs.extend(custom);
+    Ok(fovs)
+}
+
+/// Picks the `JELLYVR_DEVICE_PRESET` codec profile and applies any individual overrides on top.
+fn codec_profile_from_env() -> eyre::Result<jellyfin::CodecProfile> {
+    let preset = env_choice("JELLYVR_DEVICE_PRESET", "default", jellyfin::CodecProfile::PRESETS)?;
+    let mut profile = jellyfin::CodecProfile::preset(&preset).expect("preset was validated");
+    // Container groups are comma separated themselves, so groups are separated by `;` here
+    if let Ok(containers) = std::env::var("JELLYVR_DIRECT_PLAY_CONTAINERS") {
+        profile.containers = containers
+            .split(';')
+            .map(str::trim)
+            .filter(|group| !group.is_empty())
+            .map(str::to_string)
+            .collect();
+    }
+    if let Ok(video_codecs) = std::env::var("JELLYVR_DIRECT_PLAY_VIDEO_CODECS") {
+        profile.video_codecs = video_codecs;
+    }
+    if let Ok(audio_codecs) = std::env::var("JELLYVR_DIRECT_PLAY_AUDIO_CODECS") {
+        profile.audio_codecs = audio_codecs;
+    }
+    if let Ok(video_codecs) = std::env::var("JELLYVR_TRANSCODE_VIDEO_CODECS") {
+        profile.transcode_video_codecs = video_codecs;
+    }
+    if let Ok(audio_codecs) = std::env::var("JELLYVR_TRANSCODE_AUDIO_CODECS") {
+        profile.transcode_audio_codecs = audio_codecs;
+    }
+    profile.transcode_protocol = env_or("JELLYVR_TRANSCODE_PROTOCOL", profile.transcode_protocol)?;
+    profile.transcode_container = env_or("JELLYVR_TRANSCODE_CONTAINER", profile.transcode_container)?;
+    profile.subtitles_in_manifest = env_or("JELLYVR_HLS_SUBTITLES", profile.subtitles_in_manifest)?;
+    if !jellyfin::CodecProfile::is_supported_transcode_target(&profile.transcode_protocol, &profile.transcode_container) {
+        eyre::bail!(
+            "Unsupported transcoding target {} over {}, supported: {:?}",
+            profile.transcode_container,
+            profile.transcode_protocol,
+            jellyfin::CodecProfile::TRANSCODE_TARGETS
+        );
+    }
+    Ok(profile)
+}
+
 #[derive(RustEmbed, Clone)]
 #[folder = "assets/"]
 struct Assets;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct AppConfig {
-    jellyfin_api_host: String,
-    jellyfin_remote_host: String,
+    jellyfin_servers: Vec<JellyfinServerConfig>,
     cache_lifetime: Duration,
+    /// Cached indexes (and their videos) not rebuilt for this long are dropped by the cache vacuum.
+    cache_retention: Duration,
+    /// How often the cache vacuum runs, zero disables it.
+    cache_vacuum_interval: Duration,
+    /// Subtitle language listed first, unless the headset sends an `Accept-Language`.
     prefered_subtitles_language: Option<String>,
+    /// Whether playback is reported to Jellyfin, users can override it from the root page.
     watchtime_tracking: bool,
+    /// Never write to Jellyfin, no progress, played marks or tags, wins over everything that would.
+    read_only: bool,
+    /// How often playback progress is estimated and pushed to Jellyfin, zero disables it.
+    progress_interval: Duration,
+    /// Codecs and containers the headset plays directly, anything else gets transcoded by Jellyfin.
+    codec_profile: jellyfin::CodecProfile,
+    /// Serve HLS playlists and segments through JellyVR instead of linking Jellyfin directly.
+    hls_proxy: bool,
+    /// Serve thumbnails through JellyVR with header auth instead of linking Jellyfin with the token in the url.
+    image_proxy: bool,
+    /// Requests JellyVR sends to each Jellyfin server at once, extra ones wait for a free slot.
+    max_concurrent_requests: usize,
+    /// Fraction of the duration after which a playback is marked as played in Jellyfin.
+    completion_threshold: f64,
+    /// How far before Jellyfin's stored position a resumed playback starts.
+    resume_rewind: Duration,
+    /// Playbacks a user may have going across their sessions, the oldest are stopped beyond that, 0 for no limit.
+    max_playbacks_per_user: usize,
+    /// Listen for playstate commands from Jellyfin's remote control while a video plays.
+    remote_control: bool,
+    /// Jellyfin item types to include, ex. `Movie`, `Episode`, `MusicVideo`, `Video` (home videos).
+    item_types: Vec<String>,
+    /// Item fields requested from Jellyfin, leaving out `People` or `Chapters` speeds up scans of big libraries.
+    item_fields: Vec<String>,
+    /// Fetch playback info of every video while building the index, so starting one is instant.
+    prefetch_playback_info: bool,
+    /// How long prefetched playback info is used for, Jellyfin forgets unused play sessions.
+    prefetch_playback_info_ttl: Duration,
+    /// Only index items matching these, ex. only unplayed or favorite ones.
+    item_filters: jellyfin::ItemFilters,
+    /// Names of the Jellyfin libraries to index, all of them when empty.
+    include_libraries: Vec<String>,
+    /// Names of the Jellyfin libraries to never index, wins over `include_libraries`.
+    exclude_libraries: Vec<String>,
+    /// Hide items with a Jellyfin community rating (0-10) below this.
+    min_rating: Option<f64>,
+    /// Whether items without a community rating are shown when `min_rating` is set.
+    unrated_passes_min_rating: bool,
+    /// Official ratings (ex. `R`, `NC-17`) whose items are listed with restricted access.
+    restricted_ratings: Vec<String>,
+    /// Jellyfin tags whose items are listed with restricted access.
+    restricted_tags: Vec<String>,
+    /// Items with an official rating above this one (ex. `PG-13`) are listed with restricted access.
+    max_official_rating: Option<String>,
+    /// Restrict items without a known official rating too when `max_official_rating` is set.
+    restrict_unrated: bool,
+    /// Size of the "Recently Added" library, 0 disables it.
+    recently_added_count: usize,
+    /// Show a "Continue Watching" library with partially watched items.
+    continue_watching: bool,
+    /// Show a "Next Up" library with the next unwatched episode of each series.
+    next_up: bool,
+    /// Give every series a library of its own instead of listing its episodes in "Everything".
+    collapse_series: bool,
+    /// Add a library per production year (`year`) or per decade (`decade`) that has videos, `none` for neither.
+    browse_by_year: String,
+    /// Add a library per genre, studio and/or person, any of `index::BROWSE_BY`.
+    browse_by: Vec<String>,
+    /// Videos a genre, studio or person needs for a library of its own, keeps one-off cast members out.
+    browse_by_min_videos: usize,
+    /// Link the Jellyfin image of each genre, studio or person with its library.
+    browse_by_images: bool,
+    /// Show a "Live TV" library with the Jellyfin live TV channels.
+    live_tv: bool,
+    /// Link Jellyfin trickplay manifests (10.9+) for scrub previews.
+    enable_trickplay: bool,
+    /// Projection used when nothing more specific is known about a video.
+    default_projection: String,
+    /// Stereo mode used when nothing more specific is known about a video.
+    default_stereo: String,
+    /// Jellyfin image type used as the thumbnail, ex. `Primary`, `Backdrop` or `Thumb`.
+    image_type: Option<String>,
+    /// Thumbnail image type per Jellyfin item kind (ex. `Movie`), wins over `image_type`.
+    image_types: HashMap<String, String>,
+    /// File name or tag markers mapped to the fisheye lens they were shot with, checked in order.
+    lens_presets: Vec<(String, heresphere::LensPreset)>,
+    /// Field of view per projection for videos without a lens preset, ex. `equirectangular` → 180.
+    projection_fov: HashMap<String, f64>,
+    /// Camera IPD sent for stereo videos, HereSphere guesses when it's missing.
+    camera_ipd: Option<f64>,
+    /// Camera IPD per lens (ex. `MKX200`), wins over `camera_ipd`.
+    lens_camera_ipd: HashMap<String, f64>,
+    /// Tags or file name words giving away the stereo layout of a video, ex. `HSBS` → `sbs`.
+    stereo_markers: Vec<(String, String)>,
+    /// Tag videos with their projection and stereo layout, ex. `Projection:180` and `Stereo:SBS`.
+    format_tags: bool,
+    /// Where resolution, codecs and bitrate show up: `description`, `tags` or `none`.
+    technical_details: String,
+    /// Tags or file name words marking videos encoded with the eyes swapped.
+    eye_swap_markers: Vec<String>,
+    /// Drop chapters with generic names like "Chapter 3" from the timeline.
+    skip_generic_chapters: bool,
+    /// Leave out media sources with no video stream, size or run time, and videos that have nothing else.
+    skip_broken_media: bool,
+    /// List forced subtitle tracks, named with a "(Forced)" suffix.
+    forced_subtitles: bool,
+    /// List SDH (hearing impaired) subtitle tracks, named with an "(SDH)" suffix.
+    sdh_subtitles: bool,
+    /// Name the media of stacked movies (`part1`, `part2`, ...) `Part 1`, `Part 2` instead of just their container.
+    label_parts: bool,
+    /// Timeline track per tag category, so chapters, intro/credits markers and so on get their own rows.
+    tag_tracks: HashMap<String, i32>,
+    /// Tag categories (the part before `:`) that are never sent to HereSphere.
+    tag_blocklist: Vec<String>,
+    /// Stands in for `:` inside tag values, HereSphere would take it for a category separator.
+    tag_colon_replacement: String,
+    /// Title of episodes, with `{series}`, `{season}`, `{episode}` and `{title}` filled in.
+    episode_title_template: String,
+    /// Maximum number of people (cast & crew) to emit tags for per video.
+    max_people: Option<usize>,
+    /// Let HereSphere edit the Jellyfin tags of items, this changes the library for everyone.
+    write_tags: bool,
+    /// Write the favorite flag of HereSphere back to Jellyfin as the played state, a favorite being an
+    /// unwatched video (see `index::baseitem_to_video`). Jellyfin favorites are left alone.
+    write_played: bool,
+    /// Deleting a video from the headset moves it to JellyVR's trash instead of leaving it be.
+    soft_delete: bool,
+    /// Rebuild the indexes of known users in the background on startup.
+    warm_cache_on_start: bool,
+    /// Videos read from the database and serialized at a time when streaming the scan, 0 reads them in one go.
+    scan_chunk_size: usize,
+    /// Scan chunks read ahead while the previous ones are sent.
+    scan_concurrency: usize,
+    /// Pretty print HereSphere responses, only useful for troubleshooting.
+    pretty_json: bool,
+    /// Expose the `/debug` routes to logged in users.
+    debug_endpoints: bool,
+    /// Refuse to start when the Jellyfin server can't be reached.
+    strict_startup: bool,
+    /// Token required by the `/admin` routes, which are disabled when unset.
+    admin_token: Option<Secret>,
+    /// PIN required before the root page shows the username and password for HereSphere.
+    status_pin: Option<Secret>,
+    /// Failed HereSphere logins per username before further attempts are rejected.
+    login_max_failures: u32,
+    /// How long a username stays locked after too many failed logins.
+    login_lockout: Duration,
+    /// Forces the `Secure` cookie attribute on or off, follows the request proto when unset.
+    cookie_secure: Option<bool>,
+    cookie_http_only: bool,
+    /// `SameSite` cookie attribute, one of `lax`, `strict`, `none`.
+    cookie_same_site: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct JellyfinServerConfig {
+    name: String,
+    /// Host JellyVR talks to.
+    api_host: String,
+    /// Host used in urls handed to the headset (media & images).
+    remote_host: String,
+}
+
+impl AppConfig {
+    /// Serializes a machine-facing response, compact unless `pretty_json` is enabled.
+    fn to_json<T: Serialize>(&self, value: &T) -> serde_json::Result<String> {
+        if self.pretty_json {
+            serde_json::to_string_pretty(value)
+        } else {
+            serde_json::to_string(value)
+        }
+    }
+}
+
+/// String that is kept out of logs.
+#[derive(Serialize, Deserialize, Clone)]
+struct Secret(String);
+
+impl Secret {
+    /// Compares SHA-256 digests instead of the strings themselves, so how long a comparison
+    /// takes says nothing about how much of the secret was guessed right.
+    fn matches(&self, provided: &str) -> bool {
+        use sha2::{Digest, Sha256};
+        Sha256::digest(self.0.as_bytes()) == Sha256::digest(provided.as_bytes())
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret([redacted])")
+    }
 }
 
 // the application state
 #[derive(Clone)]
 struct AppState {
     jellyfin: JellyfinState,
-    db: Surreal<Db>,
+    db: Database,
     config: AppConfig,
+    login_throttle: LoginThrottle,
+    remote_listeners: remote::RemoteListeners,
+    /// Signs image proxy urls, so knowing a user id isn't enough to load images as that user.
+    image_key: Secret,
+}
+
+/// Failed HereSphere logins per username, kept in memory so a restart forgets them.
+#[derive(Clone, Default)]
+struct LoginThrottle(std::sync::Arc<std::sync::Mutex<HashMap<String, FailedLogins>>>);
+
+struct FailedLogins {
+    count: u32,
+    last_failure: chrono::DateTime<chrono::Utc>,
+}
+
+impl FailedLogins {
+    fn is_recent(&self, lockout: Duration) -> bool {
+        (chrono::Utc::now() - self.last_failure)
+            .to_std()
+            .map_or(true, |elapsed| elapsed < lockout)
+    }
+}
+
+impl LoginThrottle {
+    /// Whether the username failed too often within the lockout window.
+    fn is_locked(&self, username: &str, max_failures: u32, lockout: Duration) -> bool {
+        let failures = self.0.lock().expect("Login throttle poisoned");
+        failures
+            .get(username)
+            .is_some_and(|failed| failed.count >= max_failures && failed.is_recent(lockout))
+    }
+
+    fn record_failure(&self, username: &str, lockout: Duration) {
+        let mut failures = self.0.lock().expect("Login throttle poisoned");
+        let now = chrono::Utc::now();
+        // Forget old failures once they're past the lockout window
+        failures.retain(|_, failed| failed.is_recent(lockout));
+        let failed = failures.entry(username.to_string()).or_insert(FailedLogins {
+            count: 0,
+            last_failure: now,
+        });
+        failed.count += 1;
+        failed.last_failure = now;
+    }
+
+    fn clear(&self, username: &str) {
+        self.0.lock().expect("Login throttle poisoned").remove(username);
+    }
 }
 
 // jellyfin specific state
 #[derive(Clone)]
 struct JellyfinState {
+    servers: HashMap<String, JellyfinServer>,
+}
+
+#[derive(Clone)]
+struct JellyfinServer {
     client: jellyfin::JellyfinClient,
+    remote_host: String,
+}
+
+impl JellyfinState {
+    fn new(config: &AppConfig, http_client: &reqwest::Client) -> Self {
+        Self {
+            servers: config
+                .jellyfin_servers
+                .iter()
+                .map(|server| {
+                    (
+                        server.name.clone(),
+                        JellyfinServer {
+                            client: jellyfin::JellyfinClient::new(
+                                jellyfin::JellyfinConfig::new(
+                                    server.api_host.clone(),
+                                    config.codec_profile.clone(),
+                                    config.max_concurrent_requests,
+                                    config.read_only,
+                                ),
+                                http_client.clone(),
+                            ),
+                            remote_host: server.remote_host.clone(),
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    fn server(&self, name: &str) -> eyre::Result<&JellyfinServer> {
+        self.servers
+            .get(name)
+            .ok_or_else(|| JellyVrError::NotFound(format!("Jellyfin server {}", name)).into())
+    }
 }
 
 // support converting an `AppState` in an `ApiState`
@@ -201,10 +928,60 @@ struct AppError(eyre::Error);
 // Tell axum how to convert `AppError` into a response.
 impl IntoResponse for AppError {
     fn into_response(self) -> Response<Body> {
-        (StatusCode::INTERNAL_SERVER_ERROR, self.0.to_string()).into_response()
+        (self.status(), self.0.to_string()).into_response()
+    }
+}
+
+impl AppError {
+    /// Picks the status for the error, anything not recognized is a plain 500.
+    fn status(&self) -> StatusCode {
+        if let Some(err) = self.0.downcast_ref::<JellyVrError>() {
+            return err.status();
+        }
+        match self.0.downcast_ref::<reqwest::Error>() {
+            Some(err) if err.is_connect() || err.is_timeout() => StatusCode::BAD_GATEWAY,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
     }
 }
 
+/// Errors callers need to tell apart, truly internal ones stay plain `eyre` errors.
+/// They travel inside `eyre::Error` (directly or as context) and `AppError` maps them to a status.
+#[derive(Debug)]
+enum JellyVrError {
+    /// No session matches the credentials or id of the request.
+    SessionNotFound,
+    /// The request isn't logged in as anyone allowed to do this.
+    Unauthorized,
+    /// A Jellyfin server couldn't be reached.
+    JellyfinUnavailable(String),
+    /// The requested thing doesn't exist.
+    NotFound(String),
+}
+
+impl JellyVrError {
+    fn status(&self) -> StatusCode {
+        match self {
+            JellyVrError::SessionNotFound | JellyVrError::Unauthorized => StatusCode::UNAUTHORIZED,
+            JellyVrError::JellyfinUnavailable(_) => StatusCode::BAD_GATEWAY,
+            JellyVrError::NotFound(_) => StatusCode::NOT_FOUND,
+        }
+    }
+}
+
+impl std::fmt::Display for JellyVrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JellyVrError::SessionNotFound => f.write_str("No session found for request"),
+            JellyVrError::Unauthorized => f.write_str("Not logged in"),
+            JellyVrError::JellyfinUnavailable(host) => write!(f, "Jellyfin server at {} is unreachable", host),
+            JellyVrError::NotFound(what) => write!(f, "{} not found", what),
+        }
+    }
+}
+
+impl std::error::Error for JellyVrError {}
+
 // This enables using `?` on functions that return `Result<_, anyhow::Error>` to turn them into
 // `Result<_, AppError>`. That way you don't need to do that manually.
 impl<E> From<E> for AppError
@@ -234,31 +1011,138 @@ async fn handler_404(request: ExtractRequest) -> Result<impl IntoResponse, Respo
     Ok((StatusCode::NOT_FOUND, "nothing to see here"))
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 struct User {
+    /// Name of the Jellyfin server this user belongs to.
+    #[serde(default = "default_server")]
+    server: String,
     user_id: String,
     token: String,
     username: String,
     jellyvr_password: String,
     last_known_playback: Option<Playback>,
+    /// Per-user override of `AppConfig::watchtime_tracking`.
+    #[serde(default)]
+    watchtime_tracking: Option<bool>,
+    /// Headset last seen sending HereSphere requests, reported to Jellyfin as the device.
+    #[serde(default)]
+    device: Option<String>,
+    /// DeviceId the token was handed out for, sessions from before they had their own share one.
+    #[serde(default)]
+    device_id: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct Playback {
     play_session_id: String,
     video_id: String,
+    /// Run time in ticks, 0 when Jellyfin doesn't know it, which skips the completion and overflow checks.
     duration: i64,
+    /// Position in ticks as of `last_update`.
     position_estimate: i64,
     speed: f64,
     started_at: chrono::DateTime<chrono::Utc>,
     last_update: chrono::DateTime<chrono::Utc>,
     is_paused: bool,
+    /// Whether Jellyfin has been told about this playback yet.
+    /// The video endpoint only prepares a playback, it's started on the first Open/Play event.
+    #[serde(default = "default_true")]
+    is_started: bool,
+    /// Whether the item was already marked as played in Jellyfin during this playback.
+    #[serde(default)]
+    is_marked_played: bool,
+    /// Jellyfin path the headset streams from, handed out again when the play session is reused.
+    /// It carries the token, so it's kept out of logs.
+    #[serde(default)]
+    media_url: Option<Secret>,
+    /// Whether `media_url` is a transcode, which carries the subtitles itself.
+    #[serde(default)]
+    is_transcoded: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+impl Playback {
+    /// Position in ticks the headset should be at by now, going by the last reported position and speed.
+    fn predicted_position(&self) -> i64 {
+        if self.is_paused {
+            return self.position_estimate;
+        }
+        let elapsed_ms = chrono::Utc::now().signed_duration_since(self.last_update).num_milliseconds() as f64;
+        self.position_estimate + jellyfin::ms_to_ticks(elapsed_ms * self.speed)
+    }
+
+    /// Whether a new request for `vid` keeps reporting to this play session instead of opening another one.
+    fn is_reusable_for(&self, vid: &str) -> bool {
+        self.video_id == vid && chrono::Utc::now() - self.last_update < chrono::Duration::minutes(PLAY_SESSION_REUSE_MINUTES)
+    }
+
+    /// Whether this is a prepared playback of `vid` Jellyfin hasn't been told about yet.
+    fn awaits_start_of(&self, vid: &str) -> bool {
+        !self.is_started && self.video_id == vid
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 struct QuickConnect {
+    #[serde(default = "default_server")]
+    server: String,
     secret: String,
     code: String,
+    #[serde(default)]
+    poll_attempts: u32,
+    #[serde(default)]
+    last_poll: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Polls after which a Quick Connect code is considered expired and replaced, roughly 10 minutes with backoff.
+const QUICK_CONNECT_MAX_POLLS: u32 = 25;
+
+/// How long after its last update a playback's play session is reused when HereSphere asks for the same video again.
+const PLAY_SESSION_REUSE_MINUTES: i64 = 10;
+
+impl User {
+    /// Whether this user's playback should be reported to Jellyfin.
+    fn tracks_watchtime(&self, config: &AppConfig) -> bool {
+        !config.read_only && self.watchtime_tracking.unwrap_or(config.watchtime_tracking)
+    }
+
+    /// Key of this user's cached data, namespaced by server for anything but the default one
+    /// so ids from different servers can never collide.
+    fn cache_key(&self) -> String {
+        if self.server == DEFAULT_SERVER {
+            self.user_id.clone()
+        } else {
+            format!("{}:{}", self.server, self.user_id)
+        }
+    }
+
+    /// Whether the user's last known playback is of the given video. The sid in HLS urls is a weak
+    /// credential, so files are only served for the video it was handed out for, never without a playback.
+    fn is_playing(&self, vid: &str) -> bool {
+        self.last_known_playback
+            .as_ref()
+            .is_some_and(|playback| playback.video_id.eq_ignore_ascii_case(vid))
+    }
+}
+
+impl QuickConnect {
+    fn new(server: String, secret: String, code: String) -> Self {
+        Self {
+            server,
+            secret,
+            code,
+            poll_attempts: 0,
+            last_poll: None,
+        }
+    }
+
+    /// Minimum time between polls, backing off from 5 seconds to 30 seconds.
+    fn poll_interval(&self) -> chrono::Duration {
+        chrono::Duration::seconds((5 * 2i64.pow(self.poll_attempts.min(3))).min(30))
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -267,74 +1151,208 @@ enum Session {
     User(User),
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+impl Session {
+    fn server(&self) -> &str {
+        match self {
+            Session::QuickConnect(quick_connect) => &quick_connect.server,
+            Session::User(user) => &user.server,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 struct SessionState {
     id: Option<surrealdb::sql::Thing>,
+    /// Opaque value of the `jellyvr_session` cookie, record ids are short and easy to guess.
+    /// Sessions from before it existed get one on their next visit.
+    #[serde(default)]
+    secret: Option<String>,
     session: Session,
 }
 
+// Sessions get logged, their secrets and tokens must not be
+impl std::fmt::Debug for SessionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionState")
+            .field("id", &self.id)
+            .field("secret", &self.secret.as_ref().map(|_| "[redacted]"))
+            .field("session", &self.session)
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for User {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("User")
+            .field("server", &self.server)
+            .field("user_id", &self.user_id)
+            .field("token", &"[redacted]")
+            .field("username", &self.username)
+            .field("jellyvr_password", &"[redacted]")
+            .field("last_known_playback", &self.last_known_playback)
+            .field("watchtime_tracking", &self.watchtime_tracking)
+            .field("device", &self.device)
+            .field("device_id", &self.device_id)
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for QuickConnect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("QuickConnect")
+            .field("server", &self.server)
+            .field("secret", &"[redacted]")
+            .field("code", &self.code)
+            .field("poll_attempts", &self.poll_attempts)
+            .field("last_poll", &self.last_poll)
+            .finish()
+    }
+}
+
 impl AppState {
-    async fn new_session(&self) -> eyre::Result<SessionState> {
-        let new_qc = self.jellyfin.client.new_quick_connect().await?;
+    fn jellyfin_user(&self, user: &User) -> eyre::Result<jellyfin::JellyfinUser> {
+        Ok(self
+            .jellyfin
+            .server(&user.server)?
+            .client
+            .resume_user(&user.user_id, &user.token)
+            .with_device(user.device.as_deref(), user.device_id.as_deref()))
+    }
+
+    async fn new_session(&self, server: &str) -> eyre::Result<SessionState> {
+        let new_qc = self.jellyfin.server(server)?.client.new_quick_connect().await?;
         let session: Vec<SessionState> = self
             .db
+            .get()
             .create("session")
             .content(&SessionState {
                 id: None,
-                session: Session::QuickConnect(QuickConnect {
-                    secret: new_qc.secret,
-                    code: new_qc.code,
-                }),
+                secret: Some(gen_session_secret()),
+                session: Session::QuickConnect(QuickConnect::new(server.to_string(), new_qc.secret, new_qc.code)),
             })
             .await?;
-        tracing::info!("Created new session: {:?}", session);
+        tracing::info!(id = ?session.first().and_then(|state| state.id.as_ref()), "Created new session");
         Ok(session.first().expect("No session created").clone())
     }
 
     async fn update_session(&self, session: SessionState) -> eyre::Result<SessionState> {
-        let session: Option<SessionState> = self.db.update(session.id.as_ref().unwrap()).content(&session).await?;
+        let id = session.id.as_ref().unwrap();
+        let content = &session;
+        let session: Option<SessionState> = retry_db(|| async move { self.db.get().update(id).content(content).await }).await?;
         match session {
             Some(state) => Ok(state),
             None => Err(eyre::eyre!("Failed to update session")),
         }
     }
 
-    async fn handle_session(&self, session: Option<String>) -> eyre::Result<SessionState> {
-        let existing_state = match session {
-            Some(cookie) => {
-                let session: Option<SessionState> = self.db.select(("session", cookie)).await?;
-                match session {
-                    Some(state) => state.clone(),
-                    None => self.new_session().await?,
-                }
+    /// Looks a session up by the secret in its cookie.
+    /// Cookies from before secrets existed hold the record id, those sessions are given a secret
+    /// (and so a new cookie) once, after which the record id no longer works as a cookie.
+    async fn session_from_cookie(&self, cookie: &str) -> eyre::Result<Option<SessionState>> {
+        match find_session_by_cookie(&self.db.get(), cookie).await? {
+            Some(state) if state.secret.is_none() => {
+                tracing::debug!("Migrating session cookie to a session secret");
+                Ok(Some(
+                    self.update_session(SessionState {
+                        secret: Some(gen_session_secret()),
+                        ..state
+                    })
+                    .await?,
+                ))
             }
-            None => self.new_session().await?,
+            session => Ok(session),
+        }
+    }
+
+    /// Resolves the session behind the cookie, starting a new login when there is none
+    /// or when a different `server` than the session's one is requested.
+    async fn handle_session(
+        &self,
+        session: Option<String>,
+        server: Option<String>,
+    ) -> eyre::Result<SessionState> {
+        let session: Option<SessionState> = match session {
+            Some(cookie) => self.session_from_cookie(&cookie).await?,
+            None => None,
+        };
+        let existing_state = match (session, server) {
+            (Some(state), None) => state,
+            (Some(state), Some(server)) if state.session.server() == server => state,
+            (_, server) => self.new_session(server.as_deref().unwrap_or(DEFAULT_SERVER)).await?,
         };
 
         match &existing_state.session {
-            Session::QuickConnect(QuickConnect { secret, code }) => {
-                let qc = self.jellyfin.client.resume_quick_connect(&secret, &code);
+            Session::QuickConnect(quick_connect) => {
+                // The login page reloads every few seconds, don't pass every reload on to Jellyfin
+                if let Some(last_poll) = quick_connect.last_poll {
+                    if chrono::Utc::now() - last_poll < quick_connect.poll_interval() {
+                        return Ok(existing_state);
+                    }
+                }
+                if quick_connect.poll_attempts >= QUICK_CONNECT_MAX_POLLS {
+                    tracing::info!("Quick Connect code expired, starting a new one");
+                    let new_qc = self
+                        .jellyfin
+                        .server(&quick_connect.server)?
+                        .client
+                        .new_quick_connect()
+                        .await?;
+                    return self
+                        .update_session(SessionState {
+                            id: existing_state.id,
+                            secret: existing_state.secret,
+                            session: Session::QuickConnect(QuickConnect::new(
+                                quick_connect.server.clone(),
+                                new_qc.secret,
+                                new_qc.code,
+                            )),
+                        })
+                        .await;
+                }
+                let qc = self
+                    .jellyfin
+                    .server(&quick_connect.server)?
+                    .client
+                    .resume_quick_connect(
+                        &quick_connect.secret,
+                        &quick_connect.code,
+                        existing_state
+                            .id
+                            .as_ref()
+                            .map(|id| jellyfin::session_device_id(&id.id.to_raw()))
+                            .as_deref(),
+                    );
                 let resp = qc.poll().await?;
                 if resp {
                     let resp = qc.auth().await?;
                     let jellyvr_short_password = gen_short_password(6);
-                    let session: Vec<SessionState> = self
-                        .db
-                        .update("session")
-                        .content(&SessionState {
-                            id: existing_state.id,
-                            session: Session::User(User {
-                                user_id: resp.id,
-                                token: resp.token,
-                                username: resp.username,
-                                jellyvr_password: jellyvr_short_password,
-                                last_known_playback: None,
-                            }),
-                        })
-                        .await?;
-                    Ok(session.first().expect("No session created").clone())
+                    self.update_session(SessionState {
+                        id: existing_state.id,
+                        secret: existing_state.secret,
+                        session: Session::User(User {
+                            server: quick_connect.server.clone(),
+                            user_id: resp.id,
+                            token: resp.token,
+                            username: resp.username,
+                            jellyvr_password: jellyvr_short_password,
+                            last_known_playback: None,
+                            watchtime_tracking: None,
+                            device: None,
+                            device_id: resp.device_id,
+                        }),
+                    })
+                    .await
                 } else {
-                    Ok(existing_state)
+                    self.update_session(SessionState {
+                        id: existing_state.id.clone(),
+                        secret: existing_state.secret.clone(),
+                        session: Session::QuickConnect(QuickConnect {
+                            poll_attempts: quick_connect.poll_attempts + 1,
+                            last_poll: Some(chrono::Utc::now()),
+                            ..quick_connect.clone()
+                        }),
+                    })
+                    .await
                 }
             }
             Session::User(User { .. }) => Ok(existing_state),
@@ -346,10 +1364,14 @@ impl AppState {
         req: &heresphere::Request,
     ) -> eyre::Result<SessionState>  {
         // query db for session using username&password from request
-        let session: Option<SessionState> = self.db.query("SELECT * FROM session WHERE session.User.username = $username AND session.User.jellyvr_password = $password LIMIT 1").bind(req).await?.take(0)?;
+        let session: Option<SessionState> = retry_db(|| async move {
+            self.db.get().query("SELECT * FROM session WHERE session.User.username = $username AND session.User.jellyvr_password = $password LIMIT 1").bind(req).await?.check()
+        })
+        .await?
+        .take(0)?;
         match session {
             Some(state) => Ok(state),
-            None => Err(eyre::eyre!("No session found for request")),
+            None => Err(JellyVrError::SessionNotFound.into()),
         }
     }
 
@@ -357,53 +1379,399 @@ impl AppState {
         &self,
         sid: &str,
     ) -> eyre::Result<SessionState> {
-        let session: Option<SessionState> = self
-            .db
-            .query("SELECT * FROM type::thing('session', $sid)")
-            .bind(("sid", sid))
-            .await?
-            .take(0)?;
+        let session: Option<SessionState> = retry_db(|| async move {
+            self.db.get()
+                .query("SELECT * FROM type::thing('session', $sid)")
+                .bind(("sid", sid))
+                .await?
+                .check()
+        })
+        .await?
+        .take(0)?;
+        match session {
+            Some(state) => Ok(state),
+            None => Err(JellyVrError::SessionNotFound.into()),
+        }
+    }
+
+    /// Any session of the user with this `User::cache_key`, for requests that only name the user.
+    async fn get_session_from_cache_key(
+        &self,
+        cache_key: &str,
+    ) -> eyre::Result<SessionState> {
+        let (server, user_id) = cache_key.split_once(':').unwrap_or((DEFAULT_SERVER, cache_key));
+        let session: Option<SessionState> = retry_db(|| async move {
+            self.db.get()
+                .query("SELECT * FROM session WHERE session.User.user_id = $user_id AND (session.User.server = $server OR ($server = $default AND session.User.server = NONE)) LIMIT 1")
+                .bind(("user_id", user_id))
+                .bind(("server", server))
+                .bind(("default", DEFAULT_SERVER))
+                .await?
+                .check()
+        })
+        .await?
+        .take(0)?;
         match session {
             Some(state) => Ok(state),
-            None => Err(eyre::eyre!("No session found for request")),
+            None => Err(JellyVrError::SessionNotFound.into()),
         }
     }
 }
 
-fn gen_short_password(arg: i32) -> String {
-    use rand::Rng;
-    let mut rng = rand::thread_rng();
-    let mut password = String::new();
+/// How often the database is checked for a lost connection.
+const DB_HEALTH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The embedded database, reopened by `reopen_if_lost` when its engine goes away. Handlers take the
+/// current connection with `get` for every request, so they pick up a reopened one right away.
+#[derive(Clone)]
+struct Database {
+    /// Where RocksDB keeps its files, `None` for databases that can't be reopened (ex. in tests).
+    path: Option<String>,
+    current: std::sync::Arc<std::sync::RwLock<Surreal<Db>>>,
+}
+
+impl Database {
+    async fn open(path: &str) -> eyre::Result<Self> {
+        let db = Surreal::new::<RocksDb>(path).await?;
+        db.use_ns("jellyvr").use_db("jellyvr").await?;
+        Ok(Self {
+            path: Some(path.to_string()),
+            current: std::sync::Arc::new(std::sync::RwLock::new(db)),
+        })
+    }
+
+    fn get(&self) -> Surreal<Db> {
+        self.current.read().expect("Database poisoned").clone()
+    }
+
+    /// Opens the database again if the engine behind the current connection is gone.
+    /// Returns whether it was reopened, query errors and conflicts don't count as a lost connection.
+    async fn reopen_if_lost(&self) -> eyre::Result<bool> {
+        match self.get().health().await {
+            Err(err) if is_lost_db_connection(&err) => {
+                tracing::debug!(error = ?err, "Database connection is gone");
+            }
+            _ => return Ok(false),
+        }
+        let Some(path) = &self.path else {
+            return Err(eyre::eyre!("Database can't be reopened"));
+        };
+        let reopened = Database::open(path).await?;
+        *self.current.write().expect("Database poisoned") = reopened.get();
+        Ok(true)
+    }
+}
+
+impl From<Surreal<Db>> for Database {
+    fn from(db: Surreal<Db>) -> Self {
+        Self {
+            path: None,
+            current: std::sync::Arc::new(std::sync::RwLock::new(db)),
+        }
+    }
+}
+
+/// Attempts a session read or write gets before its error is returned.
+const DB_ATTEMPTS: u64 = 3;
+
+/// Retries `op` when RocksDB reports a transaction conflict, which happens when a HereSphere event and
+/// the progress routine touch the same session at once. Anything else, a bad query included, is returned
+/// right away. A lost connection is reopened by `Database::reopen_if_lost` instead.
+async fn retry_db<T, F, Fut>(mut op: F) -> Result<T, surrealdb::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, surrealdb::Error>>,
+{
+    let mut attempt = 1;
+    loop {
+        match op().await {
+            Err(err) if attempt < DB_ATTEMPTS && is_transient_db_error(&err) => {
+                tracing::debug!(error = ?err, attempt, "Retrying database operation");
+                tokio::time::sleep(Duration::from_millis(50 * attempt)).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn is_transient_db_error(err: &surrealdb::Error) -> bool {
+    matches!(
+        err,
+        surrealdb::Error::Db(surrealdb::error::Db::TxRetryable | surrealdb::error::Db::TxFailure)
+    )
+}
+
+/// The embedded engine stopped answering, its channel is closed.
+fn is_lost_db_connection(err: &surrealdb::Error) -> bool {
+    matches!(
+        err,
+        surrealdb::Error::Api(surrealdb::error::Api::InternalError(_) | surrealdb::error::Api::ConnectionUninitialised)
+    )
+}
+
+fn gen_short_password(arg: i32) -> String {
+    use rand::Rng;
+    let mut rng = rand::thread_rng();
+    let mut password = String::new();
     for _ in 0..arg {
         password.push(rng.gen_range('a'..='z'));
     }
     password
 }
 
-async fn root(State(app): State<AppState>, jar: CookieJar) -> Result<impl IntoResponse, AppError> {
-    let state = app
-        .handle_session(jar.get("jellyvr_session").map(|c| c.value().to_string()))
+/// Session whose secret is the cookie, or a session from before secrets existed whose record id is.
+async fn find_session_by_cookie(db: &Surreal<Db>, cookie: &str) -> eyre::Result<Option<SessionState>> {
+    let session: Option<SessionState> = retry_db(|| async move {
+        db.query("SELECT * FROM session WHERE secret = $secret LIMIT 1")
+            .bind(("secret", cookie))
+            .await?
+            .check()
+    })
+    .await?
+    .take(0)?;
+    if session.is_some() {
+        return Ok(session);
+    }
+    let legacy: Option<SessionState> = db.select(("session", cookie)).await?;
+    Ok(legacy.filter(|state| state.secret.is_none()))
+}
+
+/// Updates sessions only if their playback hasn't been touched since the paired `last_update`,
+/// so background updates never overwrite a fresher position reported by HereSphere.
+/// Everything goes out in a single query, the outcome of each session is reported separately:
+/// `None` if the session changed in the meantime.
+async fn update_sessions_if_unchanged(
+    db: &Surreal<Db>,
+    updates: Vec<(SessionState, chrono::DateTime<chrono::Utc>)>,
+) -> eyre::Result<Vec<(String, eyre::Result<Option<SessionState>>)>> {
+    let statements = (0..updates.len())
+        .map(|i| format!("UPDATE $id{i} CONTENT $session{i} WHERE session.User.last_known_playback.last_update = $last_update{i};"))
+        .collect::<String>();
+    let mut query = db.query(statements);
+    let mut ids = vec![];
+    for (i, (session, last_update)) in updates.into_iter().enumerate() {
+        ids.push(session.id.as_ref().map_or(String::new(), |id| id.id.to_raw()));
+        query = query
+            .bind((format!("id{i}"), session.id.clone()))
+            .bind((format!("session{i}"), session))
+            .bind((format!("last_update{i}"), last_update));
+    }
+    let mut response = query.await?;
+    Ok(ids
+        .into_iter()
+        .enumerate()
+        .map(|(i, id)| {
+            let updated: eyre::Result<Vec<SessionState>> = response.take(i).map_err(Into::into);
+            (id, updated.map(|updated| updated.into_iter().next()))
+        })
+        .collect())
+}
+
+/// Key image proxy urls are signed with, kept in the database so cached indexes stay valid across restarts.
+async fn image_key(db: &Surreal<Db>) -> eyre::Result<Secret> {
+    #[derive(Serialize, Deserialize)]
+    struct ImageKey {
+        key: String,
+    }
+    let stored: Option<ImageKey> = db.select(("meta", "image_key")).await?;
+    if let Some(stored) = stored {
+        return Ok(Secret(stored.key));
+    }
+    let key = gen_session_secret() + &gen_session_secret();
+    let _: Option<ImageKey> = db
+        .create(("meta", "image_key"))
+        .content(ImageKey { key: key.clone() })
         .await?;
-    let d = serde_json::to_string_pretty(&state).map_err(|err| AppError(err.into()))?;
+    Ok(Secret(key))
+}
+
+/// High entropy session cookie value.
+fn gen_session_secret() -> String {
+    use rand::{distributions::Alphanumeric, Rng};
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+#[derive(Serialize, Debug)]
+struct Version {
+    version: &'static str,
+    /// Commit the binary was built from, when `GIT_HASH` was set at build time.
+    git_hash: Option<&'static str>,
+    /// Jellyfin OpenAPI spec the client models were generated from.
+    jellyfin_spec_version: &'static str,
+}
+
+async fn version() -> Json<Version> {
+    Json(Version {
+        version: env!("CARGO_PKG_VERSION"),
+        git_hash: option_env!("GIT_HASH"),
+        jellyfin_spec_version: jellyfin::SPEC_VERSION,
+    })
+}
+
+#[derive(Deserialize, Debug)]
+struct RootQuery {
+    /// Jellyfin server to log into, defaults to the one from `JELLYFIN_HOST`.
+    server: Option<String>,
+    /// Turns watchtime tracking on or off for the logged in user.
+    watchtime_tracking: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct UnlockForm {
+    pin: String,
+}
+
+/// Cookie that keeps the page unlocked once the `status_pin` was entered.
+const STATUS_UNLOCK_COOKIE: &str = "jellyvr_status_unlock";
+
+/// Value of the unlock cookie of a session. It's tied to the session secret and the PIN,
+/// so it doesn't unlock any other session and stops working once the PIN changes.
+fn status_unlock_token(pin: &Secret, session_secret: &str) -> String {
+    use sha2::{Digest, Sha256};
+    hex::encode(Sha256::digest(format!("{}\0{}", pin.0, session_secret)))
+}
+
+/// Cookie with the flags configured for JellyVR's own cookies.
+fn app_cookie(config: &AppConfig, host: &str, name: &'static str, value: String) -> Cookie<'static> {
+    Cookie::build((name, value))
+        .path("/")
+        .http_only(config.cookie_http_only)
+        .secure(config.cookie_secure.unwrap_or(host.starts_with("https://")))
+        .same_site(match config.cookie_same_site.as_str() {
+            "strict" => SameSite::Strict,
+            "none" => SameSite::None,
+            _ => SameSite::Lax,
+        })
+        .build()
+}
+
+async fn root(
+    State(app): State<AppState>,
+    ProtoHost(host): ProtoHost,
+    Query(query): Query<RootQuery>,
+    jar: CookieJar,
+) -> Result<Response, AppError> {
+    let mut state = match app
+        .handle_session(
+            jar.get("jellyvr_session").map(|c| c.value().to_string()),
+            query.server,
+        )
+        .await
+    {
+        Ok(state) => state,
+        Err(err) if matches!(
+            err.downcast_ref::<jellyfin::QuickConnectError>(),
+            Some(jellyfin::QuickConnectError::Disabled)
+        ) => {
+            tracing::warn!("Quick Connect is disabled on the Jellyfin server");
+            let html = template::render("quickconnect_disabled.html", &[])?;
+            return Ok((StatusCode::SERVICE_UNAVAILABLE, Html(html)).into_response());
+        }
+        Err(err) => return Err(AppError(err)),
+    };
+    // Without the PIN the page only says someone is logged in, the credentials stay hidden
+    let unlocked = match (&app.config.status_pin, &state.secret, jar.get(STATUS_UNLOCK_COOKIE)) {
+        (None, _, _) => true,
+        (Some(pin), Some(secret), Some(cookie)) => Secret(status_unlock_token(pin, secret)).matches(cookie.value()),
+        _ => false,
+    };
+    if let (true, Some(watchtime_tracking), Session::User(user)) = (unlocked, query.watchtime_tracking, &state.session) {
+        if user.watchtime_tracking != Some(watchtime_tracking) {
+            state = app
+                .update_session(SessionState {
+                    id: state.id.clone(),
+                    secret: state.secret.clone(),
+                    session: Session::User(User {
+                        watchtime_tracking: Some(watchtime_tracking),
+                        // Nothing should be reported for a playback once tracking is off
+                        last_known_playback: user.last_known_playback.clone().filter(|_| watchtime_tracking),
+                        ..user.clone()
+                    }),
+                })
+                .await?;
+        }
+    }
     tracing::debug!(
-        state = ?d,
+        state = ?state,
         "Resolved state"
     );
-    // TODO: Rewrite this to something nicer maybe...
-    Ok((jar.add(Cookie::new("jellyvr_session", state.id.unwrap().id.to_string())), Html(format!(r#"
-<!DOCTYPE html>
-<html>
-    <head>
-        <meta http-equiv="refresh" content="5" />
-    </head>
-    <body>
-        {}
-    </body>
-</html>
-"#, match state.session {
-        Session::QuickConnect(QuickConnect{ code, ..}) => format!("<h1>Code: {}</h1>", code),
-        Session::User(User{username, jellyvr_password, ..}) => format!("<h1>User: {}</h1></br><h1>Pass: {}</h1></br><h2><a href=\"/heresphere\">Heresphere!</a></h2>", username, jellyvr_password),
-    }))))
+    let html = match &state.session {
+        Session::QuickConnect(QuickConnect { code, server, .. }) => template::render(
+            "quickconnect.html",
+            &[("code", code.as_str()), ("server", server.as_str())],
+        )?,
+        Session::User(_) if !unlocked => template::render("user_locked.html", &[("message", "")])?,
+        Session::User(user) => {
+            let tracks_watchtime = user.tracks_watchtime(&app.config);
+            template::render(
+                "user.html",
+                &[
+                    ("username", user.username.as_str()),
+                    ("jellyvr_password", user.jellyvr_password.as_str()),
+                    ("watchtime_tracking", if tracks_watchtime { "on" } else { "off" }),
+                    ("toggle_watchtime_tracking", if tracks_watchtime { "false" } else { "true" }),
+                ],
+            )?
+        }
+    };
+    let cookie = app_cookie(
+        &app.config,
+        &host,
+        "jellyvr_session",
+        state.secret.expect("Session without a secret"),
+    );
+    Ok((jar.add(cookie), Html(html)).into_response())
+}
+
+/// Checks the `status_pin` and unlocks the page for this session. The PIN is posted from a form,
+/// so it never ends up in urls, access logs or the browser history, and wrong ones are throttled
+/// like HereSphere logins.
+async fn unlock_root(
+    State(app): State<AppState>,
+    ProtoHost(host): ProtoHost,
+    jar: CookieJar,
+    Form(form): Form<UnlockForm>,
+) -> Result<Response, AppError> {
+    let Some(pin) = &app.config.status_pin else {
+        return Ok(Redirect::to("/").into_response());
+    };
+    let state = match jar.get("jellyvr_session") {
+        Some(cookie) => app.session_from_cookie(cookie.value()).await?,
+        None => None,
+    };
+    let Some(SessionState {
+        id: Some(id),
+        secret: Some(secret),
+        session: Session::User(_),
+    }) = state
+    else {
+        // Nothing to unlock without a logged in session
+        return Ok(Redirect::to("/").into_response());
+    };
+    let throttle_key = format!("status_pin:{}", id.id.to_raw());
+    let locked_page = |status: StatusCode, message: &str| -> Result<Response, AppError> {
+        let html = template::render("user_locked.html", &[("message", message)])?;
+        Ok((status, Html(html)).into_response())
+    };
+    if app
+        .login_throttle
+        .is_locked(&throttle_key, app.config.login_max_failures, app.config.login_lockout)
+    {
+        tracing::warn!("Too many wrong status PINs, rejecting");
+        return locked_page(StatusCode::TOO_MANY_REQUESTS, "Too many wrong PINs, try again later");
+    }
+    if !pin.matches(&form.pin) {
+        app.login_throttle.record_failure(&throttle_key, app.config.login_lockout);
+        return locked_page(StatusCode::UNAUTHORIZED, "Wrong PIN");
+    }
+    app.login_throttle.clear(&throttle_key);
+    let cookie = app_cookie(&app.config, &host, STATUS_UNLOCK_COOKIE, status_unlock_token(pin, &secret));
+    Ok((jar.add(cookie), Redirect::to("/")).into_response())
 }
 
 /// Extractor for a Heresphere session
@@ -411,6 +1779,8 @@ struct HeresphereSession {
     request: Json<heresphere::Request>,
     session_state: SessionState,
     user: User,
+    /// Languages from the `Accept-Language` header, most preferred first.
+    languages: Vec<String>,
 }
 
 #[async_trait]
@@ -418,13 +1788,51 @@ impl FromRequest<AppState> for HeresphereSession {
     type Rejection = Response;
 
     async fn from_request(req: Request<Body>, state: &AppState) -> Result<Self, Self::Rejection> {
+        let device = req
+            .headers()
+            .get("user-agent")
+            .and_then(|value| value.to_str().ok())
+            .and_then(device_name);
+        let languages = req
+            .headers()
+            .get("accept-language")
+            .and_then(|value| value.to_str().ok())
+            .map(index::accept_languages)
+            .unwrap_or_default();
         let body = Json::<heresphere::Request>::from_request(req, state)
             .await
             .map_err(IntoResponse::into_response)?;
-        let session =
+        // HereSphere asks without credentials before the user logs in, that's not a failed attempt
+        let username = body.username.to_lowercase();
+        if !username.is_empty()
+            && state.login_throttle.is_locked(
+                &username,
+                state.config.login_max_failures,
+                state.config.login_lockout,
+            )
+        {
+            tracing::warn!(username = %username, "Too many failed logins, rejecting");
+            return Err((
+                [
+                    (heresphere::MAGIC_HEADER, "1"),
+                    ("Content-Type", "application/json"),
+                ],
+                r#"{"access": -1, "library": [{"name": "Too many failed logins, try again later", "list": []}]}"#,
+            )
+                .into_response());
+        }
+        let mut session =
             match state.get_session_from_heresphere_request(&body).await {
-                Ok(session) => session,
+                Ok(session) => {
+                    state.login_throttle.clear(&username);
+                    session
+                }
                 Err(err) => {
+                    if !username.is_empty() {
+                        state
+                            .login_throttle
+                            .record_failure(&username, state.config.login_lockout);
+                    }
                     tracing::warn!(
                         error = ?err,
                         "Failed to resolve state"
@@ -440,7 +1848,7 @@ impl FromRequest<AppState> for HeresphereSession {
                 }
             };
 
-        let user =
+        let mut user =
             match &session {
                 SessionState {
                     session: Session::User(user),
@@ -457,15 +1865,41 @@ impl FromRequest<AppState> for HeresphereSession {
                 )
                     .into_response()),
             };
+        // Remember which headset this is, so Jellyfin's session list can show it
+        if device.is_some() && user.device != device {
+            user.device = device;
+            let updated = SessionState {
+                session: Session::User(user.clone()),
+                ..session.clone()
+            };
+            match state.update_session(updated).await {
+                Ok(updated) => session = updated,
+                Err(err) => tracing::warn!(error = ?err, "Failed to store the headset device"),
+            }
+        }
 
         Ok(Self {
             request: body.clone(),
             session_state: session,
             user: user,
+            languages,
         })
     }
 }
 
+/// Headset name from the User-Agent HereSphere sends, cut down to fit Jellyfin's session list.
+/// Quotes, commas and `=` would end up in the middle of the authorization header, they're dropped.
+fn device_name(user_agent: &str) -> Option<String> {
+    let name: String = user_agent
+        .trim()
+        .chars()
+        .filter(|c| !matches!(c, '"' | ',' | '=') && !c.is_control())
+        .take(64)
+        .collect();
+    let name = name.trim().to_string();
+    (!name.is_empty()).then_some(name)
+}
+
 struct ProtoHost(String);
 
 #[async_trait]
@@ -495,39 +1929,144 @@ where
     }
 }
 
+/// Validators for a response derived from a user's cached index, `kind` distinguishes representations of the same index.
+/// The ETag names the user too, indexes of different users built at the same moment still differ.
+fn index_validators(user_key: &str, last_updated: chrono::DateTime<chrono::Utc>, kind: &str) -> (String, String) {
+    use sha2::{Digest, Sha256};
+    let user = hex::encode(&Sha256::digest(user_key.as_bytes())[..8]);
+    let etag = format!("\"{}-{}-{}\"", kind, user, last_updated.timestamp_micros());
+    let last_modified = last_updated.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+    (etag, last_modified)
+}
+
+/// Checks `If-None-Match` against the current ETag, or without one `If-Modified-Since` against when the index was built.
+fn is_not_modified(headers: &HeaderMap, etag: &str, last_updated: chrono::DateTime<chrono::Utc>) -> bool {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    if let Some(value) = header("if-none-match") {
+        return value.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*");
+    }
+    header("if-modified-since")
+        .and_then(|value| chrono::DateTime::parse_from_rfc2822(value).ok())
+        // HTTP dates have whole seconds
+        .is_some_and(|since| last_updated.timestamp() <= since.timestamp())
+}
+
+fn not_modified(etag: String, last_modified: String) -> Response {
+    (
+        StatusCode::NOT_MODIFIED,
+        [
+            (heresphere::MAGIC_HEADER, "1".to_string()),
+            ("ETag", etag),
+            ("Last-Modified", last_modified),
+        ],
+    )
+        .into_response()
+}
+
+/// Answers a conditional request from a fresh index without loading it, `None` when the full response is needed.
+async fn check_index_validators(
+    app: &AppState,
+    user_key: &str,
+    headers: &HeaderMap,
+    kind: &str,
+) -> eyre::Result<Option<Response>> {
+    let Some(last_updated) = index::HeresphereIndex::fresh_since(app, user_key).await? else {
+        return Ok(None);
+    };
+    let (etag, last_modified) = index_validators(user_key, last_updated, kind);
+    Ok(is_not_modified(headers, &etag, last_updated).then(|| not_modified(etag, last_modified)))
+}
+
 async fn heresphere_libraries(
     State(app): State<AppState>,
     ProtoHost(host): ProtoHost,
+    headers: HeaderMap,
     HeresphereSession { user, .. }: HeresphereSession,
-) -> Result<impl IntoResponse, AppError> {
+) -> Result<Response, AppError> {
+    if let Some(response) = check_index_validators(&app, &user.cache_key(), &headers, "library").await? {
+        return Ok(response);
+    }
     let cache =
-        index::HeresphereIndex::prime_data_maybe(&app, &host, &user.user_id, &user.token).await?;
+        index::HeresphereIndex::prime_data_maybe(&app, &host, &user).await?;
+    let (etag, last_modified) = index_validators(&user.cache_key(), cache.last_updated, "library");
+    if is_not_modified(&headers, &etag, cache.last_updated) {
+        return Ok(not_modified(etag, last_modified));
+    }
     Ok((
         [
-            (heresphere::MAGIC_HEADER, "1"),
-            ("Content-Type", "application/json"),
+            (heresphere::MAGIC_HEADER, "1".to_string()),
+            ("Content-Type", "application/json".to_string()),
+            ("ETag", etag),
+            ("Last-Modified", last_modified),
         ],
         format!(
             r#"{{"access": 1, "library": {}}}"#,
-            serde_json::to_string_pretty(&cache.libraries).map_err(|err| AppError(err.into()))?,
+            app.config.to_json(&cache.libraries)?,
         ),
-    ))
+    )
+        .into_response())
 }
 
 async fn heresphere_scan(
     State(app): State<AppState>,
     ProtoHost(host): ProtoHost,
+    headers: HeaderMap,
     HeresphereSession { user, .. }: HeresphereSession,
-) -> Result<impl IntoResponse, AppError> {
+) -> Result<Response, AppError> {
+    if let Some(response) = check_index_validators(&app, &user.cache_key(), &headers, "scan").await? {
+        return Ok(response);
+    }
     let cache =
-        index::HeresphereIndex::prime_data_maybe(&app, &host, &user.user_id, &user.token).await?;
+        index::HeresphereIndex::prime_data_maybe(&app, &host, &user).await?;
+    let (etag, last_modified) = index_validators(&user.cache_key(), cache.last_updated, "scan");
+    if is_not_modified(&headers, &etag, cache.last_updated) {
+        return Ok(not_modified(etag, last_modified));
+    }
     Ok((
         [
-            (heresphere::MAGIC_HEADER, "1"),
-            ("Content-Type", "application/json"),
+            (heresphere::MAGIC_HEADER, "1".to_string()),
+            ("Content-Type", "application/json".to_string()),
+            ("ETag", etag),
+            ("Last-Modified", last_modified),
         ],
-        serde_json::to_string_pretty(&cache.scan).map_err(|err| AppError(err.into()))?,
-    ))
+        scan_body(
+            index::scan_chunks(&app, &user.cache_key(), &cache, &host),
+            app.config.pretty_json,
+        ),
+    )
+        .into_response())
+}
+
+/// Streams a scan as `{"scanData": [...]}`, serializing one chunk of entries at a time
+/// as they're read instead of building the whole document in memory.
+fn scan_body<S>(chunks: S, pretty: bool) -> Body
+where
+    S: futures::Stream<Item = eyre::Result<Vec<heresphere::ScanData>>> + Send + 'static,
+{
+    let mut is_first = true;
+    let entries = chunks.map(move |chunk| {
+        let mut bytes = vec![];
+        for data in chunk? {
+            if !std::mem::take(&mut is_first) {
+                bytes.push(b',');
+            }
+            if pretty {
+                serde_json::to_writer_pretty(&mut bytes, &data)?;
+            } else {
+                serde_json::to_writer(&mut bytes, &data)?;
+            }
+        }
+        Ok::<_, eyre::Report>(Bytes::from(bytes))
+    });
+    let stream = futures::stream::once(async { Ok(Bytes::from_static(br#"{"scanData":["#)) })
+        .chain(entries)
+        .chain(futures::stream::once(async { Ok(Bytes::from_static(b"]}")) }))
+        .map_err(|err| {
+            // Too late for an error status, the response is cut short instead
+            tracing::error!(error = ?err, "Failed to stream scan");
+            Box::<dyn std::error::Error + Send + Sync>::from(err)
+        });
+    Body::from_stream(stream)
 }
 
 async fn heresphere_video(
@@ -538,35 +2077,153 @@ async fn heresphere_video(
         user,
         request,
         session_state,
+        languages,
     }: HeresphereSession,
 ) -> Result<impl IntoResponse, AppError> {
-    let mut video = index::HeresphereIndex::get_video(&app.db, &user.user_id, &vid).await?; //.ok_or(AppError(eyre::eyre!("No video found")))?;
+    // Unknown or no longer indexed videos are a 404, so HereSphere drops them instead of retrying
+    let mut video = index::HeresphereIndex::get_video(&app.db.get(), &user.cache_key(), &vid)
+        .await
+        .inspect_err(|err| {
+            if err.status() == StatusCode::NOT_FOUND {
+                tracing::debug!(video = %vid, "Requested video is not in the index");
+            }
+        })?;
+    if let (true, Some(true)) = (app.config.soft_delete, request.delete_file) {
+        index::TrashedVideo::trash(&app.db.get(), &user.cache_key(), &vid, &video.data.title).await?;
+        tracing::info!(username = %user.username, video = %vid, "Moved video to the trash");
+        // Same as any video that's gone, HereSphere drops it from its list
+        return Err(AppError(JellyVrError::NotFound(format!("Video {}", vid)).into()));
+    }
+    if let Some(video_override) = index::VideoOverride::get(&app.db.get(), &user.cache_key(), &vid).await? {
+        video_override.apply(&mut video.data);
+    }
+    // The headset's languages win over `prefered_subtitles_language`, which the index is sorted by
+    if let (false, Some(subtitles)) = (languages.is_empty(), &mut video.data.subtitles) {
+        index::sort_subtitles(subtitles, &languages);
+    }
+    // HereSphere sends its per-video settings back when they change in the headset
+    if let Some(hsp) = &request.hsp {
+        let patch = index::VideoOverridePatch {
+            hsp: Some(hsp.clone()),
+            ..Default::default()
+        };
+        index::VideoOverride::save(&app.db.get(), &user.cache_key(), &vid, &patch).await?;
+        video.data.hsp = Some(hsp.clone());
+    }
+    if let (true, false, Some(tags)) = (app.config.write_tags, app.config.read_only, &request.tags) {
+        let tags = index::user_tags(&app.config.tag_colon_replacement, tags);
+        if tags != index::user_tags(&app.config.tag_colon_replacement, &video.data.tags) {
+            tracing::debug!(video = %vid, tags = ?tags, "Writing tags back to Jellyfin");
+            // The video still has to load, the tags just stay as they were
+            match app.jellyfin_user(&user)?.update_tags(&vid, tags.clone()).await {
+                Ok(()) => {
+                    index::HeresphereIndex::set_video_user_tags(&app.db.get(), &app.config, &user.cache_key(), &mut video, &tags).await?
+                }
+                Err(e) => tracing::warn!(video = %vid, error = ?e, "Failed to write tags back to Jellyfin"),
+            }
+        }
+    }
+    // HereSphere's favorite flag stands for "unwatched", see `index::baseitem_to_video`
+    if let (true, false, Some(is_favorite)) = (app.config.write_played, app.config.read_only, request.is_favorite) {
+        if video.data.is_favorite != Some(is_favorite) {
+            let jellyfin_user = app.jellyfin_user(&user)?;
+            let written = match is_favorite {
+                true => jellyfin_user.mark_unplayed(&vid).await,
+                false => jellyfin_user.mark_played(&vid).await,
+            };
+            // The video still has to load, the flag just stays as it was
+            match written {
+                Ok(()) => {
+                    index::HeresphereIndex::set_video_favorite(&app.db.get(), &user.cache_key(), &vid, is_favorite).await?;
+                    video.data.is_favorite = Some(is_favorite);
+                }
+                Err(e) => tracing::warn!(video = %vid, error = ?e, "Failed to write the played state back to Jellyfin"),
+            }
+        }
+    }
     if let Some(true) = request.needs_media_source {
-        let jellyfin_user = app.jellyfin.client.resume_user(&user.user_id, &user.token);
-        let playback_info = jellyfin_user.playback_info(&vid).await?;
-        let play_session = playback_info
-            .play_session_id
-            .ok_or(AppError(eyre::eyre!("Failed to get play session ID")))?;
-        let new_media_source = if let Some(transcoding_url) = playback_info
-            .media_sources
-            .first()
-            .and_then(|source| source.transcoding_url.as_ref())
-        {
-            transcoding_url.clone()
-        } else {
-            format!(
-                "/Videos/{}/master.m3u8?playSessionId={}&api_key={}&mediaSourceId={}",
-                vid,
-                play_session,
-                user.token,
-                match playback_info.media_sources.first() {
-                    Some(source) => source.id.as_ref().unwrap_or(&vid),
-                    None => &vid,
+        let jellyfin_user = app.jellyfin_user(&user)?;
+        let tracks_watchtime = user.tracks_watchtime(&app.config);
+        // HereSphere asks again for the video it's already playing (ex. after a network hiccup),
+        // keep reporting to that play session instead of opening another one in Jellyfin
+        let reused_playback = user.last_known_playback.as_ref().filter(|playback| {
+            tracks_watchtime && playback.is_reusable_for(&vid) && playback.media_url.is_some()
+        });
+        let (play_session, new_media_source, is_transcoded) = match reused_playback {
+            Some(Playback {
+                play_session_id,
+                media_url: Some(media_url),
+                duration,
+                is_transcoded,
+                ..
+            }) => {
+                // Asking Jellyfin for playback info again would open a play session nothing reports to
+                tracing::debug!(video = %vid, play_session = %play_session_id, "Reusing play session");
+                if *duration > 0 {
+                    video.data.duration = jellyfin::ticks_to_ms(*duration);
                 }
-            )
+                (play_session_id.clone(), media_url.0.clone(), *is_transcoded)
+            }
+            _ => {
+                let playback_info = match app.config.prefetch_playback_info {
+                    true => index::PrefetchedPlaybackInfo::take(&app.db.get(), &app.config, &user.cache_key(), &vid).await?,
+                    false => None,
+                };
+                let playback_info = match playback_info {
+                    Some(playback_info) => {
+                        tracing::debug!(video = %vid, "Using prefetched playback info");
+                        playback_info
+                    }
+                    None => jellyfin_user.playback_info(&vid, true).await?,
+                };
+                // Some direct play setups come back without one, progress reports still need an id to group them
+                let play_session = playback_info.play_session_id.clone().unwrap_or_else(|| {
+                    let play_session = uuid::Uuid::new_v4().simple().to_string();
+                    tracing::info!(video = %vid, play_session = %play_session, "Jellyfin returned no play session ID, using a generated one");
+                    play_session
+                });
+                // Versions of an item can differ in length, the one Jellyfin picked is what the headset gets to play
+                if let Some(run_time_ticks) = playback_info
+                    .media_sources
+                    .first()
+                    .and_then(|source| source.run_time_ticks)
+                    .filter(|ticks| *ticks > 0)
+                {
+                    video.data.duration = jellyfin::ticks_to_ms(run_time_ticks);
+                }
+                match playback_info
+                    .media_sources
+                    .first()
+                    .and_then(|source| source.transcoding_url.as_ref())
+                {
+                    Some(transcoding_url) => (
+                        play_session.clone(),
+                        jellyfin::with_play_session_id(transcoding_url, &play_session),
+                        true,
+                    ),
+                    None => (
+                        play_session.clone(),
+                        format!(
+                            "{}&playSessionId={}&api_key={}&mediaSourceId={}",
+                            app.config.codec_profile.stream_path(&vid),
+                            play_session,
+                            user.token,
+                            match playback_info.media_sources.first() {
+                                Some(source) => source.id.as_ref().unwrap_or(&vid),
+                                None => &vid,
+                            }
+                        ),
+                        false,
+                    ),
+                }
+            }
         };
-        if let Some(old_playback) = user.last_known_playback {
-            if old_playback.play_session_id != play_session {
+        // Transcodes carry the subtitles themselves then, direct play still needs the external ones
+        if is_transcoded && app.config.codec_profile.embeds_subtitles() {
+            video.data.subtitles = None;
+        }
+        if let Some(old_playback) = &user.last_known_playback {
+            if old_playback.is_started && old_playback.play_session_id != play_session {
                 tracing::debug!(
                     "Updating play session ID from {} to {}",
                     &old_playback.play_session_id,
@@ -575,36 +2232,63 @@ async fn heresphere_video(
                 jellyfin_user.playback_stopped(&old_playback.video_id, &old_playback.play_session_id, old_playback.duration).await?;
             }
         }
-        video.data.event_server = Some(format!(
-            "{}/heresphere/events/{}/{}",
-            host,
-            session_state
-                .id.clone()
-                .expect("Failed to get session ID")
-                .id
-                .to_raw(),
-            vid
-        ));
-        video.data.media[0].sources[0].url =
-            format!("{}{}", app.config.jellyfin_remote_host, new_media_source);
-        let new_session_state = SessionState {
-            id: session_state.id,
-            session: Session::User(User {
-                last_known_playback: Some(Playback {
-                    play_session_id: play_session.clone(),
-                    video_id: vid.clone(),
-                    duration: (video.data.duration * 10000.0) as i64,
-                    position_estimate: 0,
-                    speed: 1.0,
-                    started_at: chrono::Utc::now(),
-                    last_update: chrono::Utc::now(),
-                    is_paused: true,
-                }),
-                ..user
-            }),
+        let sid = session_state
+            .id
+            .clone()
+            .expect("Failed to get session ID")
+            .id
+            .to_raw();
+        video.data.event_server = tracks_watchtime.then(|| format!("{}/heresphere/events/{}/{}", host, sid, vid));
+        video.data.media[0].sources[0].url = match app
+            .config
+            .hls_proxy
+            .then(|| hls_proxy_url(&host, &sid, &vid, &new_media_source))
+            .flatten()
+        {
+            Some(url) => url,
+            None => format!("{}{}", app.jellyfin.server(&user.server)?.remote_host, new_media_source),
         };
-        app.update_session(new_session_state).await?;
-        jellyfin_user.playback_start(&vid, &play_session).await?;
+        if reused_playback.is_some() {
+            tracing::debug!(video = %vid, "Playback is already tracked");
+        } else if tracks_watchtime {
+            // The cached video doesn't know where the user left off, Jellyfin does
+            let resume_position = match jellyfin_user.item(&vid).await {
+                Ok(item) => index::resume_position(&app.config, &item),
+                Err(e) => {
+                    tracing::warn!(video = %vid, error = ?e, "Failed to get resume position");
+                    0
+                }
+            };
+            let new_session_state = SessionState {
+                id: session_state.id,
+                secret: session_state.secret,
+                session: Session::User(User {
+                    last_known_playback: Some(Playback {
+                        play_session_id: play_session.clone(),
+                        video_id: vid.clone(),
+                        duration: jellyfin::ms_to_ticks(video.data.duration),
+                        position_estimate: resume_position,
+                        speed: 1.0,
+                        started_at: chrono::Utc::now(),
+                        last_update: chrono::Utc::now(),
+                        is_paused: true,
+                        is_started: false,
+                        is_marked_played: false,
+                        media_url: Some(Secret(new_media_source.clone())),
+                        is_transcoded,
+                    }),
+                    ..user
+                }),
+            };
+            let session_state = app.update_session(new_session_state).await?;
+            if let Session::User(user) = &session_state.session {
+                if let Err(e) = enforce_playback_limit(&app, user).await {
+                    tracing::warn!(username = %user.username, error = ?e, "Failed to enforce the playback limit");
+                }
+            }
+        } else {
+            tracing::debug!(video = %vid, "Watchtime tracking is off, not tracking playback");
+        }
     }
 
     tracing::debug!(video = ?video, "Found video");
@@ -613,10 +2297,107 @@ async fn heresphere_video(
             (heresphere::MAGIC_HEADER, "1"),
             ("Content-Type", "application/json"),
         ],
-        serde_json::to_string_pretty(&video.data).map_err(|err| AppError(err.into()))?,
+        app.config.to_json(&video.data)?,
+    ))
+}
+
+/// Maps a Jellyfin `/Videos/{vid}/...m3u8` url onto the `/heresphere/hls` proxy, dropping the token.
+fn hls_proxy_url(host: &str, sid: &str, vid: &str, media_source: &str) -> Option<String> {
+    let video_root = format!("/videos/{}/", vid.to_ascii_lowercase());
+    if !media_source.to_ascii_lowercase().starts_with(&video_root) {
+        return None;
+    }
+    let rest = &media_source[video_root.len()..];
+    let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+    if !path.ends_with(".m3u8") {
+        return None;
+    }
+    Some(format!(
+        "{}/heresphere/hls/{}/{}/{}?{}",
+        host,
+        sid,
+        vid,
+        path,
+        jellyfin::strip_api_key(query)
     ))
 }
 
+/// Serves HLS playlists and segments from Jellyfin, authenticating on the server. Playlists get
+/// their URIs rewritten to come back here, so the headset never sees the token or the Jellyfin host.
+async fn heresphere_hls(
+    State(app): State<AppState>,
+    ProtoHost(host): ProtoHost,
+    Path((sid, vid, path)): Path<(String, String, String)>,
+    RawQuery(query): RawQuery,
+) -> Result<Response, AppError> {
+    let SessionState {
+        session: Session::User(user),
+        ..
+    } = app.get_session_from_heresphere_event(&sid).await?
+    else {
+        return Err(AppError(JellyVrError::Unauthorized.into()));
+    };
+    if !user.is_playing(&vid) || !jellyfin::is_video_file_path(&vid, &path) {
+        return Err(AppError(JellyVrError::NotFound(format!("Video file {}/{}", vid, path)).into()));
+    }
+    let query = jellyfin::strip_api_key(&query.unwrap_or_default());
+    let response = app.jellyfin_user(&user)?.video_file(&vid, &path, &query).await?;
+    if path.ends_with(".m3u8") {
+        let content_type = proxied_header(&response, "content-type").unwrap_or("application/vnd.apple.mpegurl".to_string());
+        let playlist = response.text().await?;
+        let proxy_base = format!("{}/heresphere/hls/{}/{}", host, sid, vid);
+        let playlist = jellyfin::rewrite_hls_playlist(&playlist, &vid, &path, &proxy_base);
+        return Ok(([("Content-Type", content_type)], playlist).into_response());
+    }
+    Ok(proxied_response(response))
+}
+
+/// Serves item images from Jellyfin with header auth, see `index::ImageSource`.
+/// The url names the user and carries a signature of the image instead of a token,
+/// so HereSphere never caches or logs one and user ids alone don't open any images.
+async fn heresphere_image(
+    State(app): State<AppState>,
+    Path((user_key, signature, id, image_type)): Path<(String, String, String, String)>,
+    RawQuery(query): RawQuery,
+) -> Result<Response, AppError> {
+    if !app.config.image_proxy {
+        return Err(AppError(JellyVrError::NotFound("Image proxy".to_string()).into()));
+    }
+    if !index::verify_image_signature(&app.image_key, &user_key, &id, &image_type, &signature) {
+        return Err(AppError(JellyVrError::NotFound(format!("Image {}", id)).into()));
+    }
+    let SessionState {
+        session: Session::User(user),
+        ..
+    } = app.get_session_from_cache_key(&user_key).await?
+    else {
+        return Err(AppError(JellyVrError::Unauthorized.into()));
+    };
+    let query = jellyfin::strip_api_key(&query.unwrap_or_default());
+    let response = app.jellyfin_user(&user)?.image(&id, &image_type, &query).await?;
+    Ok(proxied_response(response))
+}
+
+fn proxied_header(response: &reqwest::Response, name: &str) -> Option<String> {
+    response
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string())
+}
+
+/// Streams a Jellyfin response through, keeping the headers that matter to the headset.
+fn proxied_response(response: reqwest::Response) -> Response {
+    let headers = [
+        (
+            "Content-Type",
+            proxied_header(&response, "content-type").unwrap_or("application/octet-stream".to_string()),
+        ),
+        ("Cache-Control", proxied_header(&response, "cache-control").unwrap_or("no-cache".to_string())),
+    ];
+    (headers, Body::from_stream(response.bytes_stream())).into_response()
+}
+
 async fn heresphere_event(
     State(app): State<AppState>,
     ProtoHost(host): ProtoHost,
@@ -628,48 +2409,100 @@ async fn heresphere_event(
         Ok(SessionState {
             session: Session::User(user),
             id,
+            secret,
         }) => {
             tracing::debug!(user = ?user, "Got user session");
+            if !user.tracks_watchtime(&app.config) {
+                return Ok(());
+            }
+            if app.config.remote_control && event.event != heresphere::EventType::Close {
+                app.remote_listeners.ensure(&app, &sid);
+            }
             match event.event {
                 heresphere::EventType::Open => {
-                    // NO OP
+                    if let Some(playback) = start_prepared_playback(&app, &user, &vid).await? {
+                        let new_session_state = SessionState {
+                            id,
+                            secret,
+                            session: Session::User(User {
+                                last_known_playback: Some(playback),
+                                ..user
+                            }),
+                        };
+                        app.update_session(new_session_state).await?;
+                    }
                 },
                 heresphere::EventType::Play => {
+                    // Seeks are reported as Play events too, so the event position is always the new baseline
+                    let playback = match start_prepared_playback(&app, &user, &vid).await? {
+                        Some(playback) => playback,
+                        None => {
+                            // Stopped by the playback limit or tracking toggle, or about another video
+                            let Some(playback) = user.last_known_playback.clone().filter(|playback| playback.video_id == vid) else {
+                                return Ok(());
+                            };
+                            playback
+                        }
+                    };
                     // Update last known playback with is_paused = false, speed = event.speed, time = event.time
+                    let playback = Playback {
+                        is_paused: false,
+                        speed: event.speed,
+                        position_estimate: jellyfin::ms_to_ticks(event.time),
+                        last_update: chrono::Utc::now(),
+                        ..playback
+                    };
+                    let jellyfin_user = app.jellyfin_user(&user)?;
                     let new_session_state = SessionState {
                         id,
+                        secret,
                         session: Session::User(User {
-                            last_known_playback: Some(Playback {
-                                is_paused: false,
-                                speed: event.speed,
-                                position_estimate: (event.time * 10000.0) as i64,
-                                last_update: chrono::Utc::now(),
-                                ..user.last_known_playback.unwrap()
-                            }),
+                            last_known_playback: Some(playback.clone()),
                             ..user
                         }),
                     };
                     app.update_session(new_session_state).await?;
+                    if !playback.is_started {
+                        return Ok(());
+                    }
+                    // Push the new position right away instead of waiting for the next poll,
+                    // the poll still reconciles if this doesn't get through
+                    if let Err(e) = jellyfin_user.playback_progress(&playback.video_id, &playback.play_session_id, playback.position_estimate, false, playback.started_at).await {
+                        tracing::warn!(video_id = &playback.video_id, error = ?e, "Failed to push progress on play");
+                    }
                 },
                 heresphere::EventType::Pause => {
+                    // Videos can be opened paused, that's still the headset playing it
+                    let playback = match start_prepared_playback(&app, &user, &vid).await? {
+                        Some(playback) => playback,
+                        None => {
+                            let Some(playback) = user.last_known_playback.clone().filter(|playback| playback.video_id == vid) else {
+                                return Ok(());
+                            };
+                            playback
+                        }
+                    };
                     // Update last known playback with is_paused = true, speed = event.speed, time = event.time
                     let new_session_state = SessionState {
                         id,
+                        secret,
                         session: Session::User(User {
                             last_known_playback: Some(Playback {
                                 is_paused: true,
                                 speed: event.speed,
-                                position_estimate: (event.time * 10000.0) as i64,
+                                position_estimate: jellyfin::ms_to_ticks(event.time),
                                 last_update: chrono::Utc::now(),
-                                ..user.last_known_playback.clone().unwrap()
+                                ..playback.clone()
                             }),
                             ..user.clone()
                         }),
                     };
                     app.update_session(new_session_state).await?;
-                    let jellyfin_user = app.jellyfin.client.resume_user(&user.user_id, &user.token);
-                    let playback = user.last_known_playback.as_ref().unwrap();
-                    jellyfin_user.playback_progress(&playback.video_id, &playback.play_session_id, (event.time * 10000.0) as i64, true, playback.started_at).await?;
+                    let jellyfin_user = app.jellyfin_user(&user)?;
+                    if !playback.is_started {
+                        return Ok(());
+                    }
+                    jellyfin_user.playback_progress(&playback.video_id, &playback.play_session_id, jellyfin::ms_to_ticks(event.time), true, playback.started_at).await?;
                     
                 },
                 heresphere::EventType::Close => {
@@ -690,65 +2523,1624 @@ async fn heresphere_event(
     }
 }
 
-async fn progress_update_routine(app: &AppState) -> eyre::Result<()> {
-    let mut updated = 0;
-    let sessions: Vec<SessionState> = app.db.query("SELECT * FROM session").await?.check()?.take(0)?;
-    for session in sessions {
-        match session.session {
-            Session::User(user) => {
-                if let Some(playback) = user.last_known_playback {
-                    if playback.is_paused {
-                        continue;
-                    }
-                    let new_position = playback.position_estimate
-                        + ((chrono::Utc::now().signed_duration_since(playback.last_update).num_milliseconds() as f64) * playback.speed).round() as i64 * 10000;
-                    if playback.duration > 0 && new_position > playback.duration {
-                        tracing::debug!(
-                            video_id = &playback.video_id,
-                            play_session_id = &playback.play_session_id,
-                            "Playback position predicted to be greater than duration, stopping playback"
-                        );
-                        let new_session_state = SessionState {
-                            id: session.id,
-                            session: Session::User(User {
-                                last_known_playback: Some(Playback {
-                                    is_paused: true,
-                                    last_update: chrono::Utc::now(),
-                                    ..playback
-                                }),
-                                ..user
-                            }),
-                        };
-                        app.update_session(new_session_state).await?;
-                        continue;
-                    }
-                    tracing::debug!(
-                        video_id = &playback.video_id,
-                        play_session_id = &playback.play_session_id,
-                        "Updating playback position from {} to {}",
-                        playback.position_estimate,
-                        new_position
-                    );
-                    let jellyfin_user = app.jellyfin.client.resume_user(&user.user_id, &user.token);
-                    jellyfin_user.playback_progress(&playback.video_id, &playback.play_session_id, new_position, playback.is_paused, playback.started_at).await?;
-                    let new_session_state = SessionState {
-                        id: session.id,
-                        session: Session::User(User {
-                            last_known_playback: Some(Playback {
-                                position_estimate: new_position,
-                                last_update: chrono::Utc::now(),
-                                ..playback
-                            }),
-                            ..user
-                        }),
-                    };
-                    app.update_session(new_session_state).await?;
-                    updated += 1;
-                }
+/// Tells Jellyfin about a playback prepared by `heresphere_video` once the headset actually opens it.
+/// Returns the started playback, or `None` if there was nothing to start for this video.
+async fn start_prepared_playback(
+    app: &AppState,
+    user: &User,
+    vid: &str,
+) -> eyre::Result<Option<Playback>> {
+    match &user.last_known_playback {
+        Some(playback) if playback.awaits_start_of(vid) => {
+            let jellyfin_user = app.jellyfin_user(&user)?;
+            jellyfin_user.playback_start(&playback.video_id, &playback.play_session_id).await?;
+            Ok(Some(Playback {
+                is_started: true,
+                started_at: chrono::Utc::now(),
+                last_update: chrono::Utc::now(),
+                ..playback.clone()
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Stops the oldest playbacks of a user once more of their sessions hold one than `max_playbacks_per_user`,
+/// so quickly opening videos from several sessions doesn't pile up sessions in Jellyfin's dashboard.
+async fn enforce_playback_limit(app: &AppState, user: &User) -> eyre::Result<()> {
+    let limit = app.config.max_playbacks_per_user;
+    if limit == 0 {
+        return Ok(());
+    }
+    let db = app.db.get();
+    let (db, user_id) = (&db, &user.user_id);
+    let sessions: Vec<SessionState> = retry_db(|| async move {
+        db.query("SELECT * FROM session WHERE session.User.user_id = $user_id AND session.User.last_known_playback != NONE")
+            .bind(("user_id", user_id))
+            .await?
+            .check()
+    })
+    .await?
+    .take(0)?;
+    let mut playing: Vec<(SessionState, User, Playback)> = sessions
+        .into_iter()
+        .filter_map(|state| match &state.session {
+            Session::User(session_user) if session_user.cache_key() == user.cache_key() => {
+                let session_user = session_user.clone();
+                let playback = session_user.last_known_playback.clone()?;
+                Some((state, session_user, playback))
             }
-            _ => {}
+            _ => None,
+        })
+        .collect();
+    if playing.len() <= limit {
+        return Ok(());
+    }
+    playing.sort_by_key(|(_, _, playback)| playback.last_update);
+    let excess = playing.len() - limit;
+    for (state, session_user, playback) in playing.into_iter().take(excess) {
+        tracing::info!(
+            username = %session_user.username,
+            video = %playback.video_id,
+            "Too many playbacks at once, stopping the oldest"
+        );
+        if playback.is_started {
+            app.jellyfin_user(&session_user)?
+                .playback_stopped(&playback.video_id, &playback.play_session_id, playback.position_estimate)
+                .await?;
+        }
+        app.update_session(SessionState {
+            session: Session::User(User {
+                last_known_playback: None,
+                ..session_user
+            }),
+            ..state
+        })
+        .await?;
+    }
+    Ok(())
+}
+
+async fn progress_update_routine(app: &AppState) -> eyre::Result<()> {
+    let sessions: Vec<SessionState> = app.db.get().query("SELECT * FROM session").await?.check()?.take(0)?;
+    // Talk to Jellyfin first, then write every session back in one go
+    let mut updates = vec![];
+    for session in sessions {
+        let Session::User(user) = &session.session else {
+            continue;
+        };
+        let Some(playback) = &user.last_known_playback else {
+            continue;
+        };
+        if playback.is_paused || !playback.is_started || !user.tracks_watchtime(&app.config) {
+            continue;
+        }
+        match next_playback(app, user, playback).await {
+            Ok(next) => {
+                let last_update = playback.last_update;
+                let user = User {
+                    last_known_playback: Some(next),
+                    ..user.clone()
+                };
+                updates.push((
+                    SessionState {
+                        session: Session::User(user),
+                        ..session
+                    },
+                    last_update,
+                ));
+            }
+            Err(e) => {
+                tracing::warn!(username = %user.username, error = ?e, "Failed to update progress");
+            }
+        }
+    }
+    if updates.is_empty() {
+        return Ok(());
+    }
+    let mut updated = 0;
+    for (id, result) in update_sessions_if_unchanged(&app.db.get(), updates).await? {
+        match result {
+            Ok(Some(_)) => updated += 1,
+            Ok(None) => tracing::debug!(session = %id, "Playback was updated by an event in the meantime, skipping"),
+            Err(e) => tracing::warn!(session = %id, error = ?e, "Failed to save playback position"),
         }
     }
     tracing::info!(updated, "Updated playback positions");
     Ok(())
 }
+
+/// Estimates where a running playback is now and reports it to Jellyfin,
+/// marking it played past the completion threshold and pausing it once it runs past its end.
+async fn next_playback(app: &AppState, user: &User, playback: &Playback) -> eyre::Result<Playback> {
+    let new_position = playback.predicted_position();
+    let jellyfin_user = app.jellyfin_user(user)?;
+    let is_marked_played = if !playback.is_marked_played
+        && playback.duration > 0
+        && new_position as f64 >= playback.duration as f64 * app.config.completion_threshold
+    {
+        tracing::debug!(
+            video_id = &playback.video_id,
+            play_session_id = &playback.play_session_id,
+            "Playback crossed completion threshold, marking as played"
+        );
+        jellyfin_user.mark_played(&playback.video_id).await?;
+        true
+    } else {
+        playback.is_marked_played
+    };
+    if playback.duration > 0 && new_position > playback.duration {
+        tracing::debug!(
+            video_id = &playback.video_id,
+            play_session_id = &playback.play_session_id,
+            "Playback position predicted to be greater than duration, stopping playback"
+        );
+        return Ok(Playback {
+            is_paused: true,
+            is_marked_played,
+            last_update: chrono::Utc::now(),
+            ..playback.clone()
+        });
+    }
+    tracing::debug!(
+        video_id = &playback.video_id,
+        play_session_id = &playback.play_session_id,
+        "Updating playback position from {} to {}",
+        playback.position_estimate,
+        new_position
+    );
+    jellyfin_user.playback_progress(&playback.video_id, &playback.play_session_id, new_position, playback.is_paused, playback.started_at).await?;
+    Ok(Playback {
+        position_estimate: new_position,
+        is_marked_played,
+        last_update: chrono::Utc::now(),
+        ..playback.clone()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub(crate) async fn test_db() -> eyre::Result<(Surreal<Db>, std::path::PathBuf)> {
+        let path = std::env::temp_dir().join(format!("jellyvr-main-{}", uuid::Uuid::new_v4().simple()));
+        let db = Surreal::new::<RocksDb>(path.to_str().expect("Temp dir isn't UTF-8")).await?;
+        db.use_ns("jellyvr").use_db("jellyvr").await?;
+        Ok((db, path))
+    }
+
+    /// Config of a JellyVR without any environment set, talking to the Jellyfin at `jellyfin_url`.
+    pub(crate) fn test_config(jellyfin_url: &str) -> AppConfig {
+        AppConfig {
+            jellyfin_servers: vec![JellyfinServerConfig {
+                name: default_server(),
+                api_host: jellyfin_url.to_string(),
+                remote_host: jellyfin_url.to_string(),
+            }],
+            cache_lifetime: Duration::from_secs(60 * 5),
+            cache_retention: Duration::from_secs(7 * 24 * 60 * 60),
+            cache_vacuum_interval: Duration::from_secs(24 * 60 * 60),
+            prefered_subtitles_language: Some("eng".to_string()),
+            watchtime_tracking: true,
+            read_only: false,
+            progress_interval: Duration::from_secs(30),
+            codec_profile: jellyfin::CodecProfile::preset("default").expect("No default codec profile"),
+            hls_proxy: true,
+            image_proxy: false,
+            max_concurrent_requests: 8,
+            completion_threshold: 0.9,
+            resume_rewind: Duration::from_secs(5),
+            max_playbacks_per_user: 2,
+            remote_control: false,
+            item_types: vec!["Movie".to_string(), "Episode".to_string()],
+            prefetch_playback_info: false,
+            prefetch_playback_info_ttl: Duration::from_secs(300),
+            item_fields: jellyfin::DEFAULT_ITEM_FIELDS.iter().map(|field| field.to_string()).collect(),
+            item_filters: jellyfin::ItemFilters {
+                is_played: None,
+                is_favorite: None,
+                filters: vec![],
+            },
+            include_libraries: vec![],
+            exclude_libraries: vec![],
+            min_rating: None,
+            unrated_passes_min_rating: true,
+            restricted_ratings: vec![],
+            restricted_tags: vec![],
+            max_official_rating: None,
+            restrict_unrated: false,
+            recently_added_count: 50,
+            continue_watching: true,
+            next_up: true,
+            collapse_series: false,
+            browse_by_year: "none".to_string(),
+            browse_by: vec![],
+            browse_by_min_videos: 2,
+            browse_by_images: false,
+            live_tv: false,
+            enable_trickplay: false,
+            default_projection: "perspective".to_string(),
+            default_stereo: "mono".to_string(),
+            image_type: None,
+            image_types: HashMap::new(),
+            lens_presets: lens_presets_from_env().expect("Invalid built-in lens presets"),
+            projection_fov: projection_fovs_from_env().expect("Invalid built-in projection FOVs"),
+            camera_ipd: None,
+            stereo_markers: stereo_markers_from_env().expect("Invalid built-in stereo markers"),
+            format_tags: false,
+            technical_details: "none".to_string(),
+            eye_swap_markers: vec!["RL".to_string(), "SWAPPED".to_string()],
+            lens_camera_ipd: HashMap::new(),
+            skip_generic_chapters: false,
+            skip_broken_media: true,
+            forced_subtitles: true,
+            sdh_subtitles: true,
+            label_parts: true,
+            tag_tracks: default_tag_tracks(),
+            tag_blocklist: vec![],
+            episode_title_template: index::DEFAULT_EPISODE_TITLE_TEMPLATE.to_string(),
+            tag_colon_replacement: "\u{A789}".to_string(),
+            max_people: None,
+            write_tags: false,
+            write_played: false,
+            soft_delete: false,
+            warm_cache_on_start: false,
+            scan_chunk_size: 1000,
+            scan_concurrency: 4,
+            pretty_json: false,
+            debug_endpoints: false,
+            strict_startup: false,
+            admin_token: None,
+            status_pin: None,
+            login_max_failures: 5,
+            login_lockout: Duration::from_secs(300),
+            cookie_secure: None,
+            cookie_http_only: true,
+            cookie_same_site: "lax".to_string(),
+        }
+    }
+
+    pub(crate) fn test_app(db: Surreal<Db>, config: AppConfig) -> AppState {
+        let http_client = jellyfin::JellyfinClient::http_client().expect("Failed to build HTTP client");
+        AppState {
+            jellyfin: JellyfinState::new(&config, &http_client),
+            db: db.into(),
+            config,
+            login_throttle: LoginThrottle::default(),
+            remote_listeners: remote::RemoteListeners::default(),
+            image_key: Secret("image-key".to_string()),
+        }
+    }
+
+    pub(crate) async fn body_text(response: Response) -> eyre::Result<String> {
+        let bytes = response.into_body().collect().await?.to_bytes();
+        Ok(String::from_utf8(bytes.to_vec())?)
+    }
+
+    /// Jellyfin ids are UUIDs, the client parses them for some requests.
+    pub(crate) const USER_ID: &str = "11111111111111111111111111111111";
+    pub(crate) const VIDEO_ID: &str = "22222222222222222222222222222222";
+
+    /// Requests a mock Jellyfin got, as `METHOD /path`.
+    pub(crate) type Calls = std::sync::Arc<std::sync::Mutex<Vec<String>>>;
+
+    /// Serves `routes` as a stand-in Jellyfin on a free local port, recording every request it gets.
+    /// Anything not routed is a 404, like an item Jellyfin doesn't know.
+    pub(crate) async fn mock_jellyfin(routes: Router) -> eyre::Result<(String, Calls)> {
+        let calls = Calls::default();
+        let recorded = calls.clone();
+        let app = routes
+            .fallback(|| async { StatusCode::NOT_FOUND })
+            .layer(axum::middleware::from_fn(move |request: ExtractRequest, next: axum::middleware::Next| {
+                let calls = recorded.clone();
+                async move {
+                    let call = format!("{} {}", request.method(), request.uri().path());
+                    calls.lock().expect("Calls poisoned").push(call);
+                    next.run(request).await
+                }
+            }));
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        Ok((url, calls))
+    }
+
+    /// Number of recorded requests to exactly `call`, ex. `POST /Sessions/Playing`.
+    pub(crate) fn calls_to(calls: &Calls, call: &str) -> usize {
+        calls.lock().expect("Calls poisoned").iter().filter(|recorded| *recorded == call).count()
+    }
+
+    /// Sends a HereSphere event for `VIDEO_ID` at `time` milliseconds.
+    pub(crate) async fn send_event(app: &AppState, sid: &str, event: u8, time: f64) -> eyre::Result<()> {
+        let event = serde_json::json!({
+            "username": "alice",
+            "id": VIDEO_ID,
+            "title": "Video",
+            "event": event,
+            "time": time,
+            "speed": 1.0,
+            "utc": 0.0,
+            "connectionKey": "",
+        });
+        heresphere_event(
+            State(app.clone()),
+            ProtoHost("https://jellyvr.example".to_string()),
+            Path((sid.to_string(), VIDEO_ID.to_string())),
+            Json(serde_json::from_value(event)?),
+        )
+        .await
+        .map_err(|e| e.0)
+    }
+
+    /// Jellyfin answering everything a playback goes through, for a video that's direct played.
+    pub(crate) fn playback_routes() -> Router {
+        Router::new()
+            .route(
+                "/Items/:id/PlaybackInfo",
+                axum::routing::any(|| async {
+                    Json(serde_json::json!({
+                        "PlaySessionId": "play",
+                        "MediaSources": [{ "Id": "source", "SupportsDirectPlay": true, "Container": "mp4" }],
+                    }))
+                }),
+            )
+            .route("/Sessions/Playing", axum::routing::post(|| async { StatusCode::NO_CONTENT }))
+            .route("/Sessions/Playing/Progress", axum::routing::post(|| async { StatusCode::NO_CONTENT }))
+            .route("/Sessions/Playing/Stopped", axum::routing::post(|| async { StatusCode::NO_CONTENT }))
+            .route("/Users/:user/PlayedItems/:id", axum::routing::any(|| async { StatusCode::OK }))
+    }
+
+    /// Jellyfin whose library of every user holds `items`.
+    pub(crate) fn library_routes(items: serde_json::Value) -> Router {
+        Router::new().route(
+            "/Users/:user/Items",
+            axum::routing::get(move || async move {
+                let count = items.as_array().map_or(0, Vec::len);
+                Json(serde_json::json!({ "Items": items, "TotalRecordCount": count }))
+            }),
+        )
+    }
+
+    /// Asks for the media source of `VIDEO_ID` like HereSphere does before playing it.
+    pub(crate) async fn request_media_source(app: &AppState, state: &SessionState) -> eyre::Result<serde_json::Value> {
+        let response = heresphere_video(
+            State(app.clone()),
+            ProtoHost("https://jellyvr.example".to_string()),
+            Path(VIDEO_ID.to_string()),
+            heresphere_session(
+                state.clone(),
+                serde_json::json!({ "username": "alice", "password": "password", "needsMediaSource": true }),
+            ),
+        )
+        .await
+        .map_err(|e| e.0)?
+        .into_response();
+        Ok(serde_json::from_str(&body_text(response).await?)?)
+    }
+
+    /// Stores a logged in session of a user with a Jellyfin id, for endpoints that talk to Jellyfin.
+    pub(crate) async fn create_user_session(db: &Surreal<Db>, username: &str) -> eyre::Result<SessionState> {
+        let created: Vec<SessionState> = db
+            .create("session")
+            .content(SessionState {
+                id: None,
+                secret: Some(gen_session_secret()),
+                session: Session::User(User {
+                    server: DEFAULT_SERVER.to_string(),
+                    user_id: USER_ID.to_string(),
+                    token: "token".to_string(),
+                    username: username.to_string(),
+                    jellyvr_password: "password".to_string(),
+                    last_known_playback: None,
+                    watchtime_tracking: None,
+                    device: None,
+                    device_id: None,
+                }),
+            })
+            .await?;
+        Ok(created.into_iter().next().expect("No session created"))
+    }
+
+    /// Caches a video for a user, with one media source for the video endpoint to fill in.
+    pub(crate) async fn seed_video(db: &Surreal<Db>, user_key: &str, vid: &str, data: heresphere::VideoData) -> eyre::Result<()> {
+        let data = heresphere::VideoData {
+            media: vec![heresphere::Media {
+                name: "mp4".to_string(),
+                sources: vec![heresphere::MediaSource::default()],
+            }],
+            ..data
+        };
+        db.query("CREATE type::thing('videos', [$user, $id]) CONTENT { data: $data, last_updated: time::now() }")
+            .bind(("user", user_key))
+            .bind(("id", vid))
+            .bind(("data", data))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    /// What the extractor hands a HereSphere endpoint for a logged in session.
+    pub(crate) fn heresphere_session(session_state: SessionState, request: serde_json::Value) -> HeresphereSession {
+        let Session::User(user) = session_state.session.clone() else {
+            panic!("Not a user session");
+        };
+        HeresphereSession {
+            request: Json(serde_json::from_value(request).expect("Invalid HereSphere request")),
+            session_state,
+            user,
+            languages: vec![],
+        }
+    }
+
+    fn quick_connect_session(secret: Option<&str>) -> SessionState {
+        SessionState {
+            id: None,
+            secret: secret.map(str::to_string),
+            session: Session::QuickConnect(QuickConnect::new(
+                DEFAULT_SERVER.to_string(),
+                "qc-secret".to_string(),
+                "123456".to_string(),
+            )),
+        }
+    }
+
+    fn playing_session(id: &str, position: i64, last_update: chrono::DateTime<chrono::Utc>) -> SessionState {
+        SessionState {
+            id: Some(surrealdb::sql::Thing::from(("session", id))),
+            secret: Some(gen_session_secret()),
+            session: Session::User(User {
+                server: DEFAULT_SERVER.to_string(),
+                user_id: "user".to_string(),
+                token: "token".to_string(),
+                username: id.to_string(),
+                jellyvr_password: "password".to_string(),
+                last_known_playback: Some(Playback {
+                    play_session_id: "play".to_string(),
+                    video_id: "video".to_string(),
+                    duration: 0,
+                    position_estimate: position,
+                    speed: 1.0,
+                    started_at: last_update,
+                    last_update,
+                    is_paused: false,
+                    is_started: true,
+                    is_marked_played: false,
+                    media_url: None,
+                    is_transcoded: false,
+                }),
+                watchtime_tracking: None,
+                device: None,
+                device_id: None,
+            }),
+        }
+    }
+
+    fn position(state: &SessionState) -> Option<i64> {
+        match &state.session {
+            Session::User(user) => user.last_known_playback.as_ref().map(|p| p.position_estimate),
+            Session::QuickConnect(_) => None,
+        }
+    }
+
+    #[test]
+    fn repeated_requests_start_playback_once() {
+        let session = playing_session("user", 0, chrono::Utc::now());
+        let Session::User(User { last_known_playback: Some(started), .. }) = session.session else {
+            panic!("Not a playing session");
+        };
+        let prepared = Playback {
+            is_started: false,
+            ..started.clone()
+        };
+        // Open and the Play event right after it only start the playback once
+        assert!(prepared.awaits_start_of("video"));
+        assert!(!started.awaits_start_of("video"));
+        assert!(!prepared.awaits_start_of("other"));
+        // Asking for the same video again keeps the started playback instead of preparing a new one
+        assert!(started.is_reusable_for("video"));
+        assert!(!started.is_reusable_for("other"));
+        let stale = Playback {
+            last_update: chrono::Utc::now() - chrono::Duration::minutes(PLAY_SESSION_REUSE_MINUTES + 1),
+            ..started
+        };
+        assert!(!stale.is_reusable_for("video"));
+    }
+
+    #[test]
+    fn hls_files_need_a_playback_of_the_video() {
+        let Session::User(mut user) = playing_session("user", 0, chrono::Utc::now()).session else {
+            panic!("Not a playing session");
+        };
+        assert!(user.is_playing("video"));
+        assert!(user.is_playing("VIDEO"));
+        assert!(!user.is_playing("other"));
+        // A session that never played anything opens no files at all
+        user.last_known_playback = None;
+        assert!(!user.is_playing("video"));
+    }
+
+    #[tokio::test]
+    async fn credentials_stay_hidden_until_the_pin_is_posted() -> eyre::Result<()> {
+        let (db, path) = test_db().await?;
+        let mut config = test_config("http://127.0.0.1:9");
+        config.status_pin = Some(Secret("2468".to_string()));
+        config.login_max_failures = 2;
+        let app = test_app(db.clone(), config);
+        let Session::User(user) = playing_session("alice", 0, chrono::Utc::now()).session else {
+            panic!("Not a user session");
+        };
+        let created: Vec<SessionState> = db
+            .create("session")
+            .content(SessionState {
+                id: None,
+                secret: Some(gen_session_secret()),
+                session: Session::User(User {
+                    jellyvr_password: "hs-pass-1357".to_string(),
+                    ..user
+                }),
+            })
+            .await?;
+        let secret = created[0].secret.clone().expect("Session without a secret");
+        let session_jar = || CookieJar::new().add(Cookie::new("jellyvr_session", secret.clone()));
+        let host = || ProtoHost("https://jellyvr.example".to_string());
+        let page = |jar: CookieJar| {
+            root(
+                State(app.clone()),
+                host(),
+                Query(RootQuery {
+                    server: None,
+                    watchtime_tracking: None,
+                }),
+                jar,
+            )
+        };
+        let unlock = |pin: &str| {
+            unlock_root(
+                State(app.clone()),
+                host(),
+                session_jar(),
+                Form(UnlockForm { pin: pin.to_string() }),
+            )
+        };
+        let unlock_cookie = |response: &Response| {
+            response
+                .headers()
+                .get_all("set-cookie")
+                .iter()
+                .filter_map(|value| Cookie::parse(value.to_str().ok()?.to_string()).ok())
+                .find(|cookie| cookie.name() == STATUS_UNLOCK_COOKIE)
+        };
+
+        let html = body_text(page(session_jar()).await.map_err(|e| e.0)?).await?;
+        assert!(!html.contains("hs-pass-1357"));
+        // A wrong PIN unlocks nothing
+        let response = unlock("1111").await.map_err(|e| e.0)?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(unlock_cookie(&response).is_none());
+        // Nor does an unlock cookie made up by someone else
+        let forged = session_jar().add(Cookie::new(STATUS_UNLOCK_COOKIE, "0".repeat(64)));
+        assert!(!body_text(page(forged).await.map_err(|e| e.0)?).await?.contains("hs-pass-1357"));
+
+        let response = unlock("2468").await.map_err(|e| e.0)?;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        let cookie = unlock_cookie(&response).expect("No unlock cookie");
+        let html = body_text(page(session_jar().add(cookie)).await.map_err(|e| e.0)?).await?;
+        assert!(html.contains("hs-pass-1357"));
+
+        // Guessing gets throttled, even the right PIN is turned away then
+        for _ in 0..2 {
+            unlock("1111").await.map_err(|e| e.0)?;
+        }
+        let response = unlock("2468").await.map_err(|e| e.0)?;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(unlock_cookie(&response).is_none());
+
+        drop(app);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn session_cookies_are_secure_over_https() -> eyre::Result<()> {
+        let (db, path) = test_db().await?;
+        let app = test_app(db.clone(), test_config("http://127.0.0.1:9"));
+        let secret = create_user_session(&db, "alice").await?.secret.expect("Session without a secret");
+        let session_cookie = |host: &str| {
+            let app = app.clone();
+            let jar = CookieJar::new().add(Cookie::new("jellyvr_session", secret.clone()));
+            let host = ProtoHost(host.to_string());
+            async move {
+                let response = root(State(app), host, Query(RootQuery { server: None, watchtime_tracking: None }), jar)
+                    .await
+                    .map_err(|e| e.0)?;
+                let cookie = response
+                    .headers()
+                    .get_all("set-cookie")
+                    .iter()
+                    .filter_map(|value| Cookie::parse(value.to_str().ok()?.to_string()).ok())
+                    .find(|cookie| cookie.name() == "jellyvr_session")
+                    .ok_or_else(|| eyre::eyre!("No session cookie"))?;
+                eyre::Ok(cookie)
+            }
+        };
+
+        let cookie = session_cookie("https://jellyvr.example").await?;
+        assert_eq!(cookie.value(), secret);
+        assert_eq!(cookie.secure(), Some(true));
+        assert_eq!(cookie.http_only(), Some(true));
+        assert_eq!(cookie.same_site(), Some(SameSite::Lax));
+        assert_eq!(cookie.path(), Some("/"));
+        // Browsers drop secure cookies on plain http, a local setup would never stay logged in
+        let cookie = session_cookie("http://jellyvr.local").await?;
+        assert_ne!(cookie.secure(), Some(true));
+        drop(app);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn streamed_scan_matches_the_buffered_one() -> eyre::Result<()> {
+        let (db, path) = test_db().await?;
+        let mut config = test_config("http://127.0.0.1:9");
+        config.scan_chunk_size = 2;
+        let app = test_app(db.clone(), config);
+        let host = "https://jellyvr.example";
+        // Not in id order, the scan keeps the order of the index
+        let ids = ["c", "a", "d", "b", "e"].map(str::to_string);
+        let mut expected = vec![];
+        for (i, id) in ids.iter().enumerate() {
+            let data = heresphere::VideoData {
+                title: format!("Video {}", i),
+                duration: 1000.0 * i as f64,
+                ..Default::default()
+            };
+            db.query("CREATE type::thing('videos', [$user, $id]) CONTENT { data: $data, last_updated: time::now() }")
+                .bind(("user", "user"))
+                .bind(("id", id))
+                .bind(("data", &data))
+                .await?
+                .check()?;
+            expected.push(heresphere::ScanData {
+                link: format!("{}/heresphere/{}", host, id),
+                video: data,
+            });
+        }
+        let index = index::HeresphereIndex {
+            id: None,
+            libraries: vec![],
+            // A video dropped since the index was built is left out
+            videos: ids.iter().cloned().chain(["gone".to_string()]).collect(),
+            last_updated: chrono::Utc::now(),
+            host: Some(host.to_string()),
+        };
+
+        let body = scan_body(index::scan_chunks(&app, "user", &index, host), false);
+        let streamed: serde_json::Value = serde_json::from_slice(&body.collect().await?.to_bytes())?;
+        let buffered = serde_json::to_value(heresphere::Scan { scan_data: expected })?;
+        assert_eq!(streamed, buffered);
+        drop(app);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unchanged_indexes_are_not_modified() -> eyre::Result<()> {
+        let (db, path) = test_db().await?;
+        let app = test_app(db.clone(), test_config("http://127.0.0.1:9"));
+        let session = playing_session("alice", 0, chrono::Utc::now());
+        let last_updated = chrono::Utc::now();
+        let _: Option<index::HeresphereIndex> = db
+            .create(("index", "user"))
+            .content(index::HeresphereIndex {
+                id: Some(surrealdb::sql::Thing::from(("index", "user"))),
+                libraries: vec![],
+                videos: vec![],
+                last_updated,
+                host: Some("https://jellyvr.example".to_string()),
+            })
+            .await?;
+        let libraries = |headers: &[(&'static str, String)]| {
+            let headers: HeaderMap = headers
+                .iter()
+                .map(|(name, value)| (axum::http::HeaderName::from_static(name), value.parse().expect("Invalid header")))
+                .collect();
+            heresphere_libraries(
+                State(app.clone()),
+                ProtoHost("https://jellyvr.example".to_string()),
+                headers,
+                heresphere_session(session.clone(), serde_json::json!({ "username": "alice", "password": "password" })),
+            )
+        };
+
+        let response = libraries(&[]).await.map_err(|e| e.0)?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()["etag"].to_str()?.to_string();
+        let last_modified = response.headers()["last-modified"].to_str()?.to_string();
+        let response = libraries(&[("if-none-match", etag.clone())]).await.map_err(|e| e.0)?;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        let response = libraries(&[("if-modified-since", last_modified)]).await.map_err(|e| e.0)?;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        // An ETag from before the rebuild, or one of another user, gets the whole index
+        let response = libraries(&[("if-none-match", "\"library-0-0\"".to_string())]).await.map_err(|e| e.0)?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(index_validators("other", last_updated, "library").0, etag);
+        drop(app);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prefetched_playback_info_provides_the_media_url() -> eyre::Result<()> {
+        let routes = Router::new().route(
+            "/Items/:id/PlaybackInfo",
+            axum::routing::any(|| async {
+                Json(serde_json::json!({
+                    "PlaySessionId": "prefetched",
+                    "MediaSources": [{
+                        "Id": "source",
+                        "TranscodingUrl": format!("/videos/{}/master.m3u8?PlaySessionId=prefetched&api_key=token", VIDEO_ID),
+                    }],
+                }))
+            }),
+        );
+        let (jellyfin_url, calls) = mock_jellyfin(routes).await?;
+        let (db, path) = test_db().await?;
+        let mut config = test_config(&jellyfin_url);
+        config.prefetch_playback_info = true;
+        let app = test_app(db.clone(), config);
+        let state = create_user_session(&db, "alice").await?;
+        let Session::User(user) = &state.session else {
+            panic!("Not a user session");
+        };
+        seed_video(&db, &user.cache_key(), VIDEO_ID, heresphere::VideoData::default()).await?;
+
+        index::PrefetchedPlaybackInfo::prefetch(&app, user, &[VIDEO_ID.to_string()]).await.map_err(|e| e.0)?;
+        let playback_info = format!("GET /Items/{}/PlaybackInfo", VIDEO_ID);
+        assert_eq!(calls_to(&calls, &playback_info), 1);
+        let response = heresphere_video(
+            State(app.clone()),
+            ProtoHost("https://jellyvr.example".to_string()),
+            Path(VIDEO_ID.to_string()),
+            heresphere_session(
+                state.clone(),
+                serde_json::json!({ "username": "alice", "password": "password", "needsMediaSource": true }),
+            ),
+        )
+        .await
+        .map_err(|e| e.0)?
+        .into_response();
+        let video: serde_json::Value = serde_json::from_str(&body_text(response).await?)?;
+        let url = video["media"][0]["sources"][0]["url"].as_str().expect("No media url");
+        let sid = state.id.as_ref().expect("Session without an id").id.to_raw();
+        assert!(url.starts_with(&format!("https://jellyvr.example/heresphere/hls/{}/{}/master.m3u8?", sid, VIDEO_ID)));
+        assert!(url.contains("PlaySessionId=prefetched"));
+        // Played from the prefetched info, Jellyfin isn't asked again
+        assert_eq!(calls_to(&calls, &playback_info), 1);
+        drop(app);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn playback_starts_with_the_first_open_or_play_event() -> eyre::Result<()> {
+        let (jellyfin_url, calls) = mock_jellyfin(playback_routes()).await?;
+        let (db, path) = test_db().await?;
+        let app = test_app(db.clone(), test_config(&jellyfin_url));
+        let state = create_user_session(&db, "alice").await?;
+        let Session::User(user) = &state.session else {
+            panic!("Not a user session");
+        };
+        seed_video(&db, &user.cache_key(), VIDEO_ID, heresphere::VideoData::default()).await?;
+        let sid = state.id.as_ref().expect("Session without an id").id.to_raw();
+
+        // Only looking at the video
+        request_media_source(&app, &state).await?;
+        assert_eq!(calls_to(&calls, "POST /Sessions/Playing"), 0);
+        let stored = app.get_session_from_heresphere_event(&sid).await?;
+        let Session::User(stored) = stored.session else {
+            panic!("Not a user session");
+        };
+        assert!(stored.last_known_playback.is_some_and(|playback| !playback.is_started));
+
+        send_event(&app, &sid, 0, 0.0).await?;
+        assert_eq!(calls_to(&calls, "POST /Sessions/Playing"), 1);
+        send_event(&app, &sid, 1, 1000.0).await?;
+        assert_eq!(calls_to(&calls, "POST /Sessions/Playing"), 1);
+
+        // Opened paused, without an Open event going through first
+        let stored = app.get_session_from_heresphere_event(&sid).await?;
+        let Session::User(stored_user) = stored.session.clone() else {
+            panic!("Not a user session");
+        };
+        app.update_session(SessionState {
+            session: Session::User(User {
+                last_known_playback: None,
+                ..stored_user
+            }),
+            ..stored
+        })
+        .await?;
+        request_media_source(&app, &state).await?;
+        send_event(&app, &sid, 2, 5000.0).await?;
+        assert_eq!(calls_to(&calls, "POST /Sessions/Playing"), 2);
+        let stored = app.get_session_from_heresphere_event(&sid).await?;
+        let Session::User(stored) = stored.session else {
+            panic!("Not a user session");
+        };
+        let playback = stored.last_known_playback.expect("No playback");
+        assert!(playback.is_started && playback.is_paused);
+        drop(app);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn read_only_mode_never_writes_to_jellyfin() -> eyre::Result<()> {
+        let (jellyfin_url, calls) = mock_jellyfin(playback_routes()).await?;
+        let (db, path) = test_db().await?;
+        let mut config = test_config(&jellyfin_url);
+        config.read_only = true;
+        // Everything that would write, were it not read-only
+        config.write_played = true;
+        config.write_tags = true;
+        let app = test_app(db.clone(), config);
+        let state = create_user_session(&db, "alice").await?;
+        let Session::User(user) = &state.session else {
+            panic!("Not a user session");
+        };
+        seed_video(&db, &user.cache_key(), VIDEO_ID, heresphere::VideoData::default()).await?;
+        let sid = state.id.as_ref().expect("Session without an id").id.to_raw();
+
+        let video = request_media_source(&app, &state).await?;
+        assert!(video["media"][0]["sources"][0]["url"].is_string());
+        for (event, time) in [(0, 0.0), (1, 1000.0), (2, 5000.0), (1, 6000.0), (3, 7000.0)] {
+            send_event(&app, &sid, event, time).await?;
+        }
+        heresphere_video(
+            State(app.clone()),
+            ProtoHost("https://jellyvr.example".to_string()),
+            Path(VIDEO_ID.to_string()),
+            heresphere_session(
+                state.clone(),
+                serde_json::json!({
+                    "username": "alice",
+                    "password": "password",
+                    "isFavorite": true,
+                    "tags": [{ "name": "Tag:New" }],
+                }),
+            ),
+        )
+        .await
+        .map_err(|e| e.0)?;
+        let writes: Vec<String> = calls
+            .lock()
+            .expect("Calls poisoned")
+            .iter()
+            .filter(|call| !call.starts_with("GET "))
+            .cloned()
+            .collect();
+        assert_eq!(writes, Vec::<String>::new());
+        drop(app);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn favorite_toggle_marks_the_video_unplayed() -> eyre::Result<()> {
+        let routes = Router::new().route(
+            "/Users/:user/PlayedItems/:id",
+            axum::routing::any(|| async { StatusCode::OK }),
+        );
+        let (jellyfin_url, calls) = mock_jellyfin(routes).await?;
+        let (db, path) = test_db().await?;
+        let mut config = test_config(&jellyfin_url);
+        config.write_played = true;
+        let app = test_app(db.clone(), config);
+        let state = create_user_session(&db, "alice").await?;
+        let Session::User(user) = &state.session else {
+            panic!("Not a user session");
+        };
+        // Played already, so not a favorite
+        let played = heresphere::VideoData {
+            is_favorite: Some(false),
+            ..Default::default()
+        };
+        seed_video(&db, &user.cache_key(), VIDEO_ID, played).await?;
+
+        let request = serde_json::json!({ "username": "alice", "password": "password", "isFavorite": true });
+        let response = heresphere_video(
+            State(app.clone()),
+            ProtoHost("https://jellyvr.example".to_string()),
+            Path(VIDEO_ID.to_string()),
+            heresphere_session(state.clone(), request.clone()),
+        )
+        .await
+        .map_err(|e| e.0)?
+        .into_response();
+        let video: serde_json::Value = serde_json::from_str(&body_text(response).await?)?;
+        assert_eq!(video["isFavorite"], true);
+        let unplayed = format!("DELETE /Users/{}/PlayedItems/{}", USER_ID, VIDEO_ID);
+        assert_eq!(calls_to(&calls, &unplayed), 1);
+        let cached: Option<bool> = db
+            .query("SELECT VALUE data.isFavorite FROM type::thing('videos', [$user, $id])")
+            .bind(("user", user.cache_key()))
+            .bind(("id", VIDEO_ID))
+            .await?
+            .take(0)?;
+        assert_eq!(cached, Some(true));
+
+        // Already unplayed, nothing to write
+        heresphere_video(
+            State(app.clone()),
+            ProtoHost("https://jellyvr.example".to_string()),
+            Path(VIDEO_ID.to_string()),
+            heresphere_session(state.clone(), request),
+        )
+        .await
+        .map_err(|e| e.0)?;
+        assert_eq!(calls_to(&calls, &unplayed), 1);
+        drop(app);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unknown_videos_are_not_found() -> eyre::Result<()> {
+        let (db, path) = test_db().await?;
+        let app = test_app(db.clone(), test_config("http://127.0.0.1:9"));
+        let state = create_user_session(&db, "alice").await?;
+        let result = heresphere_video(
+            State(app.clone()),
+            ProtoHost("https://jellyvr.example".to_string()),
+            Path(VIDEO_ID.to_string()),
+            heresphere_session(state, serde_json::json!({ "username": "alice", "password": "password" })),
+        )
+        .await;
+        let Err(err) = result else {
+            panic!("Found a video that was never indexed");
+        };
+        assert_eq!(err.into_response().status(), StatusCode::NOT_FOUND);
+        drop(app);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    /// A running playback of `VIDEO_ID` at `position_ms`, reported to Jellyfin just now.
+    fn running_playback(position_ms: f64, duration_ms: f64) -> Playback {
+        Playback {
+            play_session_id: "play".to_string(),
+            video_id: VIDEO_ID.to_string(),
+            duration: jellyfin::ms_to_ticks(duration_ms),
+            position_estimate: jellyfin::ms_to_ticks(position_ms),
+            speed: 1.0,
+            started_at: chrono::Utc::now(),
+            last_update: chrono::Utc::now(),
+            is_paused: false,
+            is_started: true,
+            is_marked_played: false,
+            media_url: None,
+            is_transcoded: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn videos_are_marked_played_once_past_the_threshold() -> eyre::Result<()> {
+        let (jellyfin_url, calls) = mock_jellyfin(playback_routes()).await?;
+        let (db, path) = test_db().await?;
+        let app = test_app(db.clone(), test_config(&jellyfin_url));
+        let state = create_user_session(&db, "alice").await?;
+        let Session::User(user) = &state.session else {
+            panic!("Not a user session");
+        };
+        let played = format!("POST /Users/{}/PlayedItems/{}", USER_ID, VIDEO_ID);
+
+        let playback = next_playback(&app, user, &running_playback(60_000.0, 100_000.0)).await?;
+        assert!(!playback.is_marked_played);
+        assert_eq!(calls_to(&calls, &played), 0);
+
+        // Past 90%, then still past it on the following polls
+        let mut playback = next_playback(&app, user, &running_playback(95_000.0, 100_000.0)).await?;
+        assert!(playback.is_marked_played);
+        for _ in 0..3 {
+            playback = next_playback(&app, user, &playback).await?;
+        }
+        assert!(playback.is_marked_played);
+        assert_eq!(calls_to(&calls, &played), 1);
+        drop(app);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn only_the_newest_playbacks_stay_over_the_limit() -> eyre::Result<()> {
+        let (jellyfin_url, calls) = mock_jellyfin(playback_routes()).await?;
+        let (db, path) = test_db().await?;
+        let mut config = test_config(&jellyfin_url);
+        config.max_playbacks_per_user = 2;
+        let app = test_app(db.clone(), config);
+        let mut sids = vec![];
+        for minutes_ago in [5, 20, 1] {
+            let state = create_user_session(&db, "alice").await?;
+            let Session::User(user) = state.session.clone() else {
+                panic!("Not a user session");
+            };
+            sids.push(state.id.as_ref().expect("Session without an id").id.to_raw());
+            app.update_session(SessionState {
+                session: Session::User(User {
+                    last_known_playback: Some(Playback {
+                        last_update: chrono::Utc::now() - chrono::Duration::minutes(minutes_ago),
+                        ..running_playback(1000.0, 100_000.0)
+                    }),
+                    ..user
+                }),
+                ..state
+            })
+            .await?;
+        }
+        let state = app.get_session_from_heresphere_event(&sids[2]).await?;
+        let Session::User(user) = &state.session else {
+            panic!("Not a user session");
+        };
+
+        enforce_playback_limit(&app, user).await?;
+        let mut has_playback = vec![];
+        for sid in &sids {
+            let Session::User(user) = app.get_session_from_heresphere_event(sid).await?.session else {
+                panic!("Not a user session");
+            };
+            has_playback.push(user.last_known_playback.is_some());
+        }
+        // Updated 20 minutes ago, the oldest
+        assert_eq!(has_playback, vec![true, false, true]);
+        assert_eq!(calls_to(&calls, "POST /Sessions/Playing/Stopped"), 1);
+        drop(app);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn progress_updates_skip_sessions_changed_meanwhile() -> eyre::Result<()> {
+        let (db, path) = test_db().await?;
+        let read_at = chrono::Utc::now() - chrono::Duration::seconds(10);
+        for id in ["idle", "busy"] {
+            let _: Option<SessionState> = db.create(("session", id)).content(playing_session(id, 0, read_at)).await?;
+        }
+        // HereSphere reports a position for one of them while Jellyfin is being talked to
+        let _: Option<SessionState> = db
+            .update(("session", "busy"))
+            .content(playing_session("busy", 5, chrono::Utc::now()))
+            .await?;
+
+        let results = update_sessions_if_unchanged(
+            &db,
+            vec![
+                (playing_session("idle", 100, chrono::Utc::now()), read_at),
+                (playing_session("busy", 100, chrono::Utc::now()), read_at),
+            ],
+        )
+        .await?;
+        let results: HashMap<_, _> = results.into_iter().map(|(id, result)| (id, result.ok())).collect();
+        assert!(matches!(&results["idle"], Some(Some(state)) if position(state) == Some(100)));
+        assert!(matches!(results["busy"], Some(None)));
+
+        let busy: Option<SessionState> = db.select(("session", "busy")).await?;
+        assert_eq!(busy.as_ref().and_then(position), Some(5));
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    /// Stores `playback` as the playback of a new session of alice, returning the session id.
+    async fn session_with_playback(app: &AppState, playback: Playback) -> eyre::Result<String> {
+        let state = create_user_session(&app.db.get(), "alice").await?;
+        let Session::User(user) = state.session.clone() else {
+            panic!("Not a user session");
+        };
+        let sid = state.id.as_ref().expect("Session without an id").id.to_raw();
+        app.update_session(SessionState {
+            session: Session::User(User {
+                last_known_playback: Some(playback),
+                ..user
+            }),
+            ..state
+        })
+        .await?;
+        Ok(sid)
+    }
+
+    /// Playback tracked for the session `sid` right now.
+    async fn stored_playback(app: &AppState, sid: &str) -> eyre::Result<Option<Playback>> {
+        let Session::User(user) = app.get_session_from_heresphere_event(sid).await?.session else {
+            panic!("Not a user session");
+        };
+        Ok(user.last_known_playback)
+    }
+
+    #[tokio::test]
+    async fn playback_without_a_play_session_id_still_starts() -> eyre::Result<()> {
+        let routes = Router::new()
+            .route(
+                "/Items/:id/PlaybackInfo",
+                axum::routing::any(|| async {
+                    Json(serde_json::json!({
+                        "MediaSources": [{ "Id": "source", "SupportsDirectPlay": true, "Container": "mp4" }],
+                    }))
+                }),
+            )
+            .route("/Sessions/Playing", post(|| async { StatusCode::NO_CONTENT }));
+        let (jellyfin_url, calls) = mock_jellyfin(routes).await?;
+        let (db, path) = test_db().await?;
+        let app = test_app(db.clone(), test_config(&jellyfin_url));
+        let state = create_user_session(&db, "alice").await?;
+        let Session::User(user) = &state.session else {
+            panic!("Not a user session");
+        };
+        seed_video(&db, &user.cache_key(), VIDEO_ID, heresphere::VideoData::default()).await?;
+        let sid = state.id.as_ref().expect("Session without an id").id.to_raw();
+
+        let video = request_media_source(&app, &state).await?;
+        assert!(video["media"][0]["sources"][0]["url"].is_string());
+        let playback = stored_playback(&app, &sid).await?.expect("No playback");
+        // A generated one stands in, so the reports of this playback still belong together
+        assert_eq!(playback.play_session_id.len(), 32);
+        send_event(&app, &sid, 1, 0.0).await?;
+        assert_eq!(calls_to(&calls, "POST /Sessions/Playing"), 1);
+        let started = stored_playback(&app, &sid).await?.expect("No playback");
+        assert!(started.is_started);
+        assert_eq!(started.play_session_id, playback.play_session_id);
+        drop(app);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn transcodes_with_subtitles_in_the_manifest_list_none() -> eyre::Result<()> {
+        let routes = Router::new()
+            .route(
+                "/Items/:id/PlaybackInfo",
+                axum::routing::any(|| async {
+                    Json(serde_json::json!({
+                        "PlaySessionId": "play",
+                        "MediaSources": [{ "Id": "source", "TranscodingUrl": "/videos/x/master.m3u8?MediaSourceId=source" }],
+                    }))
+                }),
+            );
+        let (jellyfin_url, _) = mock_jellyfin(routes).await?;
+        let (db, path) = test_db().await?;
+        let mut config = test_config(&jellyfin_url);
+        config.codec_profile.subtitles_in_manifest = true;
+        let app = test_app(db.clone(), config);
+        let state = create_user_session(&db, "alice").await?;
+        let Session::User(user) = &state.session else {
+            panic!("Not a user session");
+        };
+        let subtitles = vec![heresphere::Subtitle {
+            name: "English".to_string(),
+            language: "eng".to_string(),
+            url: "https://jf.example/Videos/x/source/Subtitles/2/Stream.srt".to_string(),
+        }];
+        let data = heresphere::VideoData { subtitles: Some(subtitles), ..Default::default() };
+        seed_video(&db, &user.cache_key(), VIDEO_ID, data).await?;
+
+        let video = request_media_source(&app, &state).await?;
+        assert!(video["subtitles"].as_array().map_or(true, Vec::is_empty));
+
+        // Direct play has no manifest to carry them
+        let (jellyfin_url, _) = mock_jellyfin(playback_routes()).await?;
+        let mut config = test_config(&jellyfin_url);
+        config.codec_profile.subtitles_in_manifest = true;
+        let app = test_app(db.clone(), config);
+        // A session of its own, the first one would reuse its transcode
+        let state = create_user_session(&db, "alice").await?;
+        let video = request_media_source(&app, &state).await?;
+        assert_eq!(video["subtitles"][0]["name"], "English");
+        drop(app);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn play_events_push_progress_right_away() -> eyre::Result<()> {
+        let reported: std::sync::Arc<std::sync::Mutex<Vec<serde_json::Value>>> = Default::default();
+        let captured = reported.clone();
+        let routes = Router::new().route(
+            "/Sessions/Playing/Progress",
+            post(move |Json(body): Json<serde_json::Value>| {
+                let captured = captured.clone();
+                async move {
+                    captured.lock().expect("Reports poisoned").push(body);
+                    StatusCode::NO_CONTENT
+                }
+            }),
+        );
+        let (jellyfin_url, calls) = mock_jellyfin(routes).await?;
+        let (db, path) = test_db().await?;
+        let app = test_app(db.clone(), test_config(&jellyfin_url));
+        let paused = Playback { is_paused: true, ..running_playback(10_000.0, 600_000.0) };
+        let sid = session_with_playback(&app, paused).await?;
+
+        // Resuming after a seek, the progress poll is up to `progress_interval` away
+        send_event(&app, &sid, 1, 42_000.0).await?;
+        assert_eq!(calls_to(&calls, "POST /Sessions/Playing/Progress"), 1);
+        let report = reported.lock().expect("Reports poisoned").pop().expect("No progress report");
+        assert_eq!(report["PositionTicks"], jellyfin::ms_to_ticks(42_000.0));
+        assert_eq!(report["IsPaused"], false);
+        drop(app);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn playbacks_resume_a_bit_before_the_stored_position() -> eyre::Result<()> {
+        let resume_at = |position_ms: f64| async move {
+            let routes = playback_routes().route(
+                "/Users/:user/Items/:id",
+                get(move || async move {
+                    Json(serde_json::json!({ "Id": VIDEO_ID, "UserData": { "PlaybackPositionTicks": jellyfin::ms_to_ticks(position_ms) } }))
+                }),
+            );
+            let (jellyfin_url, _) = mock_jellyfin(routes).await?;
+            let (db, path) = test_db().await?;
+            let app = test_app(db.clone(), test_config(&jellyfin_url));
+            let state = create_user_session(&db, "alice").await?;
+            let Session::User(user) = &state.session else {
+                panic!("Not a user session");
+            };
+            seed_video(&db, &user.cache_key(), VIDEO_ID, heresphere::VideoData::default()).await?;
+            request_media_source(&app, &state).await?;
+            let sid = state.id.as_ref().expect("Session without an id").id.to_raw();
+            let playback = stored_playback(&app, &sid).await?.expect("No playback");
+            drop(app);
+            drop(db);
+            let _ = std::fs::remove_dir_all(path);
+            eyre::Ok(jellyfin::ticks_to_ms(playback.position_estimate))
+        };
+
+        // `resume_rewind` is 5 seconds
+        assert_eq!(resume_at(60_000.0).await?, 55_000.0);
+        assert_eq!(resume_at(2_000.0).await?, 0.0);
+        assert_eq!(resume_at(0.0).await?, 0.0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn playbacks_last_as_long_as_the_chosen_source() -> eyre::Result<()> {
+        let routes = Router::new()
+            .route(
+                "/Items/:id/PlaybackInfo",
+                axum::routing::any(|| async {
+                    // Jellyfin picked the extended cut
+                    Json(serde_json::json!({
+                        "PlaySessionId": "play",
+                        "MediaSources": [{ "Id": "extended", "SupportsDirectPlay": true, "Container": "mp4", "RunTimeTicks": 7_200_000_000i64 }],
+                    }))
+                }),
+            );
+        let (jellyfin_url, _) = mock_jellyfin(routes).await?;
+        let (db, path) = test_db().await?;
+        let app = test_app(db.clone(), test_config(&jellyfin_url));
+        let state = create_user_session(&db, "alice").await?;
+        let Session::User(user) = &state.session else {
+            panic!("Not a user session");
+        };
+        // The index went by the theatrical cut
+        let data = heresphere::VideoData { duration: 600_000.0, ..Default::default() };
+        seed_video(&db, &user.cache_key(), VIDEO_ID, data).await?;
+        let sid = state.id.as_ref().expect("Session without an id").id.to_raw();
+
+        let video = request_media_source(&app, &state).await?;
+        assert_eq!(video["duration"], 720_000.0);
+        let playback = stored_playback(&app, &sid).await?.expect("No playback");
+        assert_eq!(playback.duration, 7_200_000_000);
+        drop(app);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn seeks_win_over_a_progress_tick_in_flight() -> eyre::Result<()> {
+        let (jellyfin_url, _) = mock_jellyfin(playback_routes()).await?;
+        let (db, path) = test_db().await?;
+        let app = test_app(db.clone(), test_config(&jellyfin_url));
+        let sid = session_with_playback(&app, running_playback(10_000.0, 600_000.0)).await?;
+
+        // The tick reads the session and reports to Jellyfin...
+        let read = app.get_session_from_heresphere_event(&sid).await?;
+        let Session::User(user) = &read.session else {
+            panic!("Not a user session");
+        };
+        let playback = user.last_known_playback.clone().expect("No playback");
+        let ticked = next_playback(&app, user, &playback).await?;
+        // ...while the headset seeks
+        send_event(&app, &sid, 1, 300_000.0).await?;
+        let update = SessionState {
+            session: Session::User(User {
+                last_known_playback: Some(ticked),
+                ..user.clone()
+            }),
+            ..read.clone()
+        };
+        let results = update_sessions_if_unchanged(&db, vec![(update, playback.last_update)]).await?;
+        assert!(matches!(results[..], [(_, Ok(None))]));
+
+        let stored = stored_playback(&app, &sid).await?.expect("No playback");
+        assert_eq!(stored.position_estimate, jellyfin::ms_to_ticks(300_000.0));
+        // The next tick goes on from the seek
+        progress_update_routine(&app).await?;
+        let stored = stored_playback(&app, &sid).await?.expect("No playback");
+        assert!(stored.position_estimate >= jellyfin::ms_to_ticks(300_000.0));
+        drop(app);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn only_the_secret_resolves_a_session() -> eyre::Result<()> {
+        let (db, path) = test_db().await?;
+        let secret = gen_session_secret();
+        let _: Option<SessionState> = db.create(("session", "current")).content(quick_connect_session(Some(&secret))).await?;
+
+        assert!(find_session_by_cookie(&db, &secret).await?.is_some());
+        assert!(find_session_by_cookie(&db, &gen_session_secret()).await?.is_none());
+        assert!(find_session_by_cookie(&db, "").await?.is_none());
+        // Record ids are easy to guess, they only work for sessions from before secrets existed
+        assert!(find_session_by_cookie(&db, "current").await?.is_none());
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rapid_quick_connect_polls_are_coalesced() -> eyre::Result<()> {
+        let (db, path) = test_db().await?;
+        let routes = Router::new().route(
+            "/QuickConnect/Connect",
+            get(|| async { Json(serde_json::json!({ "Authenticated": false })) }),
+        );
+        let (url, calls) = mock_jellyfin(routes).await?;
+        let app = test_app(db.clone(), test_config(&url));
+        let _: Option<SessionState> = db.create(("session", "waiting")).content(quick_connect_session(Some("cookie-secret"))).await?;
+
+        for _ in 0..3 {
+            app.handle_session(Some("cookie-secret".to_string()), None).await?;
+        }
+        assert_eq!(calls_to(&calls, "GET /QuickConnect/Connect"), 1);
+        let state = find_session_by_cookie(&db, "cookie-secret").await?.expect("No session");
+        let Session::QuickConnect(quick_connect) = state.session else {
+            panic!("Not a Quick Connect session");
+        };
+        assert_eq!(quick_connect.poll_attempts, 1);
+        drop(app);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn legacy_record_id_cookies_still_resolve() -> eyre::Result<()> {
+        let (db, path) = test_db().await?;
+        let _: Option<SessionState> = db.create(("session", "legacy")).content(quick_connect_session(None)).await?;
+
+        let state = find_session_by_cookie(&db, "legacy").await?;
+        assert!(state.is_some_and(|state| state.secret.is_none()));
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[test]
+    fn secrets_match_only_themselves() {
+        let secret = Secret("admin-token".to_string());
+        assert!(secret.matches("admin-token"));
+        assert!(!secret.matches("admin-toke"));
+        assert!(!secret.matches("admin-tokens"));
+        assert!(!secret.matches(""));
+    }
+
+    #[test]
+    fn repeated_failures_get_throttled() {
+        let throttle = LoginThrottle::default();
+        let lockout = Duration::from_secs(300);
+        for _ in 0..2 {
+            throttle.record_failure("alice", lockout);
+        }
+        assert!(!throttle.is_locked("alice", 3, lockout));
+        throttle.record_failure("alice", lockout);
+        assert!(throttle.is_locked("alice", 3, lockout));
+        assert!(!throttle.is_locked("bob", 3, lockout));
+
+        // Once the window passes the failures no longer count
+        assert!(!throttle.is_locked("alice", 3, Duration::ZERO));
+        throttle.clear("alice");
+        assert!(!throttle.is_locked("alice", 3, lockout));
+    }
+
+    #[test]
+    fn device_names_keep_header_syntax_out() {
+        assert_eq!(device_name("HereSphere/1.0 (Quest 3)").as_deref(), Some("HereSphere/1.0 (Quest 3)"));
+        assert_eq!(device_name(r#"Quest", Token="x"#).as_deref(), Some("Quest Tokenx"));
+        assert_eq!(device_name(" \",= "), None);
+    }
+
+    #[test]
+    fn session_debug_output_has_no_secrets() {
+        let state = SessionState {
+            session: Session::User(User {
+                server: DEFAULT_SERVER.to_string(),
+                user_id: "user".to_string(),
+                token: "jellyfin-token".to_string(),
+                username: "alice".to_string(),
+                jellyvr_password: "heresphere-password".to_string(),
+                last_known_playback: None,
+                watchtime_tracking: None,
+                device: None,
+                device_id: None,
+            }),
+            ..quick_connect_session(Some("cookie-secret"))
+        };
+        let debug = format!("{:?}", state);
+        assert!(debug.contains("alice"));
+        for secret in ["cookie-secret", "jellyfin-token", "heresphere-password"] {
+            assert!(!debug.contains(secret), "{} leaked into {}", secret, debug);
+        }
+    }
+
+    #[tokio::test]
+    async fn transient_db_errors_are_retried() -> eyre::Result<()> {
+        let attempts = std::sync::atomic::AtomicU64::new(0);
+        let attempts = &attempts;
+        let result = retry_db(|| async move {
+            match attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                0 => Err(surrealdb::Error::Db(surrealdb::error::Db::TxRetryable)),
+                _ => Ok("stored"),
+            }
+        })
+        .await?;
+        assert_eq!(result, "stored");
+        assert_eq!(attempts.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        // A broken query stays broken, it's returned right away
+        attempts.store(0, std::sync::atomic::Ordering::SeqCst);
+        let result: Result<(), _> = retry_db(|| async move {
+            attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Err(surrealdb::Error::Db(surrealdb::error::Db::Thrown("broken".to_string())))
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert!(!is_lost_db_connection(&surrealdb::Error::Db(surrealdb::error::Db::TxRetryable)));
+        assert!(is_lost_db_connection(&surrealdb::Error::Api(surrealdb::error::Api::InternalError(
+            "receiving on a closed channel".to_string()
+        ))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn healthy_databases_are_not_reopened() -> eyre::Result<()> {
+        let (db, path) = test_db().await?;
+        let database = Database::from(db.clone());
+        assert!(!database.reopen_if_lost().await?);
+        drop(database);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[test]
+    fn compact_json_is_smaller_than_pretty() -> eyre::Result<()> {
+        let video = heresphere::VideoData {
+            title: "Video".to_string(),
+            tags: vec![heresphere::Tag { name: "Genre:Drama".to_string(), ..Default::default() }],
+            ..Default::default()
+        };
+        let compact = test_config("https://jf.example").to_json(&video)?;
+        let pretty = AppConfig { pretty_json: true, ..test_config("https://jf.example") }.to_json(&video)?;
+        assert!(!compact.contains('\n'));
+        assert!(pretty.contains('\n'));
+        assert!(compact.len() < pretty.len());
+        // Both are the same document
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&compact)?, serde_json::from_str::<serde_json::Value>(&pretty)?);
+        Ok(())
+    }
+
+    #[test]
+    fn users_can_override_watchtime_tracking() {
+        let Session::User(user) = playing_session("alice", 0, chrono::Utc::now()).session else {
+            panic!("Not a user session");
+        };
+        let tracking = test_config("https://jf.example");
+        let not_tracking = AppConfig { watchtime_tracking: false, ..test_config("https://jf.example") };
+        let with = |watchtime_tracking: Option<bool>| User { watchtime_tracking, ..user.clone() };
+
+        assert!(with(None).tracks_watchtime(&tracking));
+        assert!(!with(None).tracks_watchtime(&not_tracking));
+        assert!(!with(Some(false)).tracks_watchtime(&tracking));
+        assert!(with(Some(true)).tracks_watchtime(&not_tracking));
+        // Read-only mode never reports anything
+        let read_only = AppConfig { read_only: true, ..test_config("https://jf.example") };
+        assert!(!with(Some(true)).tracks_watchtime(&read_only));
+    }
+
+    #[tokio::test]
+    async fn version_reports_the_crate_version() -> eyre::Result<()> {
+        let text = body_text(version().await.into_response()).await?;
+        let reported: serde_json::Value = serde_json::from_str(&text)?;
+        assert_eq!(reported["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(reported["jellyfin_spec_version"], jellyfin::SPEC_VERSION);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn progress_updates_can_be_disabled() -> eyre::Result<()> {
+        let (db, path) = test_db().await?;
+        let disabled = AppConfig { progress_interval: Duration::ZERO, ..test_config("http://127.0.0.1:9") };
+        assert!(spawn_progress_updates(test_app(db.clone(), disabled)).is_none());
+        let task = spawn_progress_updates(test_app(db.clone(), test_config("http://127.0.0.1:9"))).expect("No progress task");
+        assert!(!task.is_finished());
+        task.abort();
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[test]
+    fn structured_errors_map_to_their_status() {
+        let status = |err: eyre::Error| AppError(err).into_response().status();
+        assert_eq!(status(JellyVrError::SessionNotFound.into()), StatusCode::UNAUTHORIZED);
+        assert_eq!(status(JellyVrError::NotFound("Video".to_string()).into()), StatusCode::NOT_FOUND);
+        // Still recognized with context added on the way up
+        let wrapped = eyre::Error::from(JellyVrError::SessionNotFound).wrap_err("Resolving the session");
+        assert_eq!(status(wrapped), StatusCode::UNAUTHORIZED);
+        assert_eq!(status(eyre::eyre!("Something else")), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn large_libraries_are_gzip_compressed() -> eyre::Result<()> {
+        let (db, path) = test_db().await?;
+        let items: Vec<serde_json::Value> = (0..500)
+            .map(|n| serde_json::json!({ "Id": uuid::Uuid::from_u128(n + 1), "Name": format!("Video {}", n), "MediaSources": [{}] }))
+            .collect();
+        let (url, _) = mock_jellyfin(library_routes(serde_json::Value::Array(items))).await?;
+        create_user_session(&db, "alice").await?;
+        let app = app_router().with_state(test_app(db.clone(), test_config(&url)));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}/heresphere", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let library = |encoding: Option<&'static str>| {
+            let mut request = reqwest::Client::new()
+                .post(&url)
+                .json(&serde_json::json!({ "username": "alice", "password": "password" }));
+            if let Some(encoding) = encoding {
+                request = request.header("Accept-Encoding", encoding);
+            }
+            request.send()
+        };
+        let plain = library(None).await?.error_for_status()?;
+        assert!(plain.headers().get("Content-Encoding").is_none());
+        let plain = plain.bytes().await?;
+        let compressed = library(Some("gzip")).await?.error_for_status()?;
+        assert_eq!(compressed.headers().get("Content-Encoding").map(|value| value.as_bytes()), Some(&b"gzip"[..]));
+        let compressed = compressed.bytes().await?;
+        assert!(compressed.len() * 4 < plain.len(), "{} bytes compressed to {}", plain.len(), compressed.len());
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+}
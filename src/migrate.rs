@@ -0,0 +1,118 @@
+use serde::{Deserialize, Serialize};
+use surrealdb::{engine::local::Db, Surreal};
+
+/// Upgrades of the stored records, one per schema version and applied in order.
+/// Only ever append here, a database remembers how many of these it has been through.
+const MIGRATIONS: &[(&str, &str)] = &[
+    (
+        "Sessions name their Jellyfin server",
+        "UPDATE session SET session.User.server = 'default' WHERE session.User != NONE AND session.User.server = NONE",
+    ),
+    (
+        // Cheaper than upgrading them, users get fresh ones the next time HereSphere asks
+        "Caches built by older versions are dropped",
+        "DELETE index; DELETE videos",
+    ),
+    (
+        "Image proxy urls are signed, caches with unsigned ones are dropped",
+        "DELETE index; DELETE videos",
+    ),
+];
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SchemaVersion {
+    version: usize,
+}
+
+/// Brings records written by older JellyVR versions up to the current shape, so they keep deserializing.
+/// Runs at startup before anything else touches the database.
+pub(crate) async fn run(db: &Surreal<Db>) -> eyre::Result<()> {
+    let stored: Option<SchemaVersion> = db.select(("meta", "schema")).await?;
+    let current = stored.map_or(0, |stored| stored.version);
+    if current > MIGRATIONS.len() {
+        tracing::warn!(
+            version = current,
+            supported = MIGRATIONS.len(),
+            "Database was written by a newer JellyVR, some records may not load"
+        );
+    }
+    for (version, (description, query)) in MIGRATIONS.iter().enumerate().skip(current) {
+        let version = version + 1;
+        tracing::info!(version, description = %description, "Migrating database");
+        apply(db, version, query).await?;
+    }
+    Ok(())
+}
+
+/// Runs one migration and records its version in the same transaction, so a migration that fails
+/// halfway leaves nothing behind and is tried again on the next start.
+async fn apply(db: &Surreal<Db>, version: usize, query: &str) -> eyre::Result<()> {
+    db.query(format!(
+        "BEGIN TRANSACTION; {}; UPDATE meta:schema CONTENT $schema; COMMIT TRANSACTION;",
+        query
+    ))
+    .bind(("schema", SchemaVersion { version }))
+    .await?
+    .check()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Session, SessionState, DEFAULT_SERVER};
+    use surrealdb::engine::local::RocksDb;
+
+    #[tokio::test]
+    async fn failed_migrations_leave_nothing_behind() -> eyre::Result<()> {
+        let path = std::env::temp_dir().join(format!("jellyvr-migrate-{}", uuid::Uuid::new_v4().simple()));
+        let db = Surreal::new::<RocksDb>(path.to_str().expect("Temp dir isn't UTF-8")).await?;
+        db.use_ns("jellyvr").use_db("jellyvr").await?;
+        db.query("CREATE session:kept CONTENT { marker: 'before' }").await?.check()?;
+
+        assert!(apply(&db, 1, "UPDATE session SET marker = 'after'; THROW 'broken'").await.is_err());
+        let marker: Option<String> = db.query("SELECT VALUE marker FROM session:kept").await?.take(0)?;
+        assert_eq!(marker.as_deref(), Some("before"));
+        let version: Option<SchemaVersion> = db.select(("meta", "schema")).await?;
+        assert!(version.is_none());
+
+        apply(&db, 1, "UPDATE session SET marker = 'after'").await?;
+        let version: Option<SchemaVersion> = db.select(("meta", "schema")).await?;
+        assert_eq!(version.map(|version| version.version), Some(1));
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn legacy_sessions_still_load_after_migrating() -> eyre::Result<()> {
+        let path = std::env::temp_dir().join(format!("jellyvr-migrate-{}", uuid::Uuid::new_v4().simple()));
+        let db = Surreal::new::<RocksDb>(path.to_str().expect("Temp dir isn't UTF-8")).await?;
+        db.use_ns("jellyvr").use_db("jellyvr").await?;
+        // As written before servers and cookie secrets existed
+        db.query(
+            "CREATE session:legacy CONTENT { session: { User: {
+                user_id: 'user', token: 'token', username: 'alice', jellyvr_password: 'password', last_known_playback: NONE
+            } } }",
+        )
+        .await?
+        .check()?;
+
+        run(&db).await?;
+
+        let state: Option<SessionState> = db.select(("session", "legacy")).await?;
+        let state = state.expect("Legacy session is gone");
+        // The cookie still holds the record id, the secret is handed out on the next visit
+        assert!(state.secret.is_none());
+        let Session::User(user) = state.session else {
+            panic!("Legacy session is no longer a user session");
+        };
+        assert_eq!(user.server, DEFAULT_SERVER);
+        assert_eq!(user.username, "alice");
+        let version: Option<SchemaVersion> = db.select(("meta", "schema")).await?;
+        assert_eq!(version.map(|version| version.version), Some(MIGRATIONS.len()));
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+}
@@ -0,0 +1,348 @@
+use axum::Json;
+use serde_json::{json, Value};
+
+/// OpenAPI description of JellyVR's own routes, the Jellyfin API isn't part of it.
+pub(crate) async fn openapi_json() -> Json<Value> {
+    Json(document())
+}
+
+fn path_param(name: &str, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "path",
+        "required": true,
+        "description": description,
+        "schema": { "type": "string" },
+    })
+}
+
+/// Every HereSphere request is a POST carrying the credentials from the JellyVR page.
+fn heresphere_operation(summary: &str, parameters: Vec<Value>) -> Value {
+    json!({
+        "post": {
+            "tags": ["heresphere"],
+            "summary": summary,
+            "parameters": parameters,
+            "requestBody": {
+                "required": true,
+                "content": { "application/json": { "schema": { "$ref": "#/components/schemas/HereSphereRequest" } } },
+            },
+            "responses": {
+                "200": { "description": "HereSphere JSON, `access: -1` when the credentials are wrong" },
+            },
+        }
+    })
+}
+
+fn admin_operation(method: &str, summary: &str, parameters: Vec<Value>) -> Value {
+    json!({
+        method: {
+            "tags": ["admin"],
+            "summary": summary,
+            "parameters": parameters,
+            "security": [{ "adminToken": [] }],
+            "responses": {
+                "200": { "description": "Done" },
+                "204": { "description": "Done, nothing to return" },
+                "401": { "description": "Wrong admin token" },
+                "404": { "description": "Admin API disabled or nothing found" },
+                "409": { "description": "Not possible in read-only mode" },
+            },
+        }
+    })
+}
+
+fn document() -> Value {
+    let sid = || path_param("sid", "Session id, as handed out in `eventServer` urls");
+    let vid = || path_param("vid", "Jellyfin item id");
+    // One `json!` per path, a single huge one runs into the macro recursion limit
+    let mut paths = serde_json::Map::new();
+    paths.insert(
+        "/".to_string(),
+        json!({
+            "get": {
+                "summary": "Login page, shows the HereSphere credentials once logged in",
+                "parameters": [
+                    { "name": "server", "in": "query", "schema": { "type": "string" } },
+                    { "name": "watchtime_tracking", "in": "query", "schema": { "type": "boolean" } },
+                ],
+                "responses": { "200": { "description": "HTML page", "content": { "text/html": {} } } },
+            },
+            "post": {
+                "summary": "Unlocks the credentials with the status PIN, failed attempts are throttled",
+                "requestBody": {
+                    "required": true,
+                    "content": {
+                        "application/x-www-form-urlencoded": {
+                            "schema": { "type": "object", "properties": { "pin": { "type": "string" } } },
+                        },
+                    },
+                },
+                "responses": {
+                    "303": { "description": "Unlocked, back to the page" },
+                    "401": { "description": "Wrong PIN", "content": { "text/html": {} } },
+                    "429": { "description": "Too many wrong PINs", "content": { "text/html": {} } },
+                },
+            },
+        }),
+    );
+    paths.insert(
+        "/health".to_string(),
+        json!({
+            "get": { "summary": "Liveness check", "responses": { "200": { "description": "`OK`" } } }
+        }),
+    );
+    paths.insert(
+        "/version".to_string(),
+        json!({
+            "get": {
+                "summary": "Build information",
+                "responses": { "200": { "description": "Version, commit and Jellyfin spec version" } },
+            }
+        }),
+    );
+    paths.insert(
+        "/openapi.json".to_string(),
+        json!({
+            "get": {
+                "summary": "This document",
+                "responses": { "200": { "description": "OpenAPI 3 JSON" } },
+            }
+        }),
+    );
+    paths.insert("/heresphere".to_string(), heresphere_operation("Library index", vec![]));
+    paths.insert(
+        "/heresphere/scan".to_string(),
+        heresphere_operation("Metadata of every video at once", vec![]),
+    );
+    paths.insert(
+        "/heresphere/{id}".to_string(),
+        heresphere_operation(
+            "One video, with a media source when `needsMediaSource` is set",
+            vec![path_param("id", "Jellyfin item id")],
+        ),
+    );
+    paths.insert(
+        "/heresphere/events/{sid}/{vid}".to_string(),
+        json!({
+            "post": {
+                "tags": ["heresphere"],
+                "summary": "Playback events, reported to Jellyfin",
+                "parameters": [sid(), vid()],
+                "requestBody": {
+                    "required": true,
+                    "content": { "application/json": { "schema": { "$ref": "#/components/schemas/HereSphereEvent" } } },
+                },
+                "responses": { "200": { "description": "Event handled" } },
+            }
+        }),
+    );
+    paths.insert("/heresphere/hls/{sid}/{vid}/{path}".to_string(), json!({
+        "get": {
+            "tags": ["heresphere"],
+            "summary": "HLS playlists and segments proxied from Jellyfin, playlists are rewritten to point back here",
+            "parameters": [sid(), vid(), path_param("path", "Path below `/Videos/{vid}/` on Jellyfin")],
+            "responses": { "200": { "description": "Playlist or segment" } },
+        }
+    }));
+    paths.insert(
+        "/heresphere/images/{user}/{signature}/Items/{id}/Images/{image_type}".to_string(),
+        json!({
+            "get": {
+                "tags": ["heresphere"],
+                "summary": "Item images proxied from Jellyfin with header auth",
+                "parameters": [
+                    path_param("user", "User cache key"),
+                    path_param("signature", "Signature of the user, item and image type, made by JellyVR"),
+                    path_param("id", "Jellyfin item id"),
+                    path_param("image_type", "Jellyfin image type, ex. `Primary`"),
+                ],
+                "responses": { "200": { "description": "Image" }, "404": { "description": "Image proxy disabled or wrong signature" } },
+            }
+        }),
+    );
+    paths.insert(
+        "/admin/sessions".to_string(),
+        admin_operation("get", "List sessions, without tokens or passwords", vec![]),
+    );
+    paths.insert(
+        "/admin/sessions/{id}".to_string(),
+        admin_operation("delete", "Log a session out", vec![path_param("id", "Session id")]),
+    );
+    paths.insert(
+        "/admin/sessions/{id}/videos/{vid}/override".to_string(),
+        admin_operation(
+            "put",
+            "Override video metadata for the user of a session, ex. `{\"is_eye_swapped\": true}`",
+            vec![path_param("id", "Session id"), vid()],
+        ),
+    );
+    paths.insert(
+        "/admin/reindex".to_string(),
+        admin_operation("post", "Rebuild the index of every logged in user", vec![]),
+    );
+    paths.insert(
+        "/admin/cache/vacuum".to_string(),
+        admin_operation("post", "Drop stale cache entries", vec![]),
+    );
+    paths.insert(
+        "/admin/cache".to_string(),
+        admin_operation("delete", "Drop the whole cache, sessions stay", vec![]),
+    );
+    paths.insert(
+        "/admin/trash".to_string(),
+        admin_operation("get", "List videos users moved to the trash", vec![]),
+    );
+    paths.insert(
+        "/admin/sessions/{id}/trash/{vid}".to_string(),
+        admin_operation(
+            "delete",
+            "Delete a trashed video from Jellyfin for good",
+            vec![path_param("id", "Session id"), vid()],
+        ),
+    );
+    paths.insert(
+        "/admin/sessions/{id}/trash/{vid}/restore".to_string(),
+        admin_operation(
+            "post",
+            "Put a trashed video back into the index of the user",
+            vec![path_param("id", "Session id"), vid()],
+        ),
+    );
+    paths.insert(
+        "/debug/item/{vid}".to_string(),
+        json!({
+            "get": {
+                "tags": ["debug"],
+                "summary": "Raw Jellyfin item next to the generated HereSphere video, needs the session cookie",
+                "parameters": [vid()],
+                "responses": { "200": { "description": "Both as JSON" }, "401": { "description": "Not logged in" } },
+            }
+        }),
+    );
+    paths.insert(
+        "/debug/playback".to_string(),
+        json!({
+            "get": {
+                "tags": ["debug"],
+                "summary": "Playback JellyVR is tracking for the user, with the predicted position, needs the session cookie",
+                "responses": { "200": { "description": "`{\"playback\": ...}`, `null` when nothing is tracked" }, "401": { "description": "Not logged in" } },
+            }
+        }),
+    );
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "JellyVR",
+            "description": "Jellyfin proxy for VR media players",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": components(),
+    })
+}
+
+fn components() -> Value {
+    json!({
+        "securitySchemes": {
+            "adminToken": { "type": "http", "scheme": "bearer" },
+        },
+        "schemas": {
+            "HereSphereRequest": {
+                "type": "object",
+                "properties": {
+                    "username": { "type": "string" },
+                    "password": { "type": "string" },
+                    "isFavorite": { "type": "boolean" },
+                    "rating": { "type": "number" },
+                    "tags": { "type": "array", "items": { "type": "object" } },
+                    "hsp": { "type": "string" },
+                    "deleteFile": { "type": "boolean" },
+                    "needsMediaSource": { "type": "boolean" },
+                },
+                "required": ["username", "password"],
+            },
+            "HereSphereEvent": {
+                "type": "object",
+                "properties": {
+                    "username": { "type": "string" },
+                    "id": { "type": "string" },
+                    "title": { "type": "string" },
+                    "event": { "type": "integer", "description": "0 open, 1 play, 2 pause, 3 close" },
+                    "time": { "type": "number", "description": "Milliseconds" },
+                    "speed": { "type": "number" },
+                    "utc": { "type": "number" },
+                    "connectionKey": { "type": "string" },
+                },
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Paths of the `.route(...)` calls in the body of `function` in `source`, below `prefix` and
+    /// in OpenAPI syntax. Axum has no way to list the routes of a router.
+    fn routes_in(source: &str, function: &str, prefix: &str) -> Vec<String> {
+        let start = source.find(&format!("fn {}(", function)).expect("No such function");
+        let body = &source[start..];
+        let body = &body[..body.find("\n}\n").expect("Function never ends")];
+        body.split(".route(\"")
+            .skip(1)
+            .map(|route| {
+                let path = &route[..route.find('"').expect("Unterminated route")];
+                let path = path
+                    .split('/')
+                    .map(|segment| match segment.strip_prefix(':').or_else(|| segment.strip_prefix('*')) {
+                        Some(param) => format!("{{{}}}", param),
+                        None => segment.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("/");
+                match (prefix, path.as_str()) {
+                    ("", path) => path.to_string(),
+                    (prefix, "/") => prefix.to_string(),
+                    (prefix, path) => format!("{}{}", prefix, path),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_route_is_documented() {
+        let main = include_str!("main.rs");
+        let routes = [
+            routes_in(main, "app_router", ""),
+            routes_in(main, "heresphere_router", "/heresphere"),
+            routes_in(include_str!("admin.rs"), "router", "/admin"),
+            routes_in(include_str!("debug.rs"), "router", "/debug"),
+        ]
+        .concat();
+        assert!(routes.contains(&"/heresphere/events/{sid}/{vid}".to_string()));
+        let document = document();
+        let paths = document["paths"].as_object().expect("No paths");
+        for route in &routes {
+            assert!(paths.contains_key(route), "{} isn't documented", route);
+        }
+        // Nothing documented that isn't served either
+        for path in paths.keys() {
+            assert!(routes.contains(path), "{} is documented but not routed", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn document_is_served() -> eyre::Result<()> {
+        let (db, path) = crate::tests::test_db().await?;
+        let app = crate::app_router().with_state(crate::tests::test_app(db.clone(), crate::tests::test_config("http://127.0.0.1:9")));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}/openapi.json", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let served: Value = reqwest::get(&url).await?.error_for_status()?.json().await?;
+        assert_eq!(served, document());
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+        Ok(())
+    }
+}
@@ -0,0 +1,196 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::{SinkExt, StreamExt};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::{
+    jellyfin, update_sessions_if_unchanged, AppState, Playback, Session, SessionState, User, PLAY_SESSION_REUSE_MINUTES,
+};
+
+/// Jellyfin drops session sockets that stay quiet for a minute.
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+/// Sessions currently listening for remote control commands, so each gets one socket at most.
+#[derive(Clone, Default)]
+pub(crate) struct RemoteListeners(Arc<Mutex<HashSet<String>>>);
+
+impl RemoteListeners {
+    /// Starts listening for commands sent to the session from Jellyfin, unless that's already happening.
+    /// The listener stops once the session no longer has a playback going.
+    pub(crate) fn ensure(&self, app: &AppState, sid: &str) {
+        if !self.0.lock().expect("Remote listeners poisoned").insert(sid.to_string()) {
+            return;
+        }
+        let app = app.clone();
+        let listeners = self.clone();
+        let sid = sid.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = listen(&app, &sid).await {
+                tracing::warn!(sid = %sid, error = ?e, "Remote control listener failed");
+            }
+            listeners.0.lock().expect("Remote listeners poisoned").remove(&sid);
+        });
+    }
+}
+
+async fn listen(app: &AppState, sid: &str) -> eyre::Result<()> {
+    let Some(user) = playing_user(app, sid).await? else {
+        return Ok(());
+    };
+    let jellyfin_user = app.jellyfin_user(&user)?;
+    jellyfin_user.enable_remote_control().await?;
+    let mut socket = jellyfin_user.session_socket().await?;
+    tracing::debug!(sid = %sid, "Listening for remote control commands");
+    let mut keep_alive = tokio::time::interval(KEEP_ALIVE);
+    loop {
+        tokio::select! {
+            message = socket.next() => {
+                let Some(message) = message else {
+                    break;
+                };
+                let Message::Text(text) = message? else {
+                    continue;
+                };
+                if let Some(command) = jellyfin::RemoteCommand::from_session_message(&text) {
+                    tracing::debug!(sid = %sid, command = ?command, "Received remote control command");
+                    handle_command(app, sid, &command).await?;
+                }
+            }
+            _ = keep_alive.tick() => {
+                if playing_user(app, sid).await?.is_none() {
+                    break;
+                }
+                socket.send(Message::Text(r#"{"MessageType":"KeepAlive"}"#.to_string())).await?;
+            }
+        }
+    }
+    tracing::debug!(sid = %sid, "Stopped listening for remote control commands");
+    Ok(())
+}
+
+/// The user of the session while it has a started playback that's running or was paused recently.
+async fn playing_user(app: &AppState, sid: &str) -> eyre::Result<Option<User>> {
+    let user = match app.get_session_from_heresphere_event(sid).await {
+        Ok(SessionState {
+            session: Session::User(user),
+            ..
+        }) => user,
+        Ok(_) => return Ok(None),
+        Err(err) if err.downcast_ref::<crate::JellyVrError>().is_some() => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let is_playing = user.last_known_playback.as_ref().is_some_and(|playback| {
+        playback.is_started
+            && (!playback.is_paused
+                || chrono::Utc::now() - playback.last_update < chrono::Duration::minutes(PLAY_SESSION_REUSE_MINUTES))
+    });
+    Ok(is_playing.then_some(user))
+}
+
+/// Applies a remote command to the tracked playback and reports the outcome back to Jellyfin.
+/// The headset itself can't be told what to do, its next event wins again.
+async fn handle_command(app: &AppState, sid: &str, command: &jellyfin::RemoteCommand) -> eyre::Result<()> {
+    let state = app.get_session_from_heresphere_event(sid).await?;
+    let Session::User(user) = &state.session else {
+        return Ok(());
+    };
+    let Some(playback) = user.last_known_playback.as_ref().filter(|playback| playback.is_started) else {
+        return Ok(());
+    };
+    let last_update = playback.last_update;
+    let playback = apply_command(playback, command);
+    let jellyfin_user = app.jellyfin_user(user)?;
+    if *command == jellyfin::RemoteCommand::Stop {
+        jellyfin_user
+            .playback_stopped(&playback.video_id, &playback.play_session_id, playback.position_estimate)
+            .await?;
+    } else {
+        jellyfin_user
+            .playback_progress(
+                &playback.video_id,
+                &playback.play_session_id,
+                playback.position_estimate,
+                playback.is_paused,
+                playback.started_at,
+            )
+            .await?;
+    }
+    let update = SessionState {
+        session: Session::User(User {
+            last_known_playback: Some(playback),
+            ..user.clone()
+        }),
+        ..state.clone()
+    };
+    // Events from the headset in the meantime win, same as over progress updates
+    for (_, result) in update_sessions_if_unchanged(&app.db.get(), vec![(update, last_update)]).await? {
+        if result?.is_none() {
+            tracing::debug!(sid = %sid, "Playback was updated by an event in the meantime, skipping");
+        }
+    }
+    Ok(())
+}
+
+fn apply_command(playback: &Playback, command: &jellyfin::RemoteCommand) -> Playback {
+    let is_paused = match command {
+        jellyfin::RemoteCommand::Pause | jellyfin::RemoteCommand::Stop => true,
+        jellyfin::RemoteCommand::Unpause => false,
+        jellyfin::RemoteCommand::PlayPause => !playback.is_paused,
+        jellyfin::RemoteCommand::Seek(_) => playback.is_paused,
+    };
+    let position_estimate = match command {
+        jellyfin::RemoteCommand::Seek(position) => *position,
+        _ => playback.predicted_position(),
+    };
+    Playback {
+        is_paused,
+        position_estimate,
+        // A stopped playback gets started again by the next Open/Play event from the headset
+        is_started: *command != jellyfin::RemoteCommand::Stop,
+        last_update: chrono::Utc::now(),
+        ..playback.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playback(is_paused: bool) -> Playback {
+        Playback {
+            play_session_id: "play".to_string(),
+            video_id: "video".to_string(),
+            duration: jellyfin::ms_to_ticks(100_000.0),
+            position_estimate: jellyfin::ms_to_ticks(10_000.0),
+            speed: 1.0,
+            started_at: chrono::Utc::now(),
+            last_update: chrono::Utc::now(),
+            is_paused,
+            is_started: true,
+            is_marked_played: false,
+            media_url: None,
+            is_transcoded: false,
+        }
+    }
+
+    #[test]
+    fn commands_change_the_tracked_playback() {
+        let paused = apply_command(&playback(false), &jellyfin::RemoteCommand::Pause);
+        assert!(paused.is_paused && paused.is_started);
+        assert!(!apply_command(&paused, &jellyfin::RemoteCommand::Unpause).is_paused);
+        assert!(!apply_command(&paused, &jellyfin::RemoteCommand::PlayPause).is_paused);
+        assert!(apply_command(&playback(false), &jellyfin::RemoteCommand::PlayPause).is_paused);
+
+        let seeked = apply_command(&paused, &jellyfin::RemoteCommand::Seek(jellyfin::ms_to_ticks(50_000.0)));
+        assert_eq!(seeked.position_estimate, jellyfin::ms_to_ticks(50_000.0));
+        assert!(seeked.is_paused);
+
+        let stopped = apply_command(&playback(false), &jellyfin::RemoteCommand::Stop);
+        assert!(stopped.is_paused && !stopped.is_started);
+        assert!(stopped.position_estimate >= jellyfin::ms_to_ticks(10_000.0));
+    }
+}
@@ -0,0 +1,70 @@
+use crate::Assets;
+
+/// Renders a template from `assets/templates/`, substituting every `{{ key }}`
+/// placeholder with the HTML-escaped value of the matching variable.
+/// Unknown placeholders are rendered as empty strings.
+pub(crate) fn render(name: &str, vars: &[(&str, &str)]) -> eyre::Result<String> {
+    let path = format!("templates/{}", name);
+    let file = Assets::get(&path).ok_or_else(|| eyre::eyre!("Template {} not found", path))?;
+    let template = std::str::from_utf8(&file.data)?;
+
+    let mut html = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        html.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find("}}") else {
+            rest = &rest[start..];
+            break;
+        };
+        let key = rest[start + 2..start + end].trim();
+        if let Some((_, value)) = vars.iter().find(|(k, _)| *k == key) {
+            html.push_str(&escape_html(value));
+        }
+        rest = &rest[start + end + 2..];
+    }
+    html.push_str(rest);
+    Ok(html)
+}
+
+pub(crate) fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_values_are_escaped() {
+        let html = render(
+            "user.html",
+            &[("username", "<script>alert('hi')</script>"), ("jellyvr_password", "a\"b&c")],
+        )
+        .expect("user.html renders");
+        assert!(!html.contains("<script>alert"));
+        assert!(html.contains("&lt;script&gt;alert(&#x27;hi&#x27;)&lt;/script&gt;"));
+        assert!(html.contains("a&quot;b&amp;c"));
+    }
+
+    #[test]
+    fn unknown_placeholders_render_empty() {
+        let html = render("quickconnect.html", &[]).expect("quickconnect.html renders");
+        assert!(!html.contains("{{"));
+    }
+
+    #[test]
+    fn missing_templates_are_an_error() {
+        assert!(render("nope.html", &[]).is_err());
+    }
+}